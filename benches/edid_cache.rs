@@ -0,0 +1,70 @@
+//! Compares reading the monitors of a three-monitor system through `EdidCache` with reading
+//! every EDID, against a mock X server that answers each EDID request over a socket.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use monitor_layout::{config::Monitor, EdidCache, Error};
+use x11rb::protocol::randr::Output;
+
+use std::{
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    thread,
+};
+
+const OUTPUTS: [Output; 3] = [64, 65, 66];
+
+/// The size of the EDID property of a monitor
+const EDID_LEN: usize = 128;
+
+/// A stand-in for the X server, which answers each request for an output's EDID after a round
+/// trip over a socket.
+struct MockServer {
+    stream: UnixStream,
+}
+
+impl MockServer {
+    fn start() -> Self {
+        let (stream, mut server) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            let mut request = [0u8; 4];
+            while server.read_exact(&mut request).is_ok() {
+                let mut edid = [0u8; EDID_LEN];
+                edid[..4].copy_from_slice(&request);
+                if server.write_all(&edid).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { stream }
+    }
+
+    fn read_monitor(&mut self, out: Output) -> Result<Option<Monitor>, Error> {
+        let mut edid = [0u8; EDID_LEN];
+        self.stream.write_all(&out.to_le_bytes()).unwrap();
+        self.stream.read_exact(&mut edid).unwrap();
+        let serial = u32::from_le_bytes([edid[0], edid[1], edid[2], edid[3]]);
+        Ok(Some(Monitor {
+            product: Some("DELL U2715H".to_string()),
+            serial: Some(serial.to_string()),
+            connector: None,
+            year: None,
+            week: None,
+        }))
+    }
+}
+
+fn three_monitors(c: &mut Criterion) {
+    let mut server = MockServer::start();
+    let mut group = c.benchmark_group("three monitors");
+    group.bench_function("uncached", |b| {
+        b.iter(|| EdidCache::default().get_monitors_with(&OUTPUTS, |out| server.read_monitor(out)))
+    });
+    let mut cache = EdidCache::default();
+    group.bench_function("cached", |b| {
+        b.iter(|| cache.get_monitors_with(&OUTPUTS, |out| server.read_monitor(out)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, three_monitors);
+criterion_main!(benches);
@@ -36,6 +36,7 @@ fn generate_man_pages<P: AsRef<Path>>(outdir: P) {
         let output = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(out_path)
             .unwrap();
         let mut scdoc = Command::new("scdoc")
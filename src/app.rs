@@ -1,8 +1,8 @@
 //! Command line argument parser for monitor-layout(1)
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, Shell, SubCommand};
 
-pub const NAME: &'static str = "monitor-layout";
+pub const NAME: &str = "monitor-layout";
 
 pub fn args() -> App<'static, 'static> {
     App::new(NAME)
@@ -14,15 +14,78 @@ pub fn args() -> App<'static, 'static> {
                 .multiple(true)
                 .help("Increase message verbosity"),
         )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("FORMAT")
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Write log messages as text or as a line of JSON each"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .possible_values(&["trace", "debug", "info", "warn", "error"])
+                .help("Show log messages at LEVEL and above; overrides -v and RUST_LOG"),
+        )
+        .arg(
+            Arg::with_name("config-file")
+                .long("config")
+                .value_name("FILE")
+                .multiple(true)
+                .number_of_values(1)
+                .global(true)
+                .help("Also read this configuration file, whose layouts replace those of the same name or monitors; may be repeated"),
+        )
         .subcommand(
             SubCommand::with_name("daemon")
                 .about("Watch for changes in connected monitors and apply matching layouts")
                 .arg(
                     Arg::with_name("config")
                         .value_name("CONFIG")
-                        .help("The configuration file")
-                        .required(true)
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
                         .index(1),
+                )
+                .arg(
+                    Arg::with_name("debounce-ms")
+                        .long("debounce-ms")
+                        .value_name("MS")
+                        .default_value("500")
+                        .help("Wait until monitor changes settle for this long before applying a layout"),
+                )
+                .arg(
+                    Arg::with_name("no-act")
+                        .long("no-act")
+                        .aliases(&["simulate", "dry-run"])
+                        .help("Print the changes the first layout would make, and exit"),
+                )
+                .arg(
+                    Arg::with_name("poll")
+                        .long("poll")
+                        .value_name("MS")
+                        .default_value("0")
+                        .help("Also look for changed monitors every MS milliseconds, for servers that miss events"),
+                )
+                .arg(
+                    Arg::with_name("reconnect-delay")
+                        .long("reconnect-delay")
+                        .value_name("MS")
+                        .default_value("2000")
+                        .help("Wait this long before each attempt to reconnect to a lost X server"),
+                )
+                .arg(
+                    Arg::with_name("reconnect-attempts")
+                        .long("reconnect-attempts")
+                        .value_name("N")
+                        .default_value("10")
+                        .help("Exit with an error after this many failed attempts to reconnect"),
+                )
+                .arg(
+                    Arg::with_name("pid-file")
+                        .long("pid-file")
+                        .value_name("FILE")
+                        .help("Write the daemon's PID to this file [default: $XDG_RUNTIME_DIR/autorandr-rs.pid]"),
                 ),
         )
         .subcommand(
@@ -31,9 +94,13 @@ pub fn args() -> App<'static, 'static> {
                 .arg(
                     Arg::with_name("config")
                         .value_name("CONFIG")
-                        .help("The configuration file")
-                        .required(true)
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
                         .index(1),
+                )
+                .arg(
+                    Arg::with_name("against-server")
+                        .long("against-server")
+                        .help("Also check that the layouts of the connected monitors can be applied"),
                 ),
         )
         .subcommand(
@@ -41,4 +108,405 @@ pub fn args() -> App<'static, 'static> {
                 "Read the edids and print them as they would appear in a configuration file",
             ),
         )
+        .subcommand(
+            SubCommand::with_name("save")
+                .about("Save the current layout of the connected monitors to the configuration")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("The name of the new layout; prompted for when not provided"),
+                )
+                .arg(
+                    Arg::with_name("overwrite")
+                        .long("overwrite")
+                        .alias("force")
+                        .help("Replace the layouts that already match the connected monitors or have the same name"),
+                )
+                .arg(
+                    Arg::with_name("print")
+                        .long("print")
+                        .help("Print the new layout, and any new monitors, instead of saving them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("render")
+                .about("Draw an SVG diagram of a configured layout or of the current layout")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .value_name("NAME")
+                        .help("Draw this layout instead of the current layout"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the diagram to this file instead of stdout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("detect")
+                .about("Print the connected monitors, their identifiers and their current modes")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print a JSON array instead of text"),
+                )
+                .arg(
+                    Arg::with_name("diagram")
+                        .long("diagram")
+                        .conflicts_with("json")
+                        .help("Also draw the monitors that are on, as they are laid out"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list")
+                .alias("list-profiles")
+                .about("List the layouts in the configuration and the monitors they match")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print a JSON object keyed by layout name, of objects with monitors, wildcard, fallback, allow_extra_monitors, disable_extra_monitors, priority, shown, off, fb_size, matches_now, selected, missing and layout fields, where layout is an array of objects with alias, monitor, mode, rate, position, relative, scale, primary and enabled fields"),
+                )
+                .arg(
+                    Arg::with_name("verbose")
+                        .long("verbose")
+                        .help("Also print the mode and position of each monitor"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("status")
+                .about("Print the name of the layout the monitors are displaying")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("none")
+                        .long("none")
+                        .value_name("TEXT")
+                        .help("Print this when no layout is displayed [default: none]"),
+                )
+                .arg(
+                    Arg::with_name("diagram")
+                        .long("diagram")
+                        .help("Also draw the monitors that are on, as they are laid out"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Apply a layout once, without watching for changes")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .value_name("NAME")
+                        .help("Apply this layout instead of the layout matching the connected monitors"),
+                )
+                .arg(
+                    Arg::with_name("no-act")
+                        .long("no-act")
+                        .aliases(&["simulate", "dry-run"])
+                        .help("Print the changes to the monitors instead of making them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("dump-config")
+                .about("Print the configuration as it was parsed")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print a JSON array instead of text"),
+                )
+                .arg(
+                    Arg::with_name("kdl")
+                        .long("kdl")
+                        .conflicts_with("json")
+                        .help("Print the configuration in the format it is read in"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Print a shell script that applies a layout with xrandr")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .value_name("NAME")
+                        .help("Export this layout instead of the layout matching the connected monitors"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("Exit successfully only when the monitors are displaying a layout")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("profile")
+                        .long("profile")
+                        .value_name("NAME")
+                        .required(true)
+                        .help("The layout the monitors should be displaying"),
+                )
+                .arg(
+                    Arg::with_name("verbose")
+                        .long("verbose")
+                        .help("Print each way in which the monitors differ from the layout"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("fingerprint")
+                .about("Print a stable fingerprint of the EDID of each connected monitor")
+                .arg(
+                    Arg::with_name("matches")
+                        .long("matches")
+                        .help("Print the connected monitors as a layout matches them instead"),
+                )
+                .arg(
+                    Arg::with_name("watch")
+                        .long("watch")
+                        .help("Print again each time the connected monitors change"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print a JSON array of objects with output and fingerprint fields, or with --matches, an object with monitors and matches fields; one per line with --watch"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reset")
+                .about("Show every connected monitor in its preferred mode, side by side"),
+        )
+        .subcommand(
+            SubCommand::with_name("list-monitors")
+                .about("List the connected monitors as a configuration names them")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print a JSON array of objects with output, monitor, mm_width, mm_height, edid and edid_error fields"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("current")
+                .about("Print the layout that matches the monitors, and whether it is applied")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print a JSON object with profile, applied and monitors fields"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("cycle")
+                .about("Apply the next of the layouts that match the connected monitors")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("switch")
+                .about("Apply a layout by name, whether or not it matches the connected monitors")
+                .arg(
+                    Arg::with_name("profile")
+                        .value_name("PROFILE")
+                        .help("The name of the layout to apply")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Apply the layout even when some of its monitors are not connected"),
+                )
+                .arg(
+                    Arg::with_name("no-act")
+                        .long("no-act")
+                        .aliases(&["simulate", "dry-run"])
+                        .help("Print the changes to the monitors instead of making them"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Apply a layout by name, and restore the previous layout unless it is kept in time")
+                .arg(
+                    Arg::with_name("profile")
+                        .value_name("PROFILE")
+                        .help("The name of the layout to test")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(2),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .default_value("15")
+                        .help("How long to wait for Enter to be pressed before restoring the previous layout"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Apply the layout even when some of its monitors are not connected"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::with_name("shell")
+                        .value_name("SHELL")
+                        .possible_values(&Shell::variants())
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("profile-names")
+                .about("Print the name of each layout, one per line, for shell completions")
+                .setting(AppSettings::Hidden)
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("Print a configuration with a layout for the connected monitors, side by side")
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .value_name("NAME")
+                        .help("The name of the layout [default: the hostname]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Print how the monitors differ from the layout that matches them")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("watch")
+                .about("Print the RandR events the daemon reacts to, without applying layouts"),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor")
+                .about("Check the X server and the configuration for common problems")
+                .arg(
+                    Arg::with_name("config")
+                        .value_name("CONFIG")
+                        .help("The configuration file; defaults to $XDG_CONFIG_HOME/autorandr-rs/config")
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("print-edid")
+                .about("Print the raw EDID of each connected output as a hex dump")
+                .arg(
+                    Arg::with_name("output-name")
+                        .value_name("OUTPUT")
+                        .help("Only print the EDID of this output, such as DP-1")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the raw bytes of the EDID to this file instead of a hex dump"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("providers")
+                .about("Print the RandR providers, such as the GPUs of a hybrid graphics laptop, and their outputs")
+                .arg(
+                    Arg::with_name("set-output-source")
+                        .long("set-output-source")
+                        .value_names(&["PROVIDER", "SOURCE"])
+                        .number_of_values(2)
+                        .help("Before printing, show the image of SOURCE on the outputs of PROVIDER, as xrandr --setprovideroutputsource does; SOURCE may be \"none\""),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("decode-edid")
+                .about("Decode the EDID of each connected output and show what it is matched as")
+                .arg(
+                    Arg::with_name("output-name")
+                        .value_name("OUTPUT")
+                        .help("Only decode the EDID of this output, such as DP-1")
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .value_name("FILE")
+                        .conflicts_with("output-name")
+                        .help("Decode the raw EDID bytes in this file, as print-edid --output writes them"),
+                ),
+        )
 }
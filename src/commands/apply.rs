@@ -0,0 +1,147 @@
+use clap::ArgMatches;
+use log::error;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{GetScreenResourcesCurrentReply, Output},
+    protocol::xproto::Atom,
+};
+
+use std::collections::HashMap;
+
+use crate::{
+    config::{Config, MonConfig, SingleConfig},
+    edid_atom, get_monitors, get_outputs, hooks, ok_or_exit,
+};
+
+use super::{
+    check,
+    daemon::{apply_config, get_config, no_match_env, on_apply_env, outputs_in, switch_env},
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("The connected monitors did not match a config")]
+    NoMatch,
+    #[error("Layout {0} needs monitors that are not connected: {}", .1.join(", "))]
+    MissingMonitors(String, Vec<String>),
+}
+
+/// The exit status when the connected monitors match no layout
+pub const EXIT_NO_MATCH: i32 = 2;
+/// The exit status when a layout was found, but could not be applied
+pub const EXIT_APPLY_FAILED: i32 = 3;
+
+/// Log why a layout could not be applied, and exit with [`EXIT_APPLY_FAILED`]
+fn apply_failed(e: miette::Report) -> i32 {
+    error!("{:?}", e);
+    EXIT_APPLY_FAILED
+}
+
+/// Apply a layout once: the layout named by `--profile`, or else the layout that matches the
+/// connected monitors. This is the daemon's first pass, without the daemon. Exits with
+/// [`EXIT_NO_MATCH`] when no layout matches, and [`EXIT_APPLY_FAILED`] when applying one fails.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    apply_layout(args, args.value_of("profile"), true)
+}
+
+/// The layout named `profile`, and the outputs that show its monitors. Unless `force` is set,
+/// every monitor it matches must be connected.
+pub(crate) fn named_layout<'a, C: Connection>(
+    config: &'a Config,
+    conn: &C,
+    res: &GetScreenResourcesCurrentReply,
+    atom_edid: Atom,
+    profile: &str,
+    force: bool,
+) -> Result<(&'a SingleConfig, HashMap<Output, &'a MonConfig>)> {
+    let (matches, single) = config.layout_named(profile).into_diagnostic()?;
+    let out_to_mon: HashMap<_, _> = get_monitors(conn, &res.outputs, atom_edid)
+        .map(|(out, mon)| (out, config.identify(mon)))
+        .collect();
+    let missing: Vec<_> = matches
+        .iter()
+        .filter(|mon| !out_to_mon.values().any(|m| m == *mon))
+        .map(|mon| match single.setup.get(mon) {
+            Some(conf) => conf.name.clone(),
+            None => mon.to_string(),
+        })
+        .collect();
+    if !missing.is_empty() && !force {
+        return Err(Error::MissingMonitors(single.name.clone(), missing)).into_diagnostic();
+    }
+    Ok((single, outputs_in(matches, single, &out_to_mon)))
+}
+
+/// Apply the layout named `profile`, or else the layout that matches the connected monitors.
+/// Unless `force` is set, a named layout is only applied when every monitor it matches is
+/// connected; otherwise, only the monitors that are connected are configured.
+pub(crate) fn apply_layout(
+    args: &ArgMatches<'_>,
+    profile: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let (single, setup) = match profile {
+        Some(profile) => named_layout(&config, &conn, &res, atom_edid, profile, force)?,
+        None => match get_config(&config, &conn, &res.outputs, atom_edid) {
+            Some((_, single, setup)) => (single, setup),
+            None => {
+                let hook = config.on_no_match.as_ref();
+                if let Some(hook) = hook.filter(|_| !args.is_present("no-act")) {
+                    let env = no_match_env(None);
+                    if let Some(hook) = hooks::spawn("on-no-match", "no layout", hook, env) {
+                        let _ = hook.join();
+                    }
+                }
+                error!("{}", Error::NoMatch);
+                std::process::exit(EXIT_NO_MATCH);
+            }
+        },
+    };
+    if args.is_present("no-act") {
+        let applied = apply_config(&conn, &res, single, setup, root, false, || ());
+        if !ok_or_exit(applied, apply_failed) {
+            println!("Monitor configuration {} is already applied", single.name);
+        }
+        return Ok(());
+    }
+    let hook_env = on_apply_env(&conn, &res, single, &setup)?;
+    // Without the daemon, the layout being switched from is unknown
+    let switch_env = switch_env(None, single);
+    let pre_switch = || {
+        for hook in config.pre_switch.iter().chain(single.pre_switch.iter()) {
+            let env = switch_env.clone();
+            hooks::run(
+                "pre-switch",
+                &single.name,
+                hook,
+                env,
+                hooks::PRE_SWITCH_TIMEOUT,
+            );
+        }
+    };
+    let applied = apply_config(&conn, &res, single, setup, root, true, pre_switch);
+    if ok_or_exit(applied, apply_failed) {
+        println!("Monitor configuration: {}", single.name);
+        for hook in config.post_switch.iter().chain(single.post_switch.iter()) {
+            let env = switch_env.clone();
+            if let Some(hook) = hooks::spawn("post-switch", &single.name, hook, env) {
+                let _ = hook.join();
+            }
+        }
+        if let (Some(hook), Some(env)) = (&single.on_apply, hook_env) {
+            // Failures are only logged by the hook's thread, so it must finish before exiting
+            if let Some(hook) = hooks::spawn("on-apply", &single.name, hook, env) {
+                let _ = hook.join();
+            }
+        }
+    }
+    Ok(())
+}
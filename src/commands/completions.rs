@@ -0,0 +1,76 @@
+use clap::{value_t, ArgMatches, Shell};
+use miette::{IntoDiagnostic, Result};
+
+use crate::app;
+
+/// The hidden subcommand that prints the name of each layout
+const PROFILE_NAMES: &str = "profile-names";
+
+/// A zsh function completing layout names by running the hidden subcommand
+const ZSH_PROFILES: &str = r#"(( $+functions[_monitor-layout_profiles] )) ||
+_monitor-layout_profiles() {
+    local -a profiles
+    profiles=(${(f)"$(monitor-layout profile-names 2>/dev/null)"})
+    compadd -a profiles
+}
+
+"#;
+
+/// The fish arguments completing layout names by running the hidden subcommand
+const FISH_PROFILES: &str = r#"-r -f -a "(monitor-layout profile-names 2>/dev/null)""#;
+
+/// Complete layout names where a layout is named, which clap can not do by itself, and leave
+/// the hidden subcommand out of the subcommands offered.
+fn complete_profiles(shell: Shell, script: &str) -> String {
+    let mut out = String::with_capacity(script.len());
+    for line in script.lines() {
+        let line = match shell {
+            Shell::Zsh if line.starts_with(&format!("\"{}:", PROFILE_NAMES)) => continue,
+            Shell::Zsh if line.starts_with("'--profile=[") => {
+                line.replacen("]'", "]:NAME:_monitor-layout_profiles'", 1)
+            }
+            Shell::Zsh if line.starts_with("':profile -- ") => {
+                line.replacen(":_files'", ":_monitor-layout_profiles'", 1)
+            }
+            Shell::Zsh if line == "_monitor-layout \"$@\"" => format!("{}{}", ZSH_PROFILES, line),
+            Shell::Fish if line.contains(&format!("-a \"{}\"", PROFILE_NAMES)) => continue,
+            Shell::Fish if line.contains(" -l profile -d ") => line.replacen(
+                " -l profile -d ",
+                &format!(" -l profile {} -d ", FISH_PROFILES),
+                1,
+            ),
+            _ => line.to_string(),
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    if let Shell::Fish = shell {
+        out.push_str(&format!(
+            "complete -c {} -n \"__fish_seen_subcommand_from switch\" {}\n",
+            app::NAME,
+            FISH_PROFILES
+        ));
+    }
+    out
+}
+
+/// Print a completion script for the shell named on the command line.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let shell = value_t!(args, "shell", Shell).unwrap_or_else(|e| e.exit());
+    let mut script = Vec::new();
+    app::args().gen_completions_to(app::NAME, shell, &mut script);
+    let script = String::from_utf8(script).into_diagnostic()?;
+    print!("{}", complete_profiles(shell, &script));
+    Ok(())
+}
+
+/// Print the name of each layout, one per line, for completion scripts.
+pub fn profile_names(args: &ArgMatches<'_>) -> Result<()> {
+    let config = super::check(args)?;
+    let mut names: Vec<_> = config.iter().map(|(_, single)| &single.name).collect();
+    names.sort();
+    for name in names {
+        println!("{}", name);
+    }
+    Ok(())
+}
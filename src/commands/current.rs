@@ -0,0 +1,69 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+use x11rb::{connect, connection::Connection};
+
+use std::collections::HashMap;
+
+use crate::config::Monitor;
+
+use super::{
+    check,
+    status::{current_state, is_active},
+};
+
+/// The layout that matches the attached monitors
+#[derive(Serialize)]
+struct Current {
+    /// None when no layout matches
+    profile: Option<String>,
+    /// Whether the monitors are displaying the layout
+    applied: bool,
+    /// The attached monitors, in the order that keys a layout
+    monitors: Vec<Monitor>,
+}
+
+/// Print the name of the layout that matches the attached monitors, and whether the monitors
+/// are displaying it. With `--json`, print a JSON object instead.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let state: HashMap<_, _> = current_state(&conn, root)?
+        .into_iter()
+        .map(|(mon, output)| (config.identify(mon), output))
+        .collect();
+    let mut connected: Vec<_> = state.keys().cloned().collect();
+    connected.sort();
+    let layout = match config.lookup(&connected) {
+        Some((matches, single)) => Some((matches.as_slice(), single)),
+        None => config.fallback.as_ref().map(|single| (&[][..], single)),
+    };
+    let current = Current {
+        profile: layout.map(|(_, single)| single.name.clone()),
+        applied: layout.is_some_and(|(matches, single)| is_active(matches, single, &state)),
+        monitors: connected,
+    };
+    if args.is_present("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&current).into_diagnostic()?
+        );
+    } else if let Some(profile) = &current.profile {
+        let applied = if current.applied {
+            "applied"
+        } else {
+            "not applied"
+        };
+        println!("{} ({})", profile, applied);
+    } else {
+        println!("no match");
+        for monitor in current.monitors.iter() {
+            println!("    {}", monitor);
+        }
+    }
+    if current.profile.is_none() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
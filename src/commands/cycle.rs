@@ -0,0 +1,75 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{connect, connection::Connection};
+
+use std::collections::HashMap;
+
+use crate::config::{Config, Monitor, SingleConfig};
+
+use super::{
+    apply::apply_layout,
+    check,
+    status::{current_state, is_active, OutputState},
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Only {0} layouts match the connected monitors; at least two are needed to cycle")]
+    TooFewLayouts(usize),
+}
+
+/// Whether a layout would match the connected monitors were it the only layout, as
+/// `Config::lookup` matches them.
+fn applies(matches: &[Monitor], single: &SingleConfig, connected: &[Monitor]) -> bool {
+    let extras = connected.len().saturating_sub(matches.len());
+    matches.iter().all(|m| connected.contains(m))
+        && match single.wildcard {
+            Some(_) => extras == 1,
+            None => extras == 0 || single.allow_extra_monitors,
+        }
+}
+
+/// The name of the layout to cycle to: of the layouts that match the connected monitors, in
+/// order of name, the one after the layout the monitors are showing, wrapping around, or the
+/// first when they show none of them. The fallback layout is left out, as it only applies when
+/// no other layout does. `state` is keyed by monitors as the configuration identifies them.
+pub(crate) fn next_layout(
+    config: &Config,
+    state: &HashMap<Monitor, Option<OutputState>>,
+) -> Result<String> {
+    let mut connected: Vec<_> = state.keys().cloned().collect();
+    connected.sort();
+    let mut layouts: Vec<_> = config
+        .layouts
+        .iter()
+        .chain(config.wildcard_layouts.iter())
+        .filter(|(matches, single)| applies(matches, single, &connected))
+        .collect();
+    if layouts.len() < 2 {
+        return Err(Error::TooFewLayouts(layouts.len())).into_diagnostic();
+    }
+    layouts.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    let next = match layouts
+        .iter()
+        .position(|(matches, single)| is_active(matches, single, state))
+    {
+        Some(i) => (i + 1) % layouts.len(),
+        None => 0,
+    };
+    Ok(layouts[next].1.name.clone())
+}
+
+/// Apply the next of the layouts that match the connected monitors, so that a key bound to this
+/// steps through them. Exits with an error when fewer than two layouts match.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let state: HashMap<_, _> = current_state(&conn, root)?
+        .into_iter()
+        .map(|(mon, output)| (config.identify(mon), output))
+        .collect();
+    let profile = next_layout(&config, &state)?;
+    apply_layout(args, Some(&profile), false)
+}
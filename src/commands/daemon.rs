@@ -1,86 +1,188 @@
-use log::{error, info};
+use log::{error, info, warn};
 use x11rb::{
-    connect,
     connection::Connection,
     cookie::Cookie,
+    errors::ReplyError,
+    protocol::dpms::ConnectionExt as DpmsExt,
     protocol::randr::{
-        ConnectionExt as RandrExt, Crtc, GetCrtcInfoReply, GetOutputInfoReply,
-        GetScreenResourcesCurrentReply, NotifyMask, Output, SetConfig, SetCrtcConfigReply,
-        SetCrtcConfigRequest,
+        Connection as RandrConnection, ConnectionExt as RandrExt, Crtc, GetCrtcInfoReply,
+        GetOutputInfoReply, GetScreenResourcesCurrentReply, ModeFlag, ModeInfo, Notify, NotifyMask,
+        Output, SetConfig, SetCrtcConfigReply, SetCrtcConfigRequest,
     },
-    protocol::xproto::{Atom, ConnectionExt as XprotoExt, Timestamp, Window},
+    protocol::render::{Fixed, Transform},
+    protocol::xproto::{Atom, AtomEnum, ConnectionExt as XprotoExt, PropMode, Timestamp, Window},
     protocol::Event,
+    rust_connection::RustConnection,
+    wrapper::ConnectionExt as WrapperExt,
 };
 
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::Display,
+    os::unix::{
+        io::{AsRawFd, RawFd},
+        net::UnixStream,
+    },
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc, Mutex, OnceLock, RwLock, RwLockReadGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use clap::ArgMatches;
+use clap::{value_t, ArgMatches};
 use miette::{IntoDiagnostic, Result};
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    flag,
+    low_level::pipe,
+};
 use thiserror::Error;
 
-use crate::config::{Config, Mode, MonConfig, Position, SingleConfig};
-use crate::{edid_atom, get_monitors, get_outputs, ok_or_exit};
+use crate::config::{
+    resolve_positions, unreachable_monitors, Config, Dpms, Error as ConfigError, Gamma, Mode,
+    ModeSpec, Modeline, MonConfig, Monitor, Position, PropertyValue, Region, Scale, SingleConfig,
+};
+use crate::ipc::{self, DaemonStats, StatusInfo};
+
+use super::cycle::next_layout;
+use super::providers::unsourced_providers;
+use super::snapshot::Snapshot;
+use super::status::current_state;
+use crate::{
+    best_mode_for_output, edid_atom, edid_atom_cached, get_monitors, get_outputs, intern,
+    mode_sizes, ok_or_exit, output_name, EdidCache, ModeSizes,
+};
+use crate::{dbus, hooks, systemd};
 
 #[derive(Error, Debug)]
 pub enum Error {
+    #[error("Could not reconnect to the X server after {0} attempts")]
+    Reconnect(u32),
     #[error("Mode {0} not found")]
     ModeNotFound(Mode),
     #[error("Mode {0} not supported")]
     ModeNotSupported(Mode),
     #[error("No Crtc available for monitor {0}")]
     NoCrtc(String),
+    #[error("Monitors {0} and {1} have no mode in common")]
+    NoCommonMode(String, String),
+    #[error("Monitor {0} has no modes")]
+    NoModes(String),
+    #[error("Monitor {0} pans across an area smaller than its mode")]
+    PanningTooSmall(String),
+    #[error("Monitor {0} has no property {1}")]
+    UnknownProperty(String, String),
+    #[error("{2} is not a valid value of property {1} of monitor {0}")]
+    InvalidPropertyValue(String, String, PropertyValue),
+    #[error("Another daemon is already running, with pid {0} in {1}")]
+    AlreadyRunning(i32, String),
+    #[error("The X server does not support DPMS")]
+    NoDpms,
+    #[error("The configuration can not be applied to the X server, with {0} problem(s)")]
+    AgainstServer(usize),
+}
+
+/// Map each output to its configuration within a layout, skipping outputs that are not part
+/// of the layout. `matches` are the monitors that the layout matches by name.
+pub(crate) fn outputs_in<'a>(
+    matches: &[Monitor],
+    single: &'a SingleConfig,
+    out_to_mon: &HashMap<Output, Monitor>,
+) -> HashMap<Output, &'a MonConfig> {
+    let mut out = HashMap::with_capacity(single.setup.len());
+    for (output, mon) in out_to_mon.iter() {
+        if let Some(moncfg) = single.monitor_config(matches, mon) {
+            out.insert(*output, moncfg);
+        }
+    }
+    out
+}
+
+/// The attached monitors, sorted as they are in a layout's `matches`.
+fn sorted_monitors(out_to_mon: &HashMap<Output, Monitor>) -> Vec<Monitor> {
+    let mut monitors: Vec<_> = out_to_mon.values().cloned().collect();
+    monitors.sort();
+    monitors
 }
 
-/// Find the config that matches the attached monitors. On a match, this returns a tuple of
-/// (name, frame buffer size, map from output to output config).
-fn get_config<'a, C: Connection>(
+/// Find the config that matches the attached monitors, falling back to the fallback layout when
+/// none does. On a match, this returns a tuple of (sorted attached monitors, matching config,
+/// map from output to output config).
+pub(crate) fn get_config<'a, C: Connection>(
     config: &'a Config,
     conn: &'a C,
-    outputs: &'a Vec<Output>,
+    outputs: &'a [Output],
     atom_edid: Atom,
-) -> Option<(&'a String, &'a Mode, HashMap<Output, &'a MonConfig>)> {
-    let out_to_mon: HashMap<_, _> = get_monitors(conn, outputs, atom_edid).collect();
-    let mut monitors: Vec<_> = out_to_mon.values().cloned().collect();
-    monitors.sort();
-    let SingleConfig {
-        name,
-        setup,
-        fb_size,
-    } = config.0.get(&monitors)?;
-    let mut out = HashMap::with_capacity(setup.len());
-    for (output, mon) in out_to_mon.into_iter() {
-        if let Some(moncfg) = setup.get(&mon) {
-            out.insert(output, moncfg);
+) -> Option<(
+    Vec<Monitor>,
+    &'a SingleConfig,
+    HashMap<Output, &'a MonConfig>,
+)> {
+    match_config(config, get_monitors(conn, outputs, atom_edid))
+}
+
+/// Find the config that matches the attached monitors, as `get_config` does, given the monitors
+/// attached to each output.
+fn match_config(
+    config: &Config,
+    attached: impl IntoIterator<Item = (Output, Monitor)>,
+) -> Option<(Vec<Monitor>, &SingleConfig, HashMap<Output, &MonConfig>)> {
+    let out_to_mon: HashMap<_, _> = attached
+        .into_iter()
+        .map(|(out, mon)| (out, config.identify(mon)))
+        .collect();
+    let monitors = sorted_monitors(&out_to_mon);
+    let (matches, single) = match config.lookup(&monitors) {
+        Some((matches, single)) => (matches.as_slice(), single),
+        None => {
+            let fallback = config.fallback.as_ref()?;
+            warn!(
+                "The connected monitors did not match a config; falling back to {}",
+                fallback.name
+            );
+            (&[][..], fallback)
         }
-    }
-    Some((name, fb_size, out))
+    };
+    let setup = outputs_in(matches, single, &out_to_mon);
+    Some((monitors, single, setup))
 }
 
-/// Create a map from human mode descriptions, in width and height, to Xorg mode identifiers
-fn mode_map<C: Connection>(
-    conn: &C,
-    root: Window,
-) -> Result<(HashMap<Mode, HashSet<u32>>, Timestamp)> {
-    let resources = conn
-        .randr_get_screen_resources(root)
-        .into_diagnostic()?
-        .reply()
-        .into_diagnostic()?;
-    let mut modes: HashMap<_, HashSet<u32>> = HashMap::with_capacity(resources.modes.len());
-    for mi in resources.modes.iter() {
-        modes
-            .entry(Mode {
-                w: mi.width,
-                h: mi.height,
-            })
-            .or_default()
-            .insert(mi.id);
-    }
-    Ok((modes, resources.timestamp))
+/// Create a map from Xorg mode identifiers to their size and refresh rate
+///
+/// The server's cached resources are used unless `probe` is set or the cache holds no modes,
+/// in which case the server is asked to probe its outputs, which is slow.
+fn mode_map<C: Connection>(conn: &C, root: Window, probe: bool) -> Result<(ModeSizes, Timestamp)> {
+    let (mode_infos, timestamp) = if probe {
+        let resources = conn
+            .randr_get_screen_resources(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        (resources.modes, resources.timestamp)
+    } else {
+        let resources = conn
+            .randr_get_screen_resources_current(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if resources.modes.is_empty() {
+            return mode_map(conn, root, true);
+        }
+        (resources.modes, resources.timestamp)
+    };
+    Ok((mode_sizes(&mode_infos), timestamp))
+}
+
+/// Check that every mode id of an output is described by the mode map.
+fn knows_modes(mode_map: &ModeSizes, info: &GetOutputInfoReply) -> bool {
+    info.modes.iter().all(|id| mode_map.contains_key(id))
 }
 
 /// Create a request to disable a CRTC or a default CRTC config request.
-fn disable_crtc<'a, 'b>(crtc: u32, from: &'a GetCrtcInfoReply) -> SetCrtcConfigRequest<'b> {
+pub(crate) fn disable_crtc<'b>(crtc: u32, from: &GetCrtcInfoReply) -> SetCrtcConfigRequest<'b> {
     SetCrtcConfigRequest {
         crtc,
         timestamp: from.timestamp,
@@ -95,7 +197,7 @@ fn disable_crtc<'a, 'b>(crtc: u32, from: &'a GetCrtcInfoReply) -> SetCrtcConfigR
 
 /// Allocate a CRTC for use by an output.
 fn allocate_crtc(info: &GetOutputInfoReply, free: &mut HashSet<&Crtc>) -> Option<Crtc> {
-    let dest = if info.crtc != 0 {
+    let dest = if info.crtc != 0 && free.contains(&info.crtc) {
         Some(info.crtc)
     } else {
         info.crtcs.iter().find_map(|c| free.get(&c).map(|&&a| a))
@@ -106,36 +208,419 @@ fn allocate_crtc(info: &GetOutputInfoReply, free: &mut HashSet<&Crtc>) -> Option
     dest
 }
 
-/// Find a matching mode id for the output within the mode map.
-///
-/// Since this is a helper function that's part of a command line utility,
-/// errors are returned as strings
+/// Find a matching mode id for the output within the mode map, as `best_mode_for_output`
+/// chooses among the modes of that size.
 fn find_mode_id(
     info: &GetOutputInfoReply,
-    mode_map: &HashMap<Mode, HashSet<u32>>,
+    mode_map: &ModeSizes,
     mode: &Mode,
+    rate: Option<f64>,
 ) -> Result<u32> {
-    let mode_ids = mode_map
-        .get(&mode)
-        .ok_or_else(|| Error::ModeNotFound(mode.clone()))
+    if !mode_map.values().any(|(size, _)| size == mode) {
+        return Err(Error::ModeNotFound(mode.clone())).into_diagnostic();
+    }
+    let id = best_mode_for_output(info, mode_map, Some(mode), rate)
+        .ok_or_else(|| Error::ModeNotSupported(mode.clone()))
+        .into_diagnostic()?;
+    if let Some(rate) = rate {
+        let found = mode_map[&id].1;
+        if (found - rate).abs() > 1.0 {
+            warn!(
+                "Mode {}@{} not supported, using {}@{:.2}",
+                mode, rate, mode, found
+            );
+        }
+    }
+    Ok(id)
+}
+
+/// Find the mode id of the output's preferred mode, falling back to its largest mode when it
+/// does not report a preferred mode, as `best_mode_for_output` chooses it.
+fn find_preferred_mode_id(
+    info: &GetOutputInfoReply,
+    mode_map: &ModeSizes,
+    name: &str,
+) -> Result<(u32, Mode)> {
+    let id = best_mode_for_output(info, mode_map, None, None)
+        .ok_or_else(|| Error::NoModes(name.to_string()))
         .into_diagnostic()?;
-    info.modes
+    let (mode, rate) = &mode_map[&id];
+    if info.num_preferred == 0 {
+        info!(
+            "Monitor {} has no preferred mode, using {}@{:.2}",
+            name, mode, rate
+        );
+    }
+    Ok((id, mode.clone()))
+}
+
+/// Resolve the configured mode of a monitor to a mode id and size.
+fn resolve_mode(
+    info: &GetOutputInfoReply,
+    mode_map: &ModeSizes,
+    conf: &MonConfig,
+) -> Result<(u32, Mode)> {
+    match &conf.mode {
+        ModeSpec::Exact(mode) => Ok((
+            find_mode_id(info, mode_map, mode, conf.target_rate())?,
+            mode.clone(),
+        )),
+        ModeSpec::Preferred => find_preferred_mode_id(info, mode_map, &conf.name),
+    }
+}
+
+/// Choose modes for a monitor and its clone. The source's configured mode is used when both
+/// monitors can display it, otherwise the largest mode both can display is used. On success,
+/// this returns a tuple of (source mode id, clone mode id, mode size).
+fn clone_modes(
+    src_info: &GetOutputInfoReply,
+    src_conf: &MonConfig,
+    clone_info: &GetOutputInfoReply,
+    clone_conf: &MonConfig,
+    mode_map: &ModeSizes,
+) -> Result<(u32, u32, Mode)> {
+    let sizes_of = |info: &GetOutputInfoReply| -> HashSet<Mode> {
+        info.modes
+            .iter()
+            .filter_map(|m| mode_map.get(m).map(|(mode, _)| mode.clone()))
+            .collect()
+    };
+    let common: HashSet<_> = sizes_of(src_info)
+        .intersection(&sizes_of(clone_info))
+        .cloned()
+        .collect();
+    let wanted = match resolve_mode(src_info, mode_map, src_conf) {
+        Ok((_, mode)) => Some(mode),
+        Err(_) => None,
+    };
+    let size = match wanted {
+        Some(mode) if common.contains(&mode) => mode,
+        _ => common
+            .into_iter()
+            .max_by_key(|m| (m.w as u32 * m.h as u32, m.w))
+            .ok_or_else(|| Error::NoCommonMode(src_conf.name.clone(), clone_conf.name.clone()))
+            .into_diagnostic()?,
+    };
+    let src_id = find_mode_id(src_info, mode_map, &size, src_conf.target_rate())?;
+    // Sharing the exact same mode allows both outputs to be driven by one CRTC
+    let clone_id = if clone_info.modes.contains(&src_id) {
+        src_id
+    } else {
+        find_mode_id(clone_info, mode_map, &size, src_conf.target_rate())?
+    };
+    Ok((src_id, clone_id, size))
+}
+
+/// A change to a RandR output property, as with `xrandr --set`
+struct PropertyChange {
+    output: Output,
+    name: String,
+    value: PropertyValue,
+    atom: Atom,
+    type_: Atom,
+    format: u8,
+    data: Vec<u8>,
+}
+
+/// The DPMS timeouts, as (standby, suspend, off), that setting `dpms` changes the server's to,
+/// when they differ from the server's.
+fn dpms_change<C: Connection>(conn: &C, dpms: &Dpms) -> Result<Option<(u16, u16, u16)>> {
+    let capable = conn
+        .dpms_capable()
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .capable;
+    if !capable {
+        return Err(Error::NoDpms).into_diagnostic();
+    }
+    let current = conn
+        .dpms_get_timeouts()
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    let current = (
+        current.standby_timeout,
+        current.suspend_timeout,
+        current.off_timeout,
+    );
+    let timeouts = dpms.timeouts(current);
+    Ok(Some(timeouts).filter(|&t| t != current))
+}
+
+/// The change that setting a property of an output to `value` makes, when it differs from the
+/// property's current value. `monitor` names the monitor in errors. Properties holding atoms
+/// take their value by name, and properties holding integers take a number.
+fn property_change<C: Connection>(
+    conn: &C,
+    output: Output,
+    monitor: &str,
+    name: &str,
+    value: &PropertyValue,
+) -> Result<Option<PropertyChange>> {
+    let unknown = || Error::UnknownProperty(monitor.to_string(), name.to_string());
+    let invalid =
+        || Error::InvalidPropertyValue(monitor.to_string(), name.to_string(), value.clone());
+    let atom = conn
+        .intern_atom(true, name.as_bytes())
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .atom;
+    if atom == u32::from(AtomEnum::NONE) {
+        return Err(unknown()).into_diagnostic();
+    }
+    // Outputs without the property answer with an error
+    let query = conn
+        .randr_query_output_property(output, atom)
+        .into_diagnostic()?
+        .reply()
+        .map_err(|_| unknown())
+        .into_diagnostic()?;
+    let current = conn
+        .randr_get_output_property(output, atom, AtomEnum::ANY, 0, 64, false, false)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    let number = match value {
+        PropertyValue::Text(text) if current.type_ == u32::from(AtomEnum::ATOM) => {
+            conn.intern_atom(false, text.as_bytes())
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic()?
+                .atom as i64
+        }
+        PropertyValue::Int(i) if current.type_ == u32::from(AtomEnum::INTEGER) => *i,
+        _ => return Err(invalid()).into_diagnostic(),
+    };
+    let valid = match &query.valid_values[..] {
+        [min, max] if query.range => (*min as i64..=*max as i64).contains(&number),
+        _ if query.range => true,
+        [] => true,
+        // Atoms are unsigned, so they are compared as the server sends them
+        values => values
+            .iter()
+            .any(|&v| v as u32 as i64 == number || v as i64 == number),
+    };
+    if !valid || query.immutable {
+        return Err(invalid()).into_diagnostic();
+    }
+    let data = match current.format {
+        8 => vec![number as u8],
+        16 => (number as u16).to_ne_bytes().to_vec(),
+        _ => (number as u32).to_ne_bytes().to_vec(),
+    };
+    if current.data == data {
+        return Ok(None);
+    }
+    Ok(Some(PropertyChange {
+        output,
+        name: name.to_string(),
+        value: value.clone(),
+        atom,
+        type_: current.type_,
+        format: if current.format == 0 {
+            32
+        } else {
+            current.format
+        },
+        data,
+    }))
+}
+
+/// The mode id that stands in for a mode that would be created, when changes are only printed
+const PLANNED_MODE: u32 = u32::MAX;
+
+/// The mode flags of a modeline, as the server describes them.
+fn modeline_flags(modeline: &Modeline) -> u32 {
+    modeline
+        .flags
         .iter()
-        .find_map(|m| mode_ids.get(m).map(|&m| m))
-        .ok_or_else(|| Error::ModeNotSupported(mode.clone()))
-        .into_diagnostic()
+        .filter_map(|flag| match flag.as_str() {
+            "+hsync" => Some(ModeFlag::HSYNC_POSITIVE),
+            "-hsync" => Some(ModeFlag::HSYNC_NEGATIVE),
+            "+vsync" => Some(ModeFlag::VSYNC_POSITIVE),
+            "-vsync" => Some(ModeFlag::VSYNC_NEGATIVE),
+            "interlace" => Some(ModeFlag::INTERLACE),
+            "doublescan" => Some(ModeFlag::DOUBLE_SCAN),
+            "csync" => Some(ModeFlag::CSYNC),
+            "+csync" => Some(ModeFlag::CSYNC_POSITIVE),
+            "-csync" => Some(ModeFlag::CSYNC_NEGATIVE),
+            // The configuration only holds flags from MODELINE_FLAGS
+            _ => None,
+        })
+        .fold(0, |flags, flag| flags | u32::from(flag))
 }
 
-/// Apply a batch of SetCrtcConfig commands.
-fn batch_config<C: Connection>(conn: &C, batch: Vec<SetCrtcConfigRequest>) -> Result<()> {
-    for req in &batch {
-        if req.mode != 0 {
-            info!(
-                "Configuring CRTC {} to mode {} at {},{}",
-                req.crtc, req.mode, req.x, req.y,
-            );
+/// Find the mode of a modeline on the server by name, creating it when the server does not have
+/// it, and add it to the modes of an output when the output does not offer it. Unless `execute`
+/// is set, the changes are printed instead, and a mode that would be created is `PLANNED_MODE`.
+fn ensure_modeline<C: Connection>(
+    conn: &C,
+    root: Window,
+    out: Output,
+    info: &GetOutputInfoReply,
+    modeline: &Modeline,
+    execute: bool,
+) -> Result<u32> {
+    let name = modeline.name();
+    let resources = conn
+        .randr_get_screen_resources_current(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    // Mode names are stored back to back, in the order of the modes
+    let mut names = resources.names.as_slice();
+    let mut existing = None;
+    for mi in resources.modes.iter() {
+        let (mode_name, rest) = names.split_at((mi.name_len as usize).min(names.len()));
+        names = rest;
+        if mode_name == name.as_bytes() {
+            existing = Some(mi.id);
+            break;
+        }
+    }
+    let id = match existing {
+        Some(id) => id,
+        None if !execute => {
+            println!("Mode {}: create {}", name, modeline);
+            PLANNED_MODE
+        }
+        None => {
+            let Mode { w, h } = modeline.size();
+            let [_, hsync_start, hsync_end, htotal] = modeline.horizontal;
+            let [_, vsync_start, vsync_end, vtotal] = modeline.vertical;
+            let mode_info = ModeInfo {
+                id: 0,
+                width: w,
+                height: h,
+                dot_clock: (modeline.clock * 1_000_000.0).round() as u32,
+                hsync_start,
+                hsync_end,
+                htotal,
+                hskew: 0,
+                vsync_start,
+                vsync_end,
+                vtotal,
+                name_len: name.len() as u16,
+                mode_flags: modeline_flags(modeline),
+            };
+            info!("Creating mode {} as {}", name, modeline);
+            conn.randr_create_mode(root, mode_info, name.as_bytes())
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic()?
+                .mode
+        }
+    };
+    if !info.modes.contains(&id) {
+        // The output's information already names it
+        let output = String::from_utf8_lossy(&info.name);
+        if execute {
+            info!("Adding mode {} to output {}", name, output);
+            conn.randr_add_output_mode(out, id)
+                .into_diagnostic()?
+                .check()
+                .into_diagnostic()?;
         } else {
-            info!("Disabling CRTC {}", req.crtc);
+            println!("Output {}: add mode {}", output, name);
+        }
+    }
+    Ok(id)
+}
+
+/// An output as log messages name it, by its connector name. The name is only read from the X
+/// server when the message is logged.
+struct LogOutput<'c, C> {
+    conn: &'c C,
+    output: Output,
+    timestamp: Timestamp,
+}
+
+impl<C: Connection> Display for LogOutput<'_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&output_name(self.conn, self.output, self.timestamp))
+    }
+}
+
+/// A CRTC as log messages name it, along with the connector names of `outputs`, or of the
+/// outputs it shows when `outputs` is empty, such as "CRTC 63 (eDP-1)". As with `LogOutput`,
+/// the X server is only asked when the message is logged.
+struct LogCrtc<'c, 'o, C> {
+    conn: &'c C,
+    crtc: Crtc,
+    outputs: &'o [Output],
+    timestamp: Timestamp,
+}
+
+impl<C: Connection> Display for LogCrtc<'_, '_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CRTC {}", self.crtc)?;
+        let shown;
+        let outputs = if self.outputs.is_empty() {
+            shown = self
+                .conn
+                .randr_get_crtc_info(self.crtc, self.timestamp)
+                .map_err(ReplyError::from)
+                .and_then(|cookie| cookie.reply())
+                .map(|info| info.outputs)
+                .unwrap_or_default();
+            &shown[..]
+        } else {
+            self.outputs
+        };
+        if outputs.is_empty() {
+            return Ok(());
+        }
+        let names: Vec<_> = outputs
+            .iter()
+            .map(|&out| output_name(self.conn, out, self.timestamp))
+            .collect();
+        write!(f, " ({})", names.join(", "))
+    }
+}
+
+/// Apply a batch of SetCrtcConfig commands. Unless `execute` is set, the commands are printed
+/// to stdout instead, naming modes as `mode_names` does.
+pub(crate) fn batch_config<C: Connection>(
+    conn: &C,
+    batch: Vec<SetCrtcConfigRequest>,
+    execute: bool,
+    mode_names: &HashMap<u32, Mode>,
+) -> Result<()> {
+    if !execute {
+        for req in &batch {
+            match mode_names.get(&req.mode) {
+                _ if req.mode == 0 => println!("CRTC {}: off", req.crtc),
+                Some(mode) => println!(
+                    "CRTC {}: {} at {},{} (mode {:#x})",
+                    req.crtc, mode, req.x, req.y, req.mode
+                ),
+                None => println!(
+                    "CRTC {}: at {},{} (mode {:#x})",
+                    req.crtc, req.x, req.y, req.mode
+                ),
+            }
+        }
+        return Ok(());
+    }
+    for req in &batch {
+        let crtc = LogCrtc {
+            conn,
+            crtc: req.crtc,
+            outputs: &req.outputs,
+            timestamp: req.config_timestamp,
+        };
+        match mode_names.get(&req.mode) {
+            _ if req.mode == 0 => info!("Disabling {}", crtc),
+            Some(mode) => info!(
+                "Configuring {} to {} at {},{} (mode {:#x})",
+                crtc, mode, req.x, req.y, req.mode
+            ),
+            None => info!(
+                "Configuring {} to mode {:#x} at {},{}",
+                crtc, req.mode, req.x, req.y
+            ),
         }
     }
     info!("Batch pre-sent");
@@ -164,63 +649,414 @@ fn batch_config<C: Connection>(conn: &C, batch: Vec<SetCrtcConfigRequest>) -> Re
     Ok(())
 }
 
-/// Make the current Xorg server match the specified configuration.
-fn apply_config<C: Connection>(
+/// Convert to the 16.16 fixed point numbers of a transform matrix.
+fn to_fixed(f: f64) -> Fixed {
+    (f * 65536.0).round() as Fixed
+}
+
+/// The transform matrix that scales a CRTC's image, or the identity without a scale.
+fn scale_transform(scale: Option<Scale>) -> Transform {
+    let Scale { x, y } = scale.unwrap_or(Scale { x: 1.0, y: 1.0 });
+    Transform {
+        matrix11: to_fixed(x),
+        matrix12: 0,
+        matrix13: 0,
+        matrix21: 0,
+        matrix22: to_fixed(y),
+        matrix23: 0,
+        matrix31: 0,
+        matrix32: 0,
+        matrix33: to_fixed(1.0),
+    }
+}
+
+/// A gamma ramp of `size` entries, computed as `xrandr --gamma` and `--brightness` do.
+fn gamma_ramp(size: u16, gamma: f64, brightness: f64) -> Vec<u16> {
+    let last = size.saturating_sub(1).max(1) as f64;
+    (0..size)
+        .map(|i| {
+            let value = (i as f64 / last).powf(1.0 / gamma) * brightness;
+            (value.min(1.0) * 65535.0).round() as u16
+        })
+        .collect()
+}
+
+/// The red, green and blue gamma ramps that a CRTC showing `conf` needs, when they differ from
+/// its current ramps. CRTCs of monitors without a gamma or brightness keep their ramps.
+fn gamma_change<C: Connection>(
+    conn: &C,
+    crtc: Crtc,
+    conf: &MonConfig,
+) -> Result<Option<[Vec<u16>; 3]>> {
+    if conf.gamma.is_none() && conf.brightness.is_none() {
+        return Ok(None);
+    }
+    let size = conn
+        .randr_get_crtc_gamma_size(crtc)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .size;
+    let Gamma { red, green, blue } = conf.gamma.unwrap_or(Gamma {
+        red: 1.0,
+        green: 1.0,
+        blue: 1.0,
+    });
+    let brightness = conf.brightness.unwrap_or(1.0);
+    let ramps = [
+        gamma_ramp(size, red, brightness),
+        gamma_ramp(size, green, brightness),
+        gamma_ramp(size, blue, brightness),
+    ];
+    let current = conn
+        .randr_get_crtc_gamma(crtc)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    if [current.red, current.green, current.blue] == ramps {
+        Ok(None)
+    } else {
+        Ok(Some(ramps))
+    }
+}
+
+/// The panning and tracking areas that a CRTC showing `conf` needs, when they differ from its
+/// current ones. A CRTC of a monitor without a panning area stops panning, and an empty
+/// tracking area is the whole screen.
+fn panning_change<C: Connection>(
+    conn: &C,
+    req: &SetCrtcConfigRequest,
+    conf: &MonConfig,
+) -> Result<Option<(Region, Region)>> {
+    let current = conn
+        .randr_get_panning(req.crtc)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    let nowhere = Region {
+        position: Position { x: 0, y: 0 },
+        size: Mode { w: 0, h: 0 },
+    };
+    let wanted = match &conf.panning {
+        Some(panning) => (
+            Region {
+                position: panning.origin(Position { x: req.x, y: req.y }),
+                size: panning.size.clone(),
+            },
+            panning.tracking.clone().unwrap_or_else(|| nowhere.clone()),
+        ),
+        None if current.width == 0 && current.height == 0 => return Ok(None),
+        None => (nowhere.clone(), nowhere),
+    };
+    let current_areas = (
+        Region {
+            position: Position {
+                x: current.left as i16,
+                y: current.top as i16,
+            },
+            size: Mode {
+                w: current.width,
+                h: current.height,
+            },
+        },
+        Region {
+            position: Position {
+                x: current.track_left as i16,
+                y: current.track_top as i16,
+            },
+            size: Mode {
+                w: current.track_width,
+                h: current.track_height,
+            },
+        },
+    );
+    if current_areas == wanted {
+        Ok(None)
+    } else {
+        Ok(Some(wanted))
+    }
+}
+
+/// Make the current Xorg server match the specified configuration. Unless `execute` is set,
+/// the changes are printed to stdout instead of made, and `before_change` is not called.
+pub(crate) fn apply_config<C: Connection>(
     conn: &C,
     res: &GetScreenResourcesCurrentReply,
-    fb_size: &Mode,
+    single: &SingleConfig,
     setup: HashMap<Output, &MonConfig>,
     root: Window,
+    execute: bool,
+    before_change: impl FnOnce(),
 ) -> Result<bool> {
-    let (modes, timestamp) = mode_map(conn, root)?;
+    let (mut modes, timestamp) = mode_map(conn, root, false)?;
+    let mut probed = false;
+    // Every CRTC is queried up front, sending all requests before waiting for any reply, so that
+    // planning waits on the server once rather than once per CRTC
+    let cookies = res
+        .crtcs
+        .iter()
+        .map(|&crtc| {
+            Ok((
+                crtc,
+                conn.randr_get_crtc_info(crtc, timestamp)
+                    .into_diagnostic()?,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut crtc_infos = HashMap::with_capacity(cookies.len());
+    for (crtc, cookie) in cookies {
+        crtc_infos.insert(crtc, cookie.reply().into_diagnostic()?);
+    }
+    let cached_crtc_info = |crtc: Crtc| -> Result<GetCrtcInfoReply> {
+        match crtc_infos.get(&crtc) {
+            Some(info) => Ok(info.clone()),
+            None => conn
+                .randr_get_crtc_info(crtc, timestamp)
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic(),
+        }
+    };
+    // The screen grows to fit the layout, as modes are only known once they are resolved
+    let mut fb_size = single.fb_size.clone().unwrap_or(Mode { w: 0, h: 0 });
     let mut free_crtcs: HashSet<_> = res.crtcs.iter().collect();
     let mut enables = Vec::with_capacity(res.crtcs.len());
-    let mut mm_w = 0;
-    let mut mm_h = 0;
-    let outs_in_conf = res
+    let mut mm_w = 0u32;
+    let mut mm_h = 0u32;
+    let primary = res
         .outputs
         .iter()
-        .filter_map(|o| setup.get(&o).map(|c| (c, o)));
-    // This loop can't easily be a map, as it needs to be able to use '?'
-    for (&conf, &out) in outs_in_conf {
+        .find(|o| setup.get(o).is_some_and(|c| c.enabled && c.primary))
+        .copied();
+    let (outs_in_conf, outs_off): (Vec<_>, Vec<_>) = res
+        .outputs
+        .iter()
+        .filter_map(|o| setup.get(o).map(|c| (c, o)))
+        .partition(|(c, _)| c.enabled);
+    // Outputs that are explicitly turned off keep their CRTC out of the free pool, so that it is
+    // disabled rather than handed to another output
+    let mut disables = Vec::with_capacity(res.crtcs.len());
+    for (_, &out) in outs_off {
         let out_info = conn
             .randr_get_output_info(out, timestamp)
             .into_diagnostic()?
             .reply()
             .into_diagnostic()?;
-        let mode = find_mode_id(&out_info, &modes, &conf.mode)?;
-        let dest_crtc = allocate_crtc(&out_info, &mut free_crtcs)
-            .ok_or_else(|| Error::NoCrtc(conf.name.clone()))
+        if out_info.crtc != 0 {
+            free_crtcs.remove(&out_info.crtc);
+            let info = cached_crtc_info(out_info.crtc)?;
+            if info.mode != 0 {
+                disables.push(disable_crtc(out_info.crtc, &info));
+            }
+        }
+    }
+    // Connected monitors that the layout does not name keep their CRTC, unless the layout
+    // disables them
+    if single.allow_extra_monitors && !single.disable_extra_monitors {
+        for out in res.outputs.iter().filter(|o| !setup.contains_key(o)) {
+            let out_info = conn
+                .randr_get_output_info(*out, timestamp)
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic()?;
+            if out_info.connection != RandrConnection::CONNECTED || out_info.crtc == 0 {
+                continue;
+            }
+            free_crtcs.remove(&out_info.crtc);
+            let info = cached_crtc_info(out_info.crtc)?;
+            if info.mode != 0 {
+                fb_size = fb_size.union(&Mode {
+                    w: (info.x.max(0) as u16).saturating_add(info.width),
+                    h: (info.y.max(0) as u16).saturating_add(info.height),
+                });
+            }
+        }
+    }
+    // This loop can't easily be a map, as it needs to be able to use '?'
+    let mut infos = HashMap::with_capacity(outs_in_conf.len());
+    for &(&conf, &out) in outs_in_conf.iter() {
+        let out_info = conn
+            .randr_get_output_info(out, timestamp)
+            .into_diagnostic()?
+            .reply()
             .into_diagnostic()?;
+        // The cached resources may be missing modes of a newly attached monitor
+        if !probed && !knows_modes(&modes, &out_info) {
+            modes = mode_map(conn, root, true)?.0;
+            probed = true;
+        }
+        infos.insert(out, (conf, out_info));
+    }
+    // Modes given by a modeline may need creating before modes are chosen
+    for (&out, (conf, info)) in infos.iter_mut() {
+        if let Some(modeline) = &conf.modeline {
+            let id = ensure_modeline(conn, root, out, info, modeline, execute)?;
+            modes.insert(id, (modeline.size(), modeline.rate()));
+            if !info.modes.contains(&id) {
+                info.modes.push(id);
+            }
+        }
+    }
+    let source_of = |clone_of: &String| {
+        infos
+            .iter()
+            .find(|(_, (c, _))| &c.name == clone_of)
+            .map(|(&o, _)| o)
+    };
+    // Clones share a mode with their source, so those are chosen together
+    let mut resolved = HashMap::with_capacity(infos.len());
+    for (&out, (conf, info)) in infos.iter() {
+        if let Some(src_out) = conf.clone_of.as_ref().and_then(source_of) {
+            let (src_conf, src_info) = &infos[&src_out];
+            let (src_id, clone_id, size) = clone_modes(src_info, src_conf, info, conf, &modes)?;
+            resolved.insert(src_out, (src_id, size.clone()));
+            resolved.insert(out, (clone_id, size));
+        }
+    }
+    for (&out, (conf, info)) in infos.iter() {
+        if let Entry::Vacant(entry) = resolved.entry(out) {
+            entry.insert(resolve_mode(info, &modes, conf)?);
+        }
+    }
+    // Relative positions depend on the sizes of the chosen modes, once scaled
+    let sizes: HashMap<_, _> = infos
+        .iter()
+        .map(|(out, (conf, _))| (conf.name.as_str(), conf.scaled(&resolved[out].1)))
+        .collect();
+    let positions = resolve_positions(infos.values().map(|(conf, _)| *conf), |m| {
+        sizes.get(m.name.as_str()).cloned()
+    })
+    .into_diagnostic()?;
+    for name in unreachable_monitors(infos.values().map(|(conf, _)| *conf)) {
+        warn!(
+            "Monitor {} has no position and is not reachable from the origin monitor, so it is placed at 0,0",
+            name
+        );
+    }
+    // Sizes are only all known now, so overlaps are looked for again with the chosen modes
+    let mut regions: Vec<_> = infos
+        .values()
+        .filter(|(conf, _)| conf.clone_of.is_none())
+        .map(|(conf, _)| {
+            let region = Region {
+                position: positions[&conf.name],
+                size: sizes[conf.name.as_str()].clone(),
+            };
+            (conf.name.as_str(), region)
+        })
+        .collect();
+    regions.sort_by_key(|(name, _)| *name);
+    for (a, b, shared) in Region::overlaps(&regions) {
+        warn!("Monitors {} and {} overlap at {}", a, b, shared);
+    }
+    // Sources are planned before their clones, so that a clone may join its source's CRTC
+    let mut ordered = outs_in_conf;
+    ordered.sort_by_key(|(c, _)| c.clone_of.is_some());
+    let mut planned: Vec<(SetCrtcConfigRequest, GetCrtcInfoReply, &MonConfig)> = Vec::new();
+    let mut planned_for: HashMap<Output, usize> = HashMap::with_capacity(ordered.len());
+    for (&conf, &out) in ordered {
+        let (_, out_info) = &infos[&out];
+        let (mode, size) = resolved[&out].clone();
         //TODO: This is not a correct computation of the screen size
         mm_w += out_info.mm_width;
         mm_h += out_info.mm_height;
-        let Position { x, y } = conf.position;
-        let crtc_info = conn
-            .randr_get_crtc_info(dest_crtc, timestamp)
-            .into_diagnostic()?
-            .reply()
+        let Position { x, y } = positions[conf.clone_of.as_ref().unwrap_or(&conf.name)];
+        let size = conf.scaled(&size);
+        // Preferred modes are only known now, so panning areas are checked against them here
+        if conf.pans_too_little(&size) {
+            return Err(Error::PanningTooSmall(conf.name.clone())).into_diagnostic();
+        }
+        fb_size = fb_size.union(&conf.extent(Position { x, y }, &size));
+        if let Some(src_out) = conf.clone_of.as_ref().and_then(source_of) {
+            if let Some(&idx) = planned_for.get(&src_out) {
+                let (req, _, _) = &mut planned[idx];
+                let (_, src_info) = &infos[&src_out];
+                if req.mode == mode
+                    && out_info.crtcs.contains(&req.crtc)
+                    && src_info.clones.contains(&out)
+                {
+                    req.outputs.to_mut().push(out);
+                    continue;
+                }
+            }
+        }
+        let dest_crtc = allocate_crtc(out_info, &mut free_crtcs)
+            .ok_or_else(|| Error::NoCrtc(conf.name.clone()))
             .into_diagnostic()?;
-        if x != crtc_info.x || y != crtc_info.y || mode != crtc_info.mode {
-            enables.push(SetCrtcConfigRequest {
+        let crtc_info = cached_crtc_info(dest_crtc)?;
+        planned_for.insert(out, planned.len());
+        planned.push((
+            SetCrtcConfigRequest {
                 x,
                 y,
                 rotation: 1,
                 mode,
                 outputs: vec![out].into(),
                 ..disable_crtc(dest_crtc, &crtc_info)
-            });
+            },
+            crtc_info,
+            conf,
+        ));
+    }
+    // Transforms only take effect once their CRTC is configured again
+    let mut transforms = Vec::new();
+    let mut gammas = Vec::new();
+    let mut pannings = Vec::new();
+    for (req, crtc_info, conf) in planned.into_iter() {
+        if let Some(ramps) = gamma_change(conn, req.crtc, conf)? {
+            gammas.push((req.crtc, ramps));
+        }
+        if let Some(area) = panning_change(conn, &req, conf)? {
+            pannings.push((req.crtc, area));
+        }
+        let transform = scale_transform(conf.scale);
+        let current_transform = conn
+            .randr_get_crtc_transform(req.crtc)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?
+            .current_transform;
+        let transform_changed = current_transform != transform;
+        if transform_changed {
+            transforms.push((req.crtc, req.outputs.to_vec(), transform));
+        }
+        let mut outputs = req.outputs.to_vec();
+        outputs.sort_unstable();
+        let mut current = crtc_info.outputs.clone();
+        current.sort_unstable();
+        if req.x != crtc_info.x
+            || req.y != crtc_info.y
+            || req.mode != crtc_info.mode
+            || outputs != current
+            || transform_changed
+        {
+            enables.push(req);
         }
     }
+    // A property that can not be set is logged, rather than keeping the layout from applying
+    let mut properties = Vec::new();
+    for (&out, (conf, _)) in infos.iter() {
+        for (name, value) in conf.properties.iter() {
+            match property_change(conn, out, &conf.name, name, value) {
+                Ok(Some(change)) => properties.push(change),
+                Ok(None) => (),
+                Err(e) => error!("{}", e),
+            }
+        }
+    }
+    // As with properties, DPMS timeouts that can not be set are logged
+    let dpms = match single.dpms.as_ref().map(|dpms| dpms_change(conn, dpms)) {
+        Some(Ok(change)) => change,
+        Some(Err(e)) => {
+            error!("{:?}", e);
+            None
+        }
+        None => None,
+    };
     // If there were CRTCs left over after allocating the next setup, ensure that they are
     // disabled
-    let mut disables = Vec::with_capacity(free_crtcs.len());
     for &crtc in free_crtcs.into_iter() {
-        let info = conn
-            .randr_get_crtc_info(crtc, timestamp)
-            .into_diagnostic()?
-            .reply()
-            .into_diagnostic()?;
+        let info = cached_crtc_info(crtc)?;
         if !info.outputs.is_empty() || info.mode != 0 {
             disables.push(disable_crtc(crtc, &info));
         }
@@ -235,75 +1071,777 @@ fn apply_config<C: Connection>(
         w: geom.width,
         h: geom.height,
     };
-    if disables.is_empty() && enables.is_empty() && &current == fb_size {
+    let current_primary = conn
+        .randr_get_output_primary(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .output;
+    let primary = primary.filter(|&out| out != current_primary);
+    if disables.is_empty()
+        && enables.is_empty()
+        && gammas.is_empty()
+        && pannings.is_empty()
+        && properties.is_empty()
+        && dpms.is_none()
+        && current == fb_size
+        && primary.is_none()
+    {
         Ok(false)
     } else {
-        // First, we disable any CTRCs that must be disabled
-        if !disables.is_empty() {
-            info!("Disabling CRTCs {:?}", disables);
-            batch_config(conn, disables)?;
-        }
-        // Then we change the screen size to be large enough for both configuration
-        if current != current.union(fb_size) {
-            current = current.union(fb_size);
-            info!(
-                "Before Config - Setting Screen {} Size to {}x{} {}mmx{}mm",
-                root, current.w, current.h, mm_w, mm_h
-            );
-            conn.randr_set_screen_size(root, current.w, current.h, mm_w, mm_h)
-                .into_diagnostic()?
-                .check()
-                .into_diagnostic()?;
+        if execute {
+            before_change();
         }
-        // Finally we enable and change modes of CRTCs
-        batch_config(conn, enables)?;
-        // Lastly we change the screen size to be the correct size for the final config
-        if &current != fb_size {
-            conn.randr_set_screen_size(root, fb_size.w, fb_size.h, mm_w, mm_h)
-                .into_diagnostic()?
-                .check()
-                .into_diagnostic()?;
-            info!(
-                "After Config - Setting Screen Size to {}x{}",
-                fb_size.w, fb_size.h
-            );
+        let mode_names: HashMap<u32, Mode> = modes
+            .iter()
+            .map(|(&id, (mode, _))| (id, mode.clone()))
+            .collect();
+        // The physical size of the screen follows the layout's DPI, when it has one
+        let mm_size = |size: &Mode| match single.dpi {
+            Some(dpi) => (
+                (size.w as f64 * 25.4 / dpi).round() as u32,
+                (size.h as f64 * 25.4 / dpi).round() as u32,
+            ),
+            None => (mm_w, mm_h),
+        };
+        // Every change after this point is undone when a later one fails
+        let snapshot = if execute {
+            Some(Snapshot::take(conn, res, root, timestamp)?)
+        } else {
+            None
+        };
+        let change = || -> Result<()> {
+            // First, we disable any CTRCs that must be disabled
+            if !disables.is_empty() {
+                info!("Disabling {} CRTCs", disables.len());
+                batch_config(conn, disables, execute, &mode_names)?;
+            }
+            // Then we change the screen size to be large enough for both configuration
+            if current != current.union(&fb_size) {
+                current = current.union(&fb_size);
+                let (mm_w, mm_h) = mm_size(&current);
+                if execute {
+                    info!(
+                        "Before Config - Setting Screen {} Size to {}x{} {}mmx{}mm",
+                        root, current.w, current.h, mm_w, mm_h
+                    );
+                    conn.randr_set_screen_size(root, current.w, current.h, mm_w, mm_h)
+                        .into_diagnostic()?
+                        .check()
+                        .into_diagnostic()?;
+                } else {
+                    println!("Screen: {} ({}mmx{}mm)", current, mm_w, mm_h);
+                }
+            }
+            for (crtc, outputs, transform) in transforms {
+                if !execute {
+                    let (x, y) = (transform.matrix11, transform.matrix22);
+                    let (x, y) = (x as f64 / 65536.0, y as f64 / 65536.0);
+                    println!("CRTC {}: scale {}", crtc, Scale { x, y });
+                    continue;
+                }
+                let log_crtc = LogCrtc {
+                    conn,
+                    crtc,
+                    outputs: &outputs,
+                    timestamp,
+                };
+                info!("Setting the transform of {} to {:?}", log_crtc, transform);
+                // Scaled images are filtered as xrandr --scale filters them
+                let filter: &[u8] = if transform == scale_transform(None) {
+                    b"nearest"
+                } else {
+                    b"bilinear"
+                };
+                conn.randr_set_crtc_transform(crtc, transform, filter, &[])
+                    .into_diagnostic()?
+                    .check()
+                    .into_diagnostic()?;
+            }
+            // Finally we enable and change modes of CRTCs
+            batch_config(conn, enables, execute, &mode_names)?;
+            // Panning applies to a CRTC once it shows its mode
+            for (crtc, (area, tracking)) in pannings {
+                if !execute {
+                    println!("CRTC {}: panning {}, tracking {}", crtc, area, tracking);
+                    continue;
+                }
+                let log_crtc = LogCrtc {
+                    conn,
+                    crtc,
+                    outputs: &[],
+                    timestamp,
+                };
+                info!(
+                    "Setting the panning of {} to {}, tracking {}",
+                    log_crtc, area, tracking
+                );
+                let reply = conn
+                    .randr_set_panning(
+                        crtc,
+                        timestamp,
+                        area.position.x as u16,
+                        area.position.y as u16,
+                        area.size.w,
+                        area.size.h,
+                        tracking.position.x as u16,
+                        tracking.position.y as u16,
+                        tracking.size.w,
+                        tracking.size.h,
+                        0,
+                        0,
+                        0,
+                        0,
+                    )
+                    .into_diagnostic()?
+                    .reply()
+                    .into_diagnostic()?;
+                if reply.status != SetConfig::SUCCESS {
+                    error!("Setting the panning of {} failed", log_crtc);
+                }
+            }
+            // Gamma ramps apply to a CRTC as it is now configured
+            for (crtc, [red, green, blue]) in gammas {
+                if !execute {
+                    println!("CRTC {}: gamma ramps of {} entries", crtc, red.len());
+                    continue;
+                }
+                let log_crtc = LogCrtc {
+                    conn,
+                    crtc,
+                    outputs: &[],
+                    timestamp,
+                };
+                info!("Setting the gamma ramps of {}", log_crtc);
+                conn.randr_set_crtc_gamma(crtc, &red, &green, &blue)
+                    .into_diagnostic()?
+                    .check()
+                    .into_diagnostic()?;
+            }
+            // Lastly we change the screen size to be the correct size for the final config
+            if current != fb_size || single.dpi.is_some() {
+                let (mm_w, mm_h) = mm_size(&fb_size);
+                if !execute {
+                    println!("Screen: {} ({}mmx{}mm)", fb_size, mm_w, mm_h);
+                } else {
+                    conn.randr_set_screen_size(root, fb_size.w, fb_size.h, mm_w, mm_h)
+                        .into_diagnostic()?
+                        .check()
+                        .into_diagnostic()?;
+                    info!(
+                        "After Config - Setting Screen Size to {}x{}",
+                        fb_size.w, fb_size.h
+                    );
+                }
+                if let Some(dpi) = single.dpi.filter(|_| execute) {
+                    info!("Set the screen to {} DPI, at {}mmx{}mm", dpi, mm_w, mm_h);
+                }
+            }
+            // Properties apply to an output as it is now configured
+            for change in properties {
+                let output = LogOutput {
+                    conn,
+                    output: change.output,
+                    timestamp,
+                };
+                if !execute {
+                    println!("Output {}: set {} to {}", output, change.name, change.value);
+                    continue;
+                }
+                info!(
+                    "Setting property {} of output {} to {}",
+                    change.name, output, change.value
+                );
+                let set = conn
+                    .randr_change_output_property(
+                        change.output,
+                        change.atom,
+                        change.type_,
+                        change.format,
+                        PropMode::REPLACE,
+                        1,
+                        &change.data,
+                    )
+                    .into_diagnostic()
+                    .and_then(|cookie| cookie.check().into_diagnostic());
+                if let Err(e) = set {
+                    error!(
+                        "Setting property {} of output {} failed: {}",
+                        change.name, output, e
+                    );
+                }
+            }
+            if let Some((standby, suspend, off)) = dpms {
+                if !execute {
+                    println!(
+                        "DPMS: standby {}s, suspend {}s, off {}s",
+                        standby, suspend, off
+                    );
+                } else {
+                    info!(
+                        "Setting the DPMS timeouts to standby {}s, suspend {}s, off {}s",
+                        standby, suspend, off
+                    );
+                    let set = conn
+                        .dpms_set_timeouts(standby, suspend, off)
+                        .into_diagnostic()
+                        .and_then(|cookie| cookie.check().into_diagnostic());
+                    if let Err(e) = set {
+                        error!("Setting the DPMS timeouts failed: {}", e);
+                    }
+                }
+            }
+            // The primary output can only be chosen once it is displaying something
+            if let Some(out) = primary {
+                let output = LogOutput {
+                    conn,
+                    output: out,
+                    timestamp,
+                };
+                if execute {
+                    info!("Setting primary output to {}", output);
+                    conn.randr_set_output_primary(root, out)
+                        .into_diagnostic()?
+                        .check()
+                        .into_diagnostic()?;
+                } else {
+                    println!("Primary: output {}", output);
+                }
+            }
+            Ok(())
+        };
+        if let Err(e) = change() {
+            if let Some(snapshot) = snapshot {
+                warn!(
+                    "Applying layout {} failed, restoring the previous layout",
+                    single.name
+                );
+                if let Err(rollback) = snapshot.restore(conn, root) {
+                    error!("Could not restore the previous layout: {}", rollback);
+                }
+            }
+            return Err(e);
         }
         Ok(true)
     }
 }
 
-/// Called for each screen change notificaiton. Detects connected monitors and switches
-/// to the appropriate config.
-fn switch_setup<C: Connection>(
-    config: &Config,
+/// The environment of a layout's on-apply hook: the layout name and the names of the outputs
+/// it shows, comma separated. This is `None` when the layout has no hook.
+pub(crate) fn on_apply_env<C: Connection>(
     conn: &C,
-    edid: Atom,
+    res: &GetScreenResourcesCurrentReply,
+    single: &SingleConfig,
+    setup: &HashMap<Output, &MonConfig>,
+) -> Result<Option<Vec<(&'static str, String)>>> {
+    if single.on_apply.is_none() {
+        return Ok(None);
+    }
+    let mut outputs = Vec::with_capacity(setup.len());
+    for out in res
+        .outputs
+        .iter()
+        .filter(|o| setup.get(o).is_some_and(|c| c.enabled))
+    {
+        let info = conn
+            .randr_get_output_info(*out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        outputs.push(String::from_utf8_lossy(&info.name).into_owned());
+    }
+    Ok(Some(vec![
+        ("AUTORANDR_PROFILE", single.name.clone()),
+        ("AUTORANDR_OUTPUTS", outputs.join(",")),
+    ]))
+}
+
+/// The environment of the pre-switch and post-switch hooks: the names of the layout being
+/// switched from, if any, and to.
+pub(crate) fn switch_env(
+    old: Option<String>,
+    single: &SingleConfig,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("AUTORANDR_OLD_PROFILE", old.unwrap_or_default()),
+        ("AUTORANDR_PROFILE", single.name.clone()),
+    ]
+}
+
+/// The default location of the daemon's PID file.
+fn default_pid_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("autorandr-rs.pid")
+}
+
+/// A file holding the daemon's PID, which is removed when dropped.
+struct PidFile(PathBuf);
+
+impl PidFile {
+    /// Write the PID file, unless it names a daemon that is still running.
+    fn create(path: PathBuf) -> Result<Self> {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            if let Ok(pid) = text.trim().parse::<i32>() {
+                // Signal 0 only checks whether the process exists
+                let alive = unsafe { libc::kill(pid, 0) } == 0
+                    || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM);
+                if alive && pid != std::process::id() as i32 {
+                    return Err(Error::AlreadyRunning(pid, path.display().to_string()))
+                        .into_diagnostic();
+                }
+            }
+        }
+        std::fs::write(&path, format!("{}\n", std::process::id())).into_diagnostic()?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            warn!("Could not remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// The environment of the on-no-match hook: the name of the layout shown before the monitors
+/// changed, if any.
+pub(crate) fn no_match_env(old: Option<String>) -> Vec<(&'static str, String)> {
+    vec![("AUTORANDR_OLD_PROFILE", old.unwrap_or_default())]
+}
+
+/// How often to check for new events while waiting for a burst of events to end
+const DEBOUNCE_POLL: Duration = Duration::from_millis(10);
+
+/// The property of the root window that holds the name of the layout the daemon applied, for
+/// status bars and other tools to read with xprop
+pub const PROFILE_PROPERTY: &str = "_AUTORANDR_PROFILE";
+
+/// The atoms that publish the active layout on the root window
+struct ProfileAtoms {
+    property: Atom,
+    utf8_string: Atom,
+}
+
+impl ProfileAtoms {
+    fn intern<C: Connection>(conn: &C) -> Result<Self, crate::Error> {
+        Ok(Self {
+            property: intern(conn, PROFILE_PROPERTY)?,
+            utf8_string: intern(conn, "UTF8_STRING")?,
+        })
+    }
+}
+
+/// The connection to the X server and what depends on it, which is replaced when the daemon
+/// reconnects to a restarted server
+struct XServer {
+    /// Shared, so that a layout can be applied without holding the lock on the server
+    conn: Arc<RustConnection>,
     root: Window,
-    force_print: bool,
-) -> () {
-    let res = match get_outputs(conn, root) {
+    atom_edid: OnceLock<Atom>,
+    profile_atoms: ProfileAtoms,
+}
+
+impl XServer {
+    /// Connect to the X server and select the events that the daemon reacts to.
+    fn connect() -> Result<Self> {
+        let (conn, screen_num) = RustConnection::connect(None).into_diagnostic()?;
+        let root = conn.setup().roots[screen_num].root;
+        let atom_edid = OnceLock::new();
+        edid_atom_cached(&conn, &atom_edid).into_diagnostic()?;
+        let profile_atoms = ProfileAtoms::intern(&conn).into_diagnostic()?;
+        setup_notify(&conn, root, notify_mask())?;
+        Ok(Self {
+            conn: Arc::new(conn),
+            root,
+            atom_edid,
+            profile_atoms,
+        })
+    }
+}
+
+/// State shared between the daemon's event loop and its IPC server.
+pub struct DaemonContext {
+    /// The configuration files, merged in order
+    config_names: Vec<String>,
+    config: RwLock<Config>,
+    /// Only locked around requests to the server, and never while it is already locked, so that
+    /// reconnecting waits for the requests in progress. Applying a layout takes its own handle
+    /// to the connection instead, as it runs pre-switch hooks.
+    x: RwLock<XServer>,
+    /// The attached monitors, kept until events show that they changed
+    edid_cache: Mutex<EdidCache>,
+    status: Mutex<StatusInfo>,
+    stats: Mutex<DaemonStats>,
+    /// Held from choosing a layout until it is applied, so that the event loop, the IPC server
+    /// and the D-Bus interface apply one layout at a time
+    applying: Mutex<()>,
+    /// The attached monitors when a layout was last applied by name, which holds until they
+    /// change, rather than giving way to the matching layout on the events it raises
+    forced: Mutex<Option<Vec<Monitor>>>,
+    /// The session bus connection, once the D-Bus interface is served
+    dbus: OnceLock<zbus::blocking::Connection>,
+}
+
+impl DaemonContext {
+    fn x(&self) -> RwLockReadGuard<'_, XServer> {
+        self.x.read().unwrap()
+    }
+
+    /// The EDID atom, interned on first use.
+    fn atom_edid(&self) -> Result<Atom> {
+        let x = self.x();
+        edid_atom_cached(&*x.conn, &x.atom_edid).into_diagnostic()
+    }
+
+    /// The active profile and the monitors it was chosen for.
+    pub fn status(&self) -> StatusInfo {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Counters collected since the daemon started.
+    pub fn stats(&self) -> DaemonStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// Re-read the configuration files and apply the layout that matches the attached monitors
+    /// under the new configuration, giving up a layout applied by name, which may have changed
+    /// or gone. The current configuration is kept on error.
+    pub fn reload(&self) -> Result<()> {
+        let config = Config::from_fnames(&self.config_names).into_diagnostic()?;
+        *self.config.write().unwrap() = config;
+        *self.forced.lock().unwrap() = None;
+        info!(
+            "Reloaded configuration from {}",
+            self.config_names.join(", ")
+        );
+        switch_setup(self, true);
+        Ok(())
+    }
+
+    /// Read the attached monitors, sorted as they are in a layout's `matches`. Their EDIDs are
+    /// always read, bypassing the EDID cache, as polling relies on this to find changes that
+    /// raised no event.
+    pub fn fingerprint(&self) -> Result<Vec<Monitor>> {
+        let atom_edid = self.atom_edid()?;
+        let x = self.x();
+        let res = get_outputs(&*x.conn, x.root).into_diagnostic()?;
+        let mut monitors: Vec<_> = get_monitors(&*x.conn, &res.outputs, atom_edid)
+            .map(|(_, mon)| mon)
+            .collect();
+        monitors.sort();
+        Ok(monitors)
+    }
+
+    /// The monitors attached to `outputs`, read through the EDID cache.
+    fn attached(&self, outputs: &[Output]) -> Result<Vec<(Output, Monitor)>> {
+        let atom_edid = self.atom_edid()?;
+        let x = self.x();
+        let mut cache = self.edid_cache.lock().unwrap();
+        Ok(cache.get_monitors(&*x.conn, outputs, atom_edid))
+    }
+
+    /// Set the root window property that names the active layout, or delete it when there is
+    /// none. Failing to is only logged, as the layout itself is unaffected.
+    fn publish_profile(&self, profile: Option<&str>) {
+        let x = self.x();
+        let ProfileAtoms {
+            property,
+            utf8_string,
+        } = x.profile_atoms;
+        let published = match profile {
+            Some(profile) => x
+                .conn
+                .change_property8(
+                    PropMode::REPLACE,
+                    x.root,
+                    property,
+                    utf8_string,
+                    profile.as_bytes(),
+                )
+                .map(|cookie| cookie.ignore_error()),
+            None => x
+                .conn
+                .delete_property(x.root, property)
+                .map(|cookie| cookie.ignore_error()),
+        };
+        if let Err(e) = published.and_then(|_| x.conn.flush()) {
+            warn!("Could not set {}: {}", PROFILE_PROPERTY, e);
+        }
+    }
+
+    /// Forget the attached monitors that `event` shows have changed.
+    fn observe(&self, event: &Event) {
+        let atom_edid = self.x().atom_edid.get().copied();
+        if let Some(atom_edid) = atom_edid {
+            self.edid_cache.lock().unwrap().observe(event, atom_edid);
+        }
+    }
+
+    /// Apply the layout named `profile`, regardless of the attached monitors. It is kept until
+    /// the attached monitors change.
+    pub fn switch_to(&self, profile: &str) -> Result<()> {
+        let _applying = self.applying.lock().unwrap();
+        self.force(profile)
+    }
+
+    /// Apply the layout named `profile`, and keep it for the attached monitors. The caller holds
+    /// `applying`.
+    fn force(&self, profile: &str) -> Result<()> {
+        let res = {
+            let x = self.x();
+            get_outputs(&*x.conn, x.root).into_diagnostic()?
+        };
+        let config = self.config.read().unwrap();
+        let (matches, single) = config.layout_named(profile).into_diagnostic()?;
+        let out_to_mon = self
+            .attached(&res.outputs)?
+            .into_iter()
+            .map(|(out, mon)| (out, config.identify(mon)))
+            .collect();
+        let setup = outputs_in(matches, single, &out_to_mon);
+        let monitors = sorted_monitors(&out_to_mon);
+        self.apply(&config, &res, &monitors, single, setup, true)?;
+        *self.forced.lock().unwrap() = Some(monitors);
+        Ok(())
+    }
+
+    /// Apply the next of the layouts that match the attached monitors, as `cycle` does, and
+    /// return its name.
+    pub fn cycle(&self) -> Result<String> {
+        let _applying = self.applying.lock().unwrap();
+        let state = {
+            let x = self.x();
+            current_state(&*x.conn, x.root)?
+        };
+        let profile = {
+            let config = self.config.read().unwrap();
+            let state = state
+                .into_iter()
+                .map(|(mon, output)| (config.identify(mon), output))
+                .collect();
+            next_layout(&config, &state)?
+        };
+        self.force(&profile)?;
+        Ok(profile)
+    }
+
+    /// Apply a layout, keeping track of the outcome.
+    fn apply(
+        &self,
+        config: &Config,
+        res: &GetScreenResourcesCurrentReply,
+        monitors: &[Monitor],
+        single: &SingleConfig,
+        setup: HashMap<Output, &MonConfig>,
+        force_print: bool,
+    ) -> Result<()> {
+        // Pre-switch hooks may block for a while, so the lock is not held while applying; a
+        // reconnect meanwhile leaves this layout to fail on the connection it replaced
+        let (conn, root) = {
+            let x = self.x();
+            (x.conn.clone(), x.root)
+        };
+        let hook_env = on_apply_env(&*conn, res, single, &setup)?;
+        let switch_env = switch_env(self.status().profile, single);
+        let pre_switch = || {
+            for hook in config.pre_switch.iter().chain(single.pre_switch.iter()) {
+                let env = switch_env.clone();
+                hooks::run(
+                    "pre-switch",
+                    &single.name,
+                    hook,
+                    env,
+                    hooks::PRE_SWITCH_TIMEOUT,
+                );
+            }
+        };
+        let applied = apply_config(&*conn, res, single, setup, root, true, pre_switch);
+        match applied {
+            Ok(changed) => {
+                if changed {
+                    self.stats.lock().unwrap().switches += 1;
+                    if let Some(conn) = self.dbus.get() {
+                        if let Err(e) = dbus::profile_changed(conn, &single.name) {
+                            warn!("Could not emit ProfileChanged: {}", e);
+                        }
+                    }
+                    for hook in config.post_switch.iter().chain(single.post_switch.iter()) {
+                        hooks::spawn("post-switch", &single.name, hook, switch_env.clone());
+                    }
+                    if let (Some(hook), Some(env)) = (&single.on_apply, hook_env) {
+                        hooks::spawn("on-apply", &single.name, hook, env);
+                    }
+                }
+                *self.status.lock().unwrap() = StatusInfo {
+                    profile: Some(single.name.clone()),
+                    monitors: monitors.to_vec(),
+                };
+                self.publish_profile(Some(&single.name));
+                if changed || force_print {
+                    println!("Monitor configuration: {}", single.name)
+                }
+                Ok(())
+            }
+            Err(e) => {
+                self.stats.lock().unwrap().failures += 1;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Called for each screen change notificaiton. Detects connected monitors and switches
+/// to the appropriate config.
+fn switch_setup(ctx: &DaemonContext, force_print: bool) {
+    let _applying = ctx.applying.lock().unwrap();
+    let res = {
+        let x = ctx.x();
+        get_outputs(&*x.conn, x.root)
+    };
+    let res = match res {
         Ok(o) => o,
         Err(e) => {
             error!("{:?}", e);
             return;
         }
     };
-    match get_config(&config, conn, &res.outputs, edid) {
-        Some((name, fb_size, setup)) => match apply_config(conn, &res, fb_size, setup, root) {
-            Ok(changed) => {
-                if changed || force_print {
-                    println!("Monitor configuration: {}", name)
+    let attached = match ctx.attached(&res.outputs) {
+        Ok(attached) => attached,
+        Err(e) => {
+            error!("{:?}", e);
+            return;
+        }
+    };
+    let config = ctx.config.read().unwrap();
+    // A layout applied by name holds until the attached monitors change, including through the
+    // events that applying it raises
+    {
+        let mut forced = ctx.forced.lock().unwrap();
+        if let Some(monitors) = forced.as_ref() {
+            let out_to_mon = attached
+                .iter()
+                .map(|(out, mon)| (*out, config.identify(mon.clone())))
+                .collect();
+            if *monitors == sorted_monitors(&out_to_mon) {
+                return;
+            }
+            *forced = None;
+        }
+    }
+    match match_config(&config, attached) {
+        Some((monitors, single, setup)) => {
+            if let Err(e) = ctx.apply(&config, &res, &monitors, single, setup, force_print) {
+                error!("{:?}", e)
+            }
+        }
+        None => {
+            error!(
+                "Error: Monitor change indicated, and the connected monitors did not match a config"
+            );
+            ctx.publish_profile(None);
+            // Monitors behind a GPU that shows no image are missing from the attached monitors
+            let unsourced = {
+                let x = ctx.x();
+                unsourced_providers(&*x.conn, x.root)
+            };
+            match unsourced {
+                Ok(hints) => {
+                    for hint in hints {
+                        warn!("Monitors may be missing, as {}", hint);
+                    }
                 }
+                Err(e) => warn!("Could not read the providers: {:?}", e),
             }
-            Err(e) => error!("{:?}", e),
-        },
-        None => error!(
-            "Error: Monitor change indicated, and the connected monitors did not match a config"
-        ),
+            if let Some(hook) = &config.on_no_match {
+                let env = no_match_env(ctx.status().profile);
+                hooks::spawn("on-no-match", "no layout", hook, env);
+            }
+        }
+    }
+}
+
+/// The timestamp of an event that may indicate a change in the attached monitors.
+pub(crate) fn change_timestamp(event: &Event) -> Option<Timestamp> {
+    match event {
+        Event::RandrScreenChangeNotify(e) => Some(e.timestamp),
+        Event::RandrNotify(e) if e.sub_code == Notify::OUTPUT_CHANGE => Some(e.u.as_oc().timestamp),
+        Event::RandrNotify(e) if e.sub_code == Notify::CRTC_CHANGE => Some(e.u.as_cc().timestamp),
+        _ => None,
+    }
+}
+
+/// Connect to the X server again after the connection to it was lost, as when the server
+/// restarts, waiting `delay` before each of up to `attempts` attempts. The new connection
+/// replaces the old one for the event loop and the IPC server alike.
+fn reconnect(
+    ctx: &DaemonContext,
+    delay: Duration,
+    attempts: u32,
+    shutdown: &AtomicBool,
+) -> Result<()> {
+    for attempt in 1..=attempts {
+        thread::sleep(delay);
+        if shutdown.load(AtomicOrdering::SeqCst) {
+            return Ok(());
+        }
+        match XServer::connect() {
+            Ok(x) => {
+                *ctx.x.write().unwrap() = x;
+                // The outputs of a new server may have different XIDs
+                ctx.edid_cache.lock().unwrap().invalidate();
+                info!("Reconnected to the X server");
+                return Ok(());
+            }
+            Err(e) => warn!(
+                "Could not reconnect to the X server (attempt {} of {}): {:?}",
+                attempt, attempts, e
+            ),
+        }
+    }
+    Err(Error::Reconnect(attempts)).into_diagnostic()
+}
+
+/// Wait until no change events have arrived for `debounce`, returning the timestamp of the
+/// last change event.
+fn settle(ctx: &DaemonContext, mut timestamp: Timestamp, debounce: Duration) -> Timestamp {
+    if debounce.is_zero() {
+        return timestamp;
+    }
+    let mut deadline = Instant::now() + debounce;
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        let event = ctx.x().conn.poll_for_event();
+        match event {
+            Ok(Some(event)) => {
+                ctx.observe(&event);
+                if let Some(next) = change_timestamp(&event) {
+                    ctx.stats.lock().unwrap().events += 1;
+                    timestamp = next;
+                    deadline = Instant::now() + debounce;
+                }
+            }
+            Ok(None) => thread::sleep(remaining.min(DEBOUNCE_POLL)),
+            Err(e) => {
+                error!("{:?}", e);
+                break;
+            }
+        }
     }
+    timestamp
 }
 
-fn setup_notify<C: Connection>(conn: &C, root: Window, mask: NotifyMask) -> Result<()> {
+/// The events the daemon selects: every change to the screen, its outputs and CRTCs, and output
+/// properties, which are only watched for EDID changes that empty the EDID cache.
+pub(crate) fn notify_mask() -> NotifyMask {
+    NotifyMask::SCREEN_CHANGE
+        | NotifyMask::OUTPUT_CHANGE
+        | NotifyMask::CRTC_CHANGE
+        | NotifyMask::OUTPUT_PROPERTY
+}
+
+pub(crate) fn setup_notify<C: Connection>(conn: &C, root: Window, mask: NotifyMask) -> Result<()> {
     conn.randr_select_input(root, mask)
         .into_diagnostic()?
         .check()
@@ -313,39 +1851,357 @@ fn setup_notify<C: Connection>(conn: &C, root: Window, mask: NotifyMask) -> Resu
 
 pub fn daemon(args: &ArgMatches<'_>) -> Result<()> {
     let config = check(args)?;
+    let debounce =
+        Duration::from_millis(value_t!(args, "debounce-ms", u64).unwrap_or_else(|e| e.exit()));
+    let poll = Duration::from_millis(value_t!(args, "poll", u64).unwrap_or_else(|e| e.exit()));
+    let reconnect_delay =
+        Duration::from_millis(value_t!(args, "reconnect-delay", u64).unwrap_or_else(|e| e.exit()));
+    let reconnect_attempts = value_t!(args, "reconnect-attempts", u32).unwrap_or_else(|e| e.exit());
+    // A dry run only plans the initial pass, exactly as `apply --no-act` does
+    if args.is_present("no-act") {
+        return super::apply::apply_layout(args, None, true);
+    }
     if !args.is_present("check") {
-        let (conn, screen_num) = ok_or_exit(connect(None), |e| {
-            eprintln!("Could not connect to X server: {}", e);
+        let x = ok_or_exit(XServer::connect(), |e| {
+            eprintln!("Could not connect to X server: {:?}", e);
             1
         });
-        let setup = conn.setup();
-        let atom_edid = ok_or_exit(edid_atom(&conn), |e| {
-            eprintln!("Failed to intern EDID atom: {}", e);
-            1
-        });
-        let root = setup.roots[screen_num].root;
-        let notify_mask =
-            NotifyMask::SCREEN_CHANGE | NotifyMask::OUTPUT_CHANGE | NotifyMask::CRTC_CHANGE;
-        ok_or_exit(setup_notify(&conn, root, notify_mask), |e| {
-            eprintln!("Could not enable notifications: {}", e);
-            1
+        // Created once nothing can exit the process without dropping it
+        let _pid_file = PidFile::create(
+            args.value_of("pid-file")
+                .map(PathBuf::from)
+                .unwrap_or_else(default_pid_path),
+        )?;
+        let ctx = Arc::new(DaemonContext {
+            config_names: config_names(args)?,
+            config: RwLock::new(config),
+            x: RwLock::new(x),
+            edid_cache: Mutex::default(),
+            status: Mutex::default(),
+            stats: Mutex::default(),
+            applying: Mutex::default(),
+            forced: Mutex::default(),
+            dbus: OnceLock::new(),
         });
-        switch_setup(&config, &conn, atom_edid, root, true);
-        loop {
-            match conn.wait_for_event() {
-                Ok(Event::RandrScreenChangeNotify(_)) => {
-                    switch_setup(&config, &conn, atom_edid, root, false)
+        let socket_path = ipc::socket_path();
+        if let Err(e) = ipc::serve(ctx.clone(), &socket_path) {
+            warn!("Could not listen on {}: {}", socket_path.display(), e);
+        }
+        match dbus::serve(ctx.clone()) {
+            Ok(conn) => {
+                let _ = ctx.dbus.set(conn);
+            }
+            Err(e) => warn!("Could not serve {} on the session bus: {}", dbus::NAME, e),
+        }
+        // Signals set their flag, and wake the event loop through the pipe
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let reload = Arc::new(AtomicBool::new(false));
+        let (mut wake_rx, wake_tx) = UnixStream::pair().into_diagnostic()?;
+        wake_rx.set_nonblocking(true).into_diagnostic()?;
+        for (signal, flag) in [(SIGTERM, &shutdown), (SIGINT, &shutdown), (SIGHUP, &reload)] {
+            flag::register(signal, flag.clone()).into_diagnostic()?;
+            pipe::register(signal, wake_tx.try_clone().into_diagnostic()?).into_diagnostic()?;
+        }
+        switch_setup(&ctx, true);
+        systemd::notify("READY=1");
+        let watchdog = systemd::watchdog_interval();
+        let mut next_ping = Instant::now();
+        let mut last_change = None;
+        // Without reliable events, the attached monitors are read on a timer, and a layout is
+        // only looked for when they differ from the last time they were read
+        let polling = !poll.is_zero();
+        let mut polled = if polling {
+            ctx.fingerprint().ok()
+        } else {
+            None
+        };
+        let mut next_poll = Instant::now() + poll;
+        while !shutdown.load(AtomicOrdering::SeqCst) {
+            if let Some(interval) = watchdog.filter(|_| Instant::now() >= next_ping) {
+                next_ping = Instant::now() + interval;
+                systemd::notify("WATCHDOG=1");
+            }
+            if polling && Instant::now() >= next_poll {
+                next_poll = Instant::now() + poll;
+                match ctx.fingerprint() {
+                    Ok(monitors) if polled.as_ref() != Some(&monitors) => {
+                        info!("The connected monitors changed without an event");
+                        polled = Some(monitors);
+                        ctx.edid_cache.lock().unwrap().invalidate();
+                        switch_setup(&ctx, false);
+                    }
+                    Ok(_) => (),
+                    Err(e) => error!("{:?}", e),
+                }
+            }
+            if reload.swap(false, AtomicOrdering::SeqCst) {
+                // The current configuration is kept when the new one has errors
+                systemd::notify("RELOADING=1");
+                if let Err(e) = ctx.reload() {
+                    error!("{:?}", e);
+                }
+                systemd::notify("READY=1");
+            }
+            let event = ctx.x().conn.poll_for_event();
+            let event = match event {
+                Ok(Some(event)) => event,
+                Err(e) => {
+                    error!("Lost the connection to the X server: {}", e);
+                    reconnect(&ctx, reconnect_delay, reconnect_attempts, &shutdown)?;
+                    // Whatever changed while the server was away has no events to show for it
+                    switch_setup(&ctx, true);
+                    last_change = None;
+                    if polling {
+                        polled = ctx.fingerprint().ok();
+                    }
+                    continue;
+                }
+                Ok(None) => {
+                    // Woken in time for the next poll and the next watchdog ping
+                    let timeout = [polling.then_some(next_poll), watchdog.map(|_| next_ping)]
+                        .iter()
+                        .flatten()
+                        .min()
+                        .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+                    let x_fd = ctx.x().conn.stream().as_raw_fd();
+                    wait_readable(&[x_fd, wake_rx.as_raw_fd()], timeout);
+                    // Empty the pipe, so that it only wakes the loop for new signals
+                    let _ = std::io::copy(&mut wake_rx, &mut std::io::sink());
+                    continue;
+                }
+            };
+            ctx.observe(&event);
+            if let Some(timestamp) = change_timestamp(&event) {
+                ctx.stats.lock().unwrap().events += 1;
+                let timestamp = settle(&ctx, timestamp, debounce);
+                // A single hotplug raises several events with the same timestamp
+                if last_change != Some(timestamp) {
+                    last_change = Some(timestamp);
+                    switch_setup(&ctx, false);
+                    if polling {
+                        polled = ctx.fingerprint().ok();
+                    }
                 }
-                _ => (),
             }
         }
+        info!("Shutting down");
+        systemd::notify("STOPPING=1");
+        ctx.publish_profile(None);
+        let x = ctx.x();
+        setup_notify(&*x.conn, x.root, NotifyMask::from(0u8))?;
     }
     Ok(())
 }
 
+/// Block until any of `fds` is readable, a signal arrives, or `timeout` passes.
+pub(crate) fn wait_readable(fds: &[RawFd], timeout: Option<Duration>) {
+    let mut pollfds: Vec<_> = fds
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // Rounded up, so that the timeout has passed once poll returns
+    let timeout = timeout.map_or(-1, |t| {
+        t.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32
+    });
+    // Safe, because the pointer and length describe a live, exclusively borrowed slice
+    let ret = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+    if ret < 0 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            error!("Could not wait for events: {}", e);
+        }
+    }
+}
+
+/// The configuration files given on the command line, from lowest to highest priority: the
+/// CONFIG argument, then each `--config` in order. Without any, the default configuration file.
+pub(crate) fn config_names(args: &ArgMatches<'_>) -> Result<Vec<String>> {
+    let names: Vec<_> = args
+        .value_of("config")
+        .into_iter()
+        .chain(args.values_of("config-file").into_iter().flatten())
+        .map(str::to_string)
+        .collect();
+    if !names.is_empty() {
+        return Ok(names);
+    }
+    Config::default_path()
+        .map(|path| vec![path.to_string_lossy().into_owned()])
+        .ok_or(ConfigError::NoDefaultPath)
+        .into_diagnostic()
+}
+
+/// The configuration file with the highest priority, which is the one that is written to.
+pub(crate) fn config_name(args: &ArgMatches<'_>) -> Result<String> {
+    // There is always at least one configuration file
+    Ok(config_names(args)?.pop().unwrap())
+}
+
 pub fn check(args: &ArgMatches<'_>) -> Result<Config> {
-    // Unwrap below is safe, because the program exits from `get_matches` above when a config
-    // is not provided.
-    let config_name = args.value_of("config").unwrap();
-    Config::from_fname(&config_name).into_diagnostic()
+    // Configuration errors are diagnostics of their own, which may point into the file
+    let config = Config::from_fnames(&config_names(args)?)?;
+    for (_, single) in config.iter() {
+        if let Some(fb_size) = &single.fb_size {
+            let computed = Config::computed_fb_size(single.monitor_configs());
+            // An fb-size smaller than the layout is rejected by the parser, and a larger one
+            // is only known to be larger when every size is known
+            let sizes_known = single
+                .monitor_configs()
+                .all(|m| !m.enabled || matches!(m.mode, ModeSpec::Exact(_)));
+            if sizes_known && *fb_size != computed {
+                eprintln!(
+                    "Warning: layout {} has fb-size {}, which is larger than the {} it needs",
+                    single.name, fb_size, computed
+                );
+            }
+        }
+        let regions = Config::regions(single.monitor_configs());
+        for (a, b, shared) in Region::overlaps(&regions) {
+            eprintln!(
+                "Warning: monitors {} and {} of layout {} overlap at {}",
+                a, b, single.name, shared
+            );
+        }
+        for name in unreachable_monitors(single.monitor_configs()) {
+            eprintln!(
+                "Warning: monitor {} of layout {} has no position and is not reachable from the origin monitor, so it is placed at 0,0",
+                name, single.name
+            );
+        }
+    }
+    if args.is_present("against-server") {
+        let problems = check_against_server(&config)?;
+        if problems > 0 {
+            return Err(Error::AgainstServer(problems)).into_diagnostic();
+        }
+    }
+    Ok(config)
+}
+
+/// Check the layouts whose monitors are all connected against the X server, as applying them
+/// would: that each monitor's mode is one its output can display, that there are enough CRTCs
+/// to show the monitors at once, and that the screen they need is within the server's limits.
+/// Each problem is printed on stderr, and the number of problems is returned.
+fn check_against_server(config: &Config) -> Result<usize> {
+    let (conn, screen_num) = RustConnection::connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    // Probed, so that every mode of the attached monitors is known
+    let (modes, timestamp) = mode_map(&conn, root, true)?;
+    let range = conn
+        .randr_get_screen_size_range(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    let out_to_mon: HashMap<_, _> = get_monitors(&conn, &res.outputs, atom_edid)
+        .map(|(out, mon)| (out, config.identify(mon)))
+        .collect();
+    let mut infos = HashMap::with_capacity(res.outputs.len());
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        infos.insert(out, info);
+    }
+    let mut problems = 0;
+    let mut report = |layout: &str, monitor: Option<&str>, e: &dyn Display| {
+        problems += 1;
+        match monitor {
+            Some(monitor) => eprintln!("Error: layout {}, monitor {}: {}", layout, monitor, e),
+            None => eprintln!("Error: layout {}: {}", layout, e),
+        }
+    };
+    for (matches, single) in config.iter() {
+        if !matches.iter().all(|m| out_to_mon.values().any(|c| c == m)) {
+            continue;
+        }
+        let setup = outputs_in(matches, single, &out_to_mon);
+        // In the order apply_config plans them: by output, with sources before their clones
+        let mut shown: Vec<_> = res
+            .outputs
+            .iter()
+            .filter_map(|out| setup.get(out).filter(|c| c.enabled).map(|&c| (*out, c)))
+            .collect();
+        shown.sort_by_key(|(_, c)| c.clone_of.is_some());
+        let mut sizes = HashMap::with_capacity(shown.len());
+        for &(out, conf) in shown.iter() {
+            let source = conf
+                .clone_of
+                .as_ref()
+                .and_then(|src| shown.iter().find(|(_, c)| &c.name == src));
+            let size = match (source, &conf.modeline) {
+                (Some(&(src_out, src_conf)), _) => {
+                    clone_modes(&infos[&src_out], src_conf, &infos[&out], conf, &modes)
+                        .map(|(_, _, size)| size)
+                }
+                // The mode is created when the layout is applied
+                (None, Some(modeline)) => Ok(modeline.size()),
+                (None, None) => resolve_mode(&infos[&out], &modes, conf).map(|(_, size)| size),
+            };
+            match size {
+                Ok(size) => {
+                    sizes.insert(conf.name.as_str(), conf.scaled(&size));
+                }
+                Err(e) => report(&single.name, Some(&conf.name), &e),
+            }
+        }
+        // CRTCs of outputs that are turned off, or left as they are, are not handed out
+        let mut free: HashSet<_> = res.crtcs.iter().collect();
+        let keeps_extras = single.allow_extra_monitors && !single.disable_extra_monitors;
+        for (out, info) in infos.iter() {
+            let kept = match setup.get(out) {
+                Some(conf) => !conf.enabled,
+                None => keeps_extras && out_to_mon.contains_key(out),
+            };
+            if kept {
+                free.remove(&info.crtc);
+            }
+        }
+        for &(out, conf) in shown.iter().filter(|(_, c)| c.clone_of.is_none()) {
+            if allocate_crtc(&infos[&out], &mut free).is_none() {
+                report(
+                    &single.name,
+                    Some(&conf.name),
+                    &Error::NoCrtc(conf.name.clone()),
+                );
+            }
+        }
+        // The screen's size is only known once every mode is
+        if sizes.len() < shown.len() {
+            continue;
+        }
+        let positions = match resolve_positions(shown.iter().map(|(_, c)| *c), |m| {
+            sizes.get(m.name.as_str()).cloned()
+        }) {
+            Ok(positions) => positions,
+            Err(e) => {
+                report(&single.name, None, &e);
+                continue;
+            }
+        };
+        let mut fb_size = single.fb_size.clone().unwrap_or(Mode { w: 0, h: 0 });
+        for (_, conf) in shown.iter() {
+            let position = positions[conf.clone_of.as_ref().unwrap_or(&conf.name)];
+            fb_size = fb_size.union(&conf.extent(position, &sizes[conf.name.as_str()]));
+        }
+        if fb_size.w < range.min_width
+            || fb_size.h < range.min_height
+            || fb_size.w > range.max_width
+            || fb_size.h > range.max_height
+        {
+            let e = format!(
+                "needs a screen of {}, outside of the X server's range of {}x{} to {}x{}",
+                fb_size, range.min_width, range.min_height, range.max_width, range.max_height
+            );
+            report(&single.name, None, &e);
+        }
+    }
+    Ok(problems)
 }
@@ -0,0 +1,242 @@
+use clap::ArgMatches;
+use edid::{Descriptor, DetailedTiming, EDID};
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt},
+};
+
+use crate::{config::Monitor, edid_atom, get_edid_bytes, get_outputs, parse_edid};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No connected output named {0}")]
+    UnknownOutput(String),
+}
+
+/// The established timings, in the order of the bits of EDID bytes 35 to 37, from the most
+/// significant bit of byte 35
+const ESTABLISHED_TIMINGS: [&str; 17] = [
+    "720x400@70",
+    "720x400@88",
+    "640x480@60",
+    "640x480@67",
+    "640x480@72",
+    "640x480@75",
+    "800x600@56",
+    "800x600@60",
+    "800x600@72",
+    "800x600@75",
+    "832x624@75",
+    "1024x768@87 interlaced",
+    "1024x768@60",
+    "1024x768@70",
+    "1024x768@75",
+    "1280x1024@75",
+    "1152x870@75",
+];
+
+/// Where the established timing bits start in an EDID
+const ESTABLISHED_OFFSET: usize = 35;
+
+/// Where the eight two-byte standard timings start in an EDID
+const STANDARD_OFFSET: usize = 38;
+
+/// The established timings that an EDID supports, which the edid crate does not parse
+fn established_timings(bytes: &[u8]) -> Vec<&'static str> {
+    ESTABLISHED_TIMINGS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            bytes
+                .get(ESTABLISHED_OFFSET + i / 8)
+                .is_some_and(|byte| byte & (0x80 >> (i % 8)) != 0)
+        })
+        .map(|(_, timing)| *timing)
+        .collect()
+}
+
+/// The standard timings that an EDID supports, which the edid crate does not parse. Each is a
+/// width and an aspect ratio, from which the height follows, and a refresh rate.
+fn standard_timings(bytes: &[u8], version: (u8, u8)) -> Vec<String> {
+    let timings = match bytes.get(STANDARD_OFFSET..STANDARD_OFFSET + 16) {
+        Some(timings) => timings,
+        None => return Vec::new(),
+    };
+    timings
+        .chunks(2)
+        // Unused entries are 0x01 0x01, though some monitors leave them as zeros or spaces
+        .filter(|t| !matches!(t, [0x01, 0x01] | [0x00, 0x00] | [0x20, 0x20]))
+        .map(|t| {
+            let w = (t[0] as u32 + 31) * 8;
+            let h = match t[1] >> 6 {
+                // Before EDID 1.3, this meant 1:1 rather than 16:10
+                0 if version < (1, 3) => w,
+                0 => w * 10 / 16,
+                1 => w * 3 / 4,
+                2 => w * 4 / 5,
+                _ => w * 9 / 16,
+            };
+            format!("{}x{}@{}", w, h, (t[1] & 0x3f) as u32 + 60)
+        })
+        .collect()
+}
+
+fn detailed_timing(timing: &DetailedTiming) -> String {
+    let h_total = (timing.horizontal_active_pixels + timing.horizontal_blanking_pixels) as f64;
+    let v_total = (timing.vertical_active_lines + timing.vertical_blanking_lines) as f64;
+    let rate = if h_total * v_total > 0.0 {
+        timing.pixel_clock as f64 * 1000.0 / (h_total * v_total)
+    } else {
+        0.0
+    };
+    format!(
+        "{}x{}@{:.2}, pixel clock {:.2} MHz, {}x{}mm",
+        timing.horizontal_active_pixels,
+        timing.vertical_active_lines,
+        rate,
+        timing.pixel_clock as f64 / 1000.0,
+        timing.horizontal_size,
+        timing.vertical_size
+    )
+}
+
+/// Describe a descriptor other than a detailed timing, the product name or the serial number
+fn other_descriptor(desc: &Descriptor) -> Option<String> {
+    Some(match desc {
+        Descriptor::UnspecifiedText(text) => format!("text {:?}", text),
+        Descriptor::RangeLimits => "range limits".to_string(),
+        Descriptor::WhitePoint => "white point".to_string(),
+        Descriptor::StandardTiming => "more standard timings".to_string(),
+        Descriptor::ColorManagement => "color management".to_string(),
+        Descriptor::TimingCodes => "CVT timing codes".to_string(),
+        Descriptor::EstablishedTimings => "more established timings".to_string(),
+        Descriptor::Unknown(_) => "unknown".to_string(),
+        Descriptor::DetailedTiming(_)
+        | Descriptor::ProductName(_)
+        | Descriptor::SerialNumber(_)
+        | Descriptor::Dummy => return None,
+    })
+}
+
+/// Explain which fields of an EDID make up the monitor it is matched as, following
+/// `Monitor::from`.
+fn matched_from(edid: &EDID) -> Vec<&'static str> {
+    let has = |f: fn(&Descriptor) -> bool| edid.descriptors.iter().any(f);
+    let mut sources = Vec::new();
+    if has(|d| matches!(d, Descriptor::ProductName(_))) {
+        sources.push("product from the product name descriptor");
+    } else {
+        sources.push("no product, as there is no product name descriptor");
+    }
+    if has(|d| matches!(d, Descriptor::SerialNumber(_))) {
+        sources.push("serial from the serial number descriptor");
+    } else if edid.header.serial != 0 {
+        sources.push("serial from the serial number of the header, as there is no descriptor");
+    } else {
+        sources.push("no serial, as there is no descriptor and the header's serial number is 0");
+    }
+    sources
+}
+
+fn print_edid(bytes: &[u8]) {
+    let edid = match parse_edid(bytes) {
+        Some(edid) => edid,
+        None => return println!("    does not parse as an EDID"),
+    };
+    let header = &edid.header;
+    let version = (header.version, header.revision);
+    println!(
+        "    manufacturer: {}",
+        header.vendor.iter().collect::<String>()
+    );
+    println!("    product code: 0x{:04x}", header.product);
+    println!("    serial number: {}", header.serial);
+    let year = 1990 + header.year as u32;
+    match header.week {
+        0 => println!("    manufactured: {}", year),
+        0xff => println!("    model year: {}", year),
+        week => println!("    manufactured: week {} of {}", week, year),
+    }
+    println!("    EDID version: {}.{}", version.0, version.1);
+    match (edid.display.width, edid.display.height) {
+        (0, 0) => println!("    physical size: unknown"),
+        (w, h) => println!("    physical size: {}x{}cm", w, h),
+    }
+    for desc in edid.descriptors.iter() {
+        match desc {
+            Descriptor::ProductName(name) => println!("    product name: {:?}", name),
+            Descriptor::SerialNumber(serial) => println!("    serial number text: {:?}", serial),
+            _ => (),
+        }
+    }
+    let established = established_timings(bytes);
+    if !established.is_empty() {
+        println!("    established timings: {}", established.join(", "));
+    }
+    let standard = standard_timings(bytes, version);
+    if !standard.is_empty() {
+        println!("    standard timings: {}", standard.join(", "));
+    }
+    for desc in edid.descriptors.iter() {
+        if let Descriptor::DetailedTiming(timing) = desc {
+            println!("    detailed timing: {}", detailed_timing(timing));
+        }
+    }
+    let others: Vec<_> = edid
+        .descriptors
+        .iter()
+        .filter_map(other_descriptor)
+        .collect();
+    if !others.is_empty() {
+        println!("    other descriptors: {}", others.join(", "));
+    }
+    let sources = matched_from(&edid);
+    println!("    matched as: {}", Monitor::from(edid));
+    for source in sources {
+        println!("        {}", source);
+    }
+}
+
+/// Decode the EDID of the named output, or of every connected output, or of a file of raw EDID
+/// bytes, and print its fields, ending with the monitor that it is matched as and the fields
+/// that monitor comes from.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    if let Some(file) = args.value_of("file") {
+        let bytes = std::fs::read(file).into_diagnostic()?;
+        println!("{}", file);
+        print_edid(&bytes);
+        return Ok(());
+    }
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let wanted = args.value_of("output-name");
+    let mut found = false;
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let name = String::from_utf8_lossy(&info.name).into_owned();
+        if info.connection != RandrConnection::CONNECTED || wanted.is_some_and(|w| w != name) {
+            continue;
+        }
+        found = true;
+        println!("{}", name);
+        let bytes = get_edid_bytes(&conn, atom_edid, out).into_diagnostic()?;
+        if bytes.is_empty() {
+            println!("    no EDID");
+        } else {
+            print_edid(&bytes);
+        }
+    }
+    match wanted {
+        Some(wanted) if !found => Err(Error::UnknownOutput(wanted.to_string())).into_diagnostic(),
+        _ => Ok(()),
+    }
+}
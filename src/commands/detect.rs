@@ -0,0 +1,145 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt},
+};
+
+use std::collections::HashMap;
+
+use crate::{
+    config::{Mode, Monitor, Position, Region},
+    edid_atom, get_edid, get_outputs, refresh_rate, Error,
+};
+
+use super::diagram::diagram;
+
+/// A connected output and the monitor attached to it
+#[derive(Serialize)]
+struct Detected {
+    output: String,
+    /// None when the monitor has no parseable EDID
+    manufacturer: Option<String>,
+    monitor: Option<Monitor>,
+    /// None when the output is not displaying anything
+    mode: Option<Mode>,
+    rate: Option<f64>,
+    position: Option<Position>,
+}
+
+fn print_text(detected: &[Detected]) {
+    for d in detected {
+        println!("{}", d.output);
+        match (&d.manufacturer, &d.monitor) {
+            (Some(manufacturer), Some(mon)) => {
+                println!("    manufacturer: {}", manufacturer);
+                if let Some(product) = &mon.product {
+                    println!("    product: {}", product);
+                }
+                if let Some(serial) = &mon.serial {
+                    println!("    serial: {}", serial);
+                }
+                println!("    monitor: {:?}", mon);
+            }
+            _ => println!("    no EDID"),
+        }
+        match (&d.mode, &d.position) {
+            (Some(mode), Some(position)) => {
+                match d.rate {
+                    Some(rate) => println!("    mode: {}@{:.2}", mode, rate),
+                    None => println!("    mode: {}", mode),
+                }
+                println!("    position: {},{}", position.x, position.y);
+            }
+            _ => println!("    mode: off"),
+        }
+    }
+}
+
+/// Print the connected monitors, their identifiers and their current mode and position.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let rates: HashMap<_, _> = res
+        .modes
+        .iter()
+        .map(|mi| (mi.id, refresh_rate(mi)))
+        .collect();
+    let mut detected = Vec::new();
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if info.connection != RandrConnection::CONNECTED {
+            continue;
+        }
+        // Monitors with a broken EDID are shown as if they had none
+        let edid = match get_edid(&conn, atom_edid, out) {
+            Err(Error::EdidParse(_)) => None,
+            edid => edid.into_diagnostic()?,
+        };
+        let manufacturer = edid
+            .as_ref()
+            .map(|edid| edid.header.vendor.iter().collect::<String>());
+        let monitor = edid.map(Monitor::from);
+        let crtc = match info.crtc {
+            0 => None,
+            crtc => Some(
+                conn.randr_get_crtc_info(crtc, res.config_timestamp)
+                    .into_diagnostic()?
+                    .reply()
+                    .into_diagnostic()?,
+            ),
+        };
+        let (mode, rate, position) = match crtc {
+            Some(crtc) if crtc.mode != 0 => (
+                Some(Mode {
+                    w: crtc.width,
+                    h: crtc.height,
+                }),
+                rates.get(&crtc.mode).copied(),
+                Some(Position {
+                    x: crtc.x,
+                    y: crtc.y,
+                }),
+            ),
+            _ => (None, None, None),
+        };
+        detected.push(Detected {
+            output: String::from_utf8_lossy(&info.name).into_owned(),
+            manufacturer,
+            monitor,
+            mode,
+            rate,
+            position,
+        });
+    }
+    if args.is_present("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&detected).into_diagnostic()?
+        );
+    } else {
+        print_text(&detected);
+    }
+    if args.is_present("diagram") {
+        let regions: Vec<_> = detected
+            .iter()
+            .filter_map(|d| {
+                let region = Region {
+                    position: d.position?,
+                    size: d.mode.clone()?,
+                };
+                Some((vec![d.output.clone(), region.size.to_string()], region))
+            })
+            .collect();
+        print!("{}", diagram(&regions));
+    }
+    Ok(())
+}
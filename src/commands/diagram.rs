@@ -0,0 +1,100 @@
+use crate::config::Region;
+
+/// The width of the terminal that a diagram fits in
+const COLUMNS: usize = 80;
+
+/// Characters are about twice as tall as they are wide, so a row covers twice the pixels of a
+/// column
+const ROW_ASPECT: f64 = 2.0;
+
+/// The (corner, horizontal, vertical) border characters, given to monitors in turn so that
+/// monitors sharing a region, such as clones, stay distinguishable
+const BORDERS: [(char, char, char); 4] = [
+    ('+', '-', '|'),
+    ('#', '=', '!'),
+    ('*', '~', ':'),
+    ('o', '.', '\''),
+];
+
+/// Draw each labelled region as a rectangle, scaled so that the whole diagram fits in an
+/// 80-column terminal. Each label is a list of lines, written inside its rectangle as far as
+/// they fit. Regions are drawn in order, so later regions are drawn over earlier ones.
+pub(crate) fn diagram(regions: &[(Vec<String>, Region)]) -> String {
+    let left = regions.iter().map(|(_, r)| r.position.x as i64).min();
+    let top = regions.iter().map(|(_, r)| r.position.y as i64).min();
+    let (left, top) = match (left, top) {
+        (Some(left), Some(top)) => (left, top),
+        _ => return String::new(),
+    };
+    let right = regions
+        .iter()
+        .map(|(_, r)| r.position.x as i64 + r.size.w as i64)
+        .max()
+        .unwrap_or(left);
+    let bottom = regions
+        .iter()
+        .map(|(_, r)| r.position.y as i64 + r.size.h as i64)
+        .max()
+        .unwrap_or(top);
+    // The right and bottom edges of the widest monitor take the last column and row
+    let per_column = ((right - left).max(1) as f64 / (COLUMNS - 1) as f64).max(1.0);
+    let per_row = per_column * ROW_ASPECT;
+    let column = |x: i64| ((x - left) as f64 / per_column).round() as usize;
+    let row = |y: i64| ((y - top) as f64 / per_row).round() as usize;
+    let mut grid = vec![vec![' '; column(right) + 1]; row(bottom) + 1];
+
+    let mut drawn: Vec<(usize, usize, usize)> = Vec::new();
+    for (i, (label, region)) in regions.iter().enumerate() {
+        let (corner, horizontal, vertical) = BORDERS[i % BORDERS.len()];
+        let x0 = column(region.position.x as i64);
+        let y0 = row(region.position.y as i64);
+        // Every rectangle is at least large enough to show its corners apart
+        let x1 = column(region.position.x as i64 + region.size.w as i64).max(x0 + 1);
+        let y1 = row(region.position.y as i64 + region.size.h as i64).max(y0 + 1);
+        if grid.len() <= y1 {
+            grid.resize(y1 + 1, Vec::new());
+        }
+        for line in grid.iter_mut() {
+            if line.len() <= x1 {
+                line.resize(x1 + 1, ' ');
+            }
+        }
+        for y in [y0, y1] {
+            for c in grid[y].iter_mut().take(x1).skip(x0 + 1) {
+                *c = horizontal;
+            }
+        }
+        for line in grid.iter_mut().take(y1).skip(y0 + 1) {
+            line[x0] = vertical;
+            line[x1] = vertical;
+        }
+        for (x, y) in [(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+            grid[y][x] = corner;
+        }
+        // The labels of regions drawn at the same place follow one another
+        let below = drawn
+            .iter()
+            .filter(|&&(x, y, _)| (x, y) == (x0, y0))
+            .map(|&(_, _, lines)| lines)
+            .sum::<usize>();
+        let width = x1 - x0 - 1;
+        for (n, text) in label.iter().enumerate() {
+            let y = y0 + 1 + below + n;
+            if y >= y1 {
+                break;
+            }
+            for (x, c) in text.chars().take(width).enumerate() {
+                grid[y][x0 + 1 + x] = c;
+            }
+        }
+        drawn.push((x0, y0, label.len()));
+    }
+
+    let mut out = String::new();
+    for line in grid {
+        let line: String = line.into_iter().collect();
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
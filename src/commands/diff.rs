@@ -0,0 +1,65 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{connect, connection::Connection, protocol::xproto::ConnectionExt as XprotoExt};
+
+use std::collections::HashMap;
+
+use crate::config::Mode;
+
+use super::{
+    check,
+    status::{current_state, differences, Difference},
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("The connected monitors did not match a config")]
+    NoMatch,
+}
+
+/// Print each way in which the monitors differ from the layout that matches them, exiting with
+/// status 1 when they differ at all. The screen is compared only with a layout's fb-size, as
+/// without one it is sized to fit the monitors.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let state: HashMap<_, _> = current_state(&conn, root)?
+        .into_iter()
+        .map(|(mon, output)| (config.identify(mon), output))
+        .collect();
+    let mut connected: Vec<_> = state.keys().cloned().collect();
+    connected.sort();
+    let (matches, single) = match config.lookup(&connected) {
+        Some((matches, single)) => (matches.as_slice(), single),
+        None => match &config.fallback {
+            Some(single) => (&[][..], single),
+            None => return Err(Error::NoMatch).into_diagnostic(),
+        },
+    };
+    let mut differences = differences(matches, single, &state);
+    if let Some(fb_size) = &single.fb_size {
+        let geom = conn
+            .get_geometry(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let screen = Mode {
+            w: geom.width,
+            h: geom.height,
+        };
+        if screen != *fb_size {
+            differences.push(Difference::Screen(fb_size.clone(), screen));
+        }
+    }
+    if differences.is_empty() {
+        println!("The monitors are displaying layout {}", single.name);
+        return Ok(());
+    }
+    println!("The monitors differ from layout {}:", single.name);
+    for difference in differences {
+        println!("    {}", difference);
+    }
+    std::process::exit(1);
+}
@@ -0,0 +1,258 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use x11rb::{
+    connection::Connection,
+    protocol::{
+        randr::{Connection as RandrConnection, ConnectionExt as RandrExt},
+        xproto::ConnectionExt as XprotoExt,
+    },
+    rust_connection::RustConnection,
+};
+
+use std::{
+    fmt::{Display, Formatter},
+    path::Path,
+};
+
+use crate::{config::Config, get_edid_bytes, get_outputs};
+
+use super::config_names;
+
+/// The oldest RandR that monitor-layout works with, which introduced panning and reading the
+/// screen resources without probing the outputs
+const RANDR_MIN: (u32, u32) = (1, 3);
+
+/// The result of a check, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verdict {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl Display for Verdict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Verdict::Pass => write!(f, "pass"),
+            Verdict::Warn => write!(f, "warn"),
+            Verdict::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// Prints the result of each check, remembering the worst
+#[derive(Default)]
+struct Report {
+    worst: Option<Verdict>,
+}
+
+impl Report {
+    fn check(&mut self, verdict: Verdict, explanation: impl Display) {
+        println!("{}: {}", verdict, explanation);
+        self.worst = self.worst.max(Some(verdict));
+    }
+
+    /// The exit status: 0 when every check passed, 1 with warnings and 2 with failures
+    fn status(&self) -> i32 {
+        match self.worst {
+            None | Some(Verdict::Pass) => 0,
+            Some(Verdict::Warn) => 1,
+            Some(Verdict::Fail) => 2,
+        }
+    }
+}
+
+/// Check that each configuration file is found and parses.
+fn check_configs(args: &ArgMatches<'_>, report: &mut Report) {
+    match config_names(args) {
+        Ok(config_names) => {
+            for config_name in config_names {
+                check_config(&config_name, report);
+            }
+        }
+        Err(e) => report.check(Verdict::Fail, e),
+    }
+}
+
+fn check_config(config_name: &str, report: &mut Report) {
+    if !Path::new(config_name).exists() {
+        return report.check(
+            Verdict::Warn,
+            format_args!(
+                "the configuration {} does not exist; see monitor-layout generate",
+                config_name
+            ),
+        );
+    }
+    match Config::from_fname(config_name) {
+        Ok(config) => {
+            let layouts = config.iter().count();
+            report.check(
+                Verdict::Pass,
+                format_args!(
+                    "the configuration {} parses, with {} layout{}",
+                    config_name,
+                    layouts,
+                    if layouts == 1 { "" } else { "s" }
+                ),
+            )
+        }
+        Err(e) => report.check(
+            Verdict::Fail,
+            format_args!(
+                "the configuration {} has errors: {}; see monitor-layout check",
+                config_name, e
+            ),
+        ),
+    }
+}
+
+/// Check the X server: its RandR version, the EDIDs of the connected outputs, and whether it can
+/// show every connected output at once.
+fn check_server(report: &mut Report) -> Result<()> {
+    let (conn, screen_num) = match RustConnection::connect(None) {
+        Ok(connected) => connected,
+        Err(e) => {
+            report.check(
+                Verdict::Fail,
+                format_args!("could not connect to the X server: {}", e),
+            );
+            return Ok(());
+        }
+    };
+    let root = conn.setup().roots[screen_num].root;
+    let version = conn
+        .randr_query_version(RANDR_MIN.0, RANDR_MIN.1)
+        .map_err(|e| e.to_string())
+        .and_then(|cookie| cookie.reply().map_err(|e| e.to_string()));
+    match version {
+        Ok(v) if (v.major_version, v.minor_version) >= RANDR_MIN => report.check(
+            Verdict::Pass,
+            format_args!("RandR {}.{}", v.major_version, v.minor_version),
+        ),
+        Ok(v) => {
+            report.check(
+                Verdict::Fail,
+                format_args!(
+                    "RandR {}.{} is older than {}.{}, which monitor-layout needs",
+                    v.major_version, v.minor_version, RANDR_MIN.0, RANDR_MIN.1
+                ),
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            report.check(
+                Verdict::Fail,
+                format_args!("the X server does not support RandR: {}", e),
+            );
+            return Ok(());
+        }
+    }
+
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let mut connected = Vec::with_capacity(res.outputs.len());
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if info.connection == RandrConnection::CONNECTED {
+            connected.push((out, info));
+        }
+    }
+
+    // An atom that was never interned means that no driver has published an EDID
+    let atom_edid = conn
+        .intern_atom(true, b"EDID")
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .atom;
+    if atom_edid == x11rb::NONE {
+        report.check(
+            Verdict::Fail,
+            "the X server has no EDID property, so no monitor can be matched",
+        );
+    } else {
+        report.check(Verdict::Pass, "the X server has an EDID property");
+        for (out, info) in connected.iter() {
+            let name = String::from_utf8_lossy(&info.name);
+            match get_edid_bytes(&conn, atom_edid, *out) {
+                Ok(edid) if !edid.is_empty() => {
+                    report.check(Verdict::Pass, format_args!("output {} has an EDID", name))
+                }
+                Ok(_) => report.check(
+                    Verdict::Warn,
+                    format_args!(
+                        "output {} has no EDID, so its monitor cannot be matched",
+                        name
+                    ),
+                ),
+                Err(e) => report.check(
+                    Verdict::Warn,
+                    format_args!("the EDID of output {} cannot be read: {}", name, e),
+                ),
+            }
+        }
+    }
+
+    let crtcs = res.crtcs.len();
+    let verdict = if connected.len() > crtcs {
+        Verdict::Warn
+    } else {
+        Verdict::Pass
+    };
+    report.check(
+        verdict,
+        format_args!(
+            "{} CRTCs for {} connected outputs{}",
+            crtcs,
+            connected.len(),
+            if verdict == Verdict::Pass {
+                ""
+            } else {
+                ", so they cannot all be on at once"
+            }
+        ),
+    );
+
+    let range = conn
+        .randr_get_screen_size_range(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    // The connected outputs side by side in their preferred modes, as reset shows them
+    let preferred = connected.iter().filter_map(|(_, info)| {
+        let id = info.modes.iter().take(info.num_preferred as usize).next()?;
+        res.modes.iter().find(|mi| mi.id == *id)
+    });
+    let (width, height) = preferred.fold((0u32, 0u16), |(w, h), mi| {
+        (w + mi.width as u32, h.max(mi.height))
+    });
+    let range_text = format!(
+        "the screen may be from {}x{} to {}x{}",
+        range.min_width, range.min_height, range.max_width, range.max_height
+    );
+    if width > range.max_width as u32 || height > range.max_height {
+        report.check(
+            Verdict::Warn,
+            format_args!(
+                "{}, which is too small to show the connected outputs side by side at {}x{}",
+                range_text, width, height
+            ),
+        );
+    } else {
+        report.check(Verdict::Pass, range_text);
+    }
+    Ok(())
+}
+
+/// Check the environment that monitor-layout runs in, printing whether each check passed, and
+/// exiting with the status of the worst result.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let mut report = Report::default();
+    check_configs(args, &mut report);
+    check_server(&mut report)?;
+    std::process::exit(report.status());
+}
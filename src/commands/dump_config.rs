@@ -0,0 +1,213 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+
+use crate::config::{
+    Config, Dpms, Gamma, Mode, Modeline, Monitor, Panning, Position, PropertyValue, Relative,
+    Scale, SingleConfig, WILDCARD,
+};
+
+use super::check;
+
+/// A monitor of a layout, as the parser understood it
+#[derive(Serialize)]
+struct DumpedMonitor<'a> {
+    alias: &'a str,
+    mode: String,
+    rate: Option<f64>,
+    /// The absolute position, resolved from any relative position when the sizes involved are
+    /// known from the configuration
+    position: Position,
+    relative: Option<String>,
+    clone_of: Option<&'a str>,
+    scale: Option<Scale>,
+    gamma: Option<Gamma>,
+    brightness: Option<f64>,
+    panning: Option<&'a Panning>,
+    modeline: Option<String>,
+    properties: &'a BTreeMap<String, PropertyValue>,
+    primary: bool,
+    enabled: bool,
+}
+
+/// A layout, as the parser understood it
+#[derive(Serialize)]
+struct DumpedLayout<'a> {
+    name: &'a str,
+    /// The sorted monitors that key the layout
+    matches: &'a [Monitor],
+    wildcard: bool,
+    fallback: bool,
+    allow_extra_monitors: bool,
+    disable_extra_monitors: bool,
+    priority: i64,
+    dpi: Option<f64>,
+    dpms: Option<Dpms>,
+    /// The configured screen size, or else the computed one
+    fb_size: Mode,
+    fb_size_computed: bool,
+    monitors: Vec<DumpedMonitor<'a>>,
+}
+
+fn dump_layout<'a>(
+    matches: &'a [Monitor],
+    single: &'a SingleConfig,
+    wildcard: bool,
+    fallback: bool,
+) -> DumpedLayout<'a> {
+    let mut monitors: Vec<_> = single
+        .monitor_configs()
+        .map(|conf| DumpedMonitor {
+            alias: &conf.name,
+            mode: conf.mode.to_string(),
+            rate: conf.rate,
+            position: conf.position,
+            relative: conf.relative.as_ref().map(Relative::to_string),
+            clone_of: conf.clone_of.as_deref(),
+            scale: conf.scale,
+            gamma: conf.gamma,
+            brightness: conf.brightness,
+            panning: conf.panning.as_ref(),
+            modeline: conf.modeline.as_ref().map(Modeline::to_string),
+            properties: &conf.properties,
+            primary: conf.primary,
+            enabled: conf.enabled,
+        })
+        .collect();
+    monitors.sort_by(|a, b| a.alias.cmp(b.alias));
+    let (fb_size, fb_size_computed) = match &single.fb_size {
+        Some(fb_size) => (fb_size.clone(), false),
+        None => (Config::computed_fb_size(single.monitor_configs()), true),
+    };
+    DumpedLayout {
+        name: &single.name,
+        matches,
+        wildcard,
+        fallback,
+        allow_extra_monitors: single.allow_extra_monitors,
+        disable_extra_monitors: single.disable_extra_monitors,
+        priority: single.priority,
+        dpi: single.dpi,
+        dpms: single.dpms,
+        fb_size,
+        fb_size_computed,
+        monitors,
+    }
+}
+
+fn print_text(layouts: &[DumpedLayout<'_>]) {
+    for layout in layouts {
+        println!("layout {:?}", layout.name);
+        if layout.fallback {
+            println!("    fallback");
+        }
+        if layout.disable_extra_monitors {
+            println!("    disable-extra-monitors");
+        } else if layout.allow_extra_monitors {
+            println!("    allow-extra-monitors");
+        }
+        if layout.priority != 0 {
+            println!("    priority {}", layout.priority);
+        }
+        for monitor in layout.matches {
+            println!("    matches {}", monitor);
+        }
+        if layout.wildcard {
+            println!("    matches {}", WILDCARD);
+        }
+        let computed = if layout.fb_size_computed {
+            " (computed)"
+        } else {
+            ""
+        };
+        println!("    fb-size {}{}", layout.fb_size, computed);
+        if let Some(dpi) = layout.dpi {
+            println!("    dpi {}", dpi);
+        }
+        if let Some(dpms) = &layout.dpms {
+            println!("    dpms {}", dpms);
+        }
+        for m in layout.monitors.iter() {
+            print!("    monitor {:?}", m.alias);
+            if !m.enabled {
+                println!(" off");
+                continue;
+            }
+            print!(" mode={}", m.mode);
+            if let Some(rate) = m.rate {
+                print!(" rate={}", rate);
+            }
+            print!(" position={},{}", m.position.x, m.position.y);
+            if let Some(relative) = &m.relative {
+                print!(" ({})", relative);
+            }
+            if let Some(clone_of) = m.clone_of {
+                print!(" clone-of={:?}", clone_of);
+            }
+            if let Some(scale) = m.scale {
+                print!(" scale={}", scale);
+            }
+            if let Some(gamma) = m.gamma {
+                print!(" gamma={}", gamma);
+            }
+            if let Some(brightness) = m.brightness {
+                print!(" brightness={}", brightness);
+            }
+            if let Some(panning) = m.panning {
+                let Position { x, y } = panning.origin(m.position);
+                print!(" panning={}+{}+{}", panning.size, x, y);
+                if let Some(tracking) = &panning.tracking {
+                    print!("/{}", tracking);
+                }
+            }
+            if let Some(modeline) = &m.modeline {
+                print!(" modeline={:?}", modeline);
+            }
+            for (property, value) in m.properties.iter() {
+                print!(" property {:?}={:?}", property, value.to_string());
+            }
+            if m.primary {
+                print!(" primary");
+            }
+            println!();
+        }
+    }
+}
+
+/// Print the configuration as the parser understood it.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    if args.is_present("kdl") {
+        print!("{}", config.to_kdl());
+        return Ok(());
+    }
+    let mut layouts: Vec<_> = config
+        .layouts
+        .iter()
+        .map(|(matches, single)| dump_layout(matches, single, false, false))
+        .chain(
+            config
+                .wildcard_layouts
+                .iter()
+                .map(|(matches, single)| dump_layout(matches, single, true, false)),
+        )
+        .chain(
+            config
+                .fallback
+                .iter()
+                .map(|single| dump_layout(&[], single, false, true)),
+        )
+        .collect();
+    layouts.sort_by(|a, b| a.name.cmp(b.name));
+    if args.is_present("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&layouts).into_diagnostic()?
+        );
+    } else {
+        print_text(&layouts);
+    }
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt, Output},
+};
+
+use std::collections::HashMap;
+
+use crate::{
+    config::{ModeSpec, MonConfig, Position, SingleConfig},
+    edid_atom, get_monitors, get_outputs,
+};
+
+use super::{
+    check,
+    daemon::{get_config, outputs_in},
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("The connected monitors did not match a config")]
+    NoMatch,
+}
+
+/// Quote a word for sh, when it needs quoting.
+fn quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:/".contains(c))
+    {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// The xrandr arguments that lay out one output as `conf` describes. `names` maps the aliases
+/// of the layout to the names of the outputs showing them.
+fn output_args(name: &str, conf: &MonConfig, names: &HashMap<&str, &str>) -> Vec<String> {
+    let mut args = vec!["--output".to_string(), quote(name)];
+    if !conf.enabled {
+        args.push("--off".to_string());
+        return args;
+    }
+    match (&conf.modeline, &conf.mode) {
+        (Some(modeline), _) => args.extend(["--mode".to_string(), quote(&modeline.name())]),
+        (None, ModeSpec::Exact(mode)) => args.extend(["--mode".to_string(), mode.to_string()]),
+        (None, ModeSpec::Preferred) => args.push("--preferred".to_string()),
+    }
+    if let Some(rate) = conf.rate {
+        args.extend(["--rate".to_string(), rate.to_string()]);
+    }
+    let source = conf.clone_of.as_deref().and_then(|of| names.get(of));
+    let relative = conf
+        .relative
+        .as_ref()
+        .and_then(|r| names.get(r.of.as_str()).map(|of| (r.direction, of)));
+    match (source, relative) {
+        (Some(source), _) => args.extend(["--same-as".to_string(), quote(source)]),
+        (None, Some((direction, of))) => args.extend([format!("--{}", direction), quote(of)]),
+        (None, None) => args.extend([
+            "--pos".to_string(),
+            format!("{}x{}", conf.position.x, conf.position.y),
+        ]),
+    }
+    args.extend(["--rotate".to_string(), "normal".to_string()]);
+    if let Some(scale) = conf.scale {
+        args.extend(["--scale".to_string(), format!("{}x{}", scale.x, scale.y)]);
+    }
+    if let Some(panning) = &conf.panning {
+        let Position { x, y } = panning.origin(conf.position);
+        let mut area = format!("{}+{}+{}", panning.size, x, y);
+        if let Some(tracking) = &panning.tracking {
+            area.push_str(&format!("/{}", tracking));
+        }
+        args.extend(["--panning".to_string(), area]);
+    }
+    if let Some(gamma) = conf.gamma {
+        args.extend(["--gamma".to_string(), gamma.to_string()]);
+    }
+    if let Some(brightness) = conf.brightness {
+        args.extend(["--brightness".to_string(), brightness.to_string()]);
+    }
+    for (property, value) in conf.properties.iter() {
+        args.extend([
+            "--set".to_string(),
+            quote(property),
+            quote(&value.to_string()),
+        ]);
+    }
+    if conf.primary {
+        args.push("--primary".to_string());
+    }
+    args
+}
+
+/// The outputs of `setup` with their names, sorted by name.
+fn sorted_setup<'a>(
+    setup: &HashMap<Output, &'a MonConfig>,
+    outputs: &'a HashMap<Output, (String, bool)>,
+) -> Vec<(&'a str, &'a MonConfig)> {
+    let mut sorted: Vec<_> = setup
+        .iter()
+        .filter_map(|(out, &conf)| Some((outputs.get(out)?.0.as_str(), conf)))
+        .collect();
+    sorted.sort_by_key(|(name, _)| *name);
+    sorted
+}
+
+/// Write a shell script that lays out the outputs with xrandr, as applying `single` would.
+/// `setup` maps the outputs in the layout to their configuration, and `outputs` names every
+/// output along with whether it is connected.
+fn script(
+    single: &SingleConfig,
+    setup: &HashMap<Output, &MonConfig>,
+    outputs: &HashMap<Output, (String, bool)>,
+) -> String {
+    let names: HashMap<&str, &str> = setup
+        .iter()
+        .filter_map(|(out, conf)| Some((conf.name.as_str(), outputs.get(out)?.0.as_str())))
+        .collect();
+    let mut lines = Vec::new();
+    if let Some(fb_size) = &single.fb_size {
+        lines.push(format!("--fb {}", fb_size));
+    }
+    if let Some(dpi) = single.dpi {
+        lines.push(format!("--dpi {}", dpi));
+    }
+    let mut sorted: Vec<_> = outputs.iter().collect();
+    sorted.sort_by(|(_, (a, _)), (_, (b, _))| a.cmp(b));
+    for (out, (name, connected)) in sorted {
+        match setup.get(out) {
+            Some(conf) => lines.push(output_args(name, conf, &names).join(" ")),
+            // Connected monitors that the layout does not name are left as they are, unless
+            // it disables them
+            None if *connected && single.allow_extra_monitors && !single.disable_extra_monitors => {
+            }
+            None => lines.push(format!("--output {} --off", quote(name))),
+        }
+    }
+    let mut out = format!(
+        "#!/bin/sh\n# Layout {}, exported by monitor-layout\n",
+        quote(&single.name)
+    );
+    // Modes given by a modeline are created first; creating a mode that exists fails harmlessly
+    for (out_name, modeline) in sorted_setup(setup, outputs)
+        .into_iter()
+        .filter_map(|(name, conf)| Some((name, conf.modeline.as_ref()?)))
+    {
+        let name = quote(&modeline.name());
+        out.push_str(&format!(
+            "xrandr --newmode {} {} 2>/dev/null\n",
+            name, modeline
+        ));
+        out.push_str(&format!("xrandr --addmode {} {}\n", quote(out_name), name));
+    }
+    out.push_str("xrandr");
+    for line in lines {
+        out.push_str(" \\\n    ");
+        out.push_str(&line);
+    }
+    out.push('\n');
+    out
+}
+
+/// Print a shell script that applies a layout with xrandr: the layout named by `--profile`, or
+/// else the layout that matches the connected monitors.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let (single, setup) = match args.value_of("profile") {
+        Some(profile) => {
+            let (matches, single) = config.layout_named(profile).into_diagnostic()?;
+            let out_to_mon: HashMap<_, _> = get_monitors(&conn, &res.outputs, atom_edid)
+                .map(|(out, mon)| (out, config.identify(mon)))
+                .collect();
+            (single, outputs_in(matches, single, &out_to_mon))
+        }
+        None => match get_config(&config, &conn, &res.outputs, atom_edid) {
+            Some((_, single, setup)) => (single, setup),
+            None => return Err(Error::NoMatch).into_diagnostic(),
+        },
+    };
+    let mut outputs = HashMap::with_capacity(res.outputs.len());
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let name = String::from_utf8_lossy(&info.name).into_owned();
+        outputs.insert(out, (name, info.connection == RandrConnection::CONNECTED));
+    }
+    print!("{}", script(single, &setup, &outputs));
+    Ok(())
+}
@@ -0,0 +1,169 @@
+use clap::ArgMatches;
+use kdl::KdlValue;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt, NotifyMask},
+    protocol::xproto::{Atom, Window},
+};
+
+use crate::{
+    config::Monitor, edid_atom, edid_fingerprint, get_edid_bytes, get_monitors, get_outputs,
+};
+
+use super::daemon::{change_timestamp, setup_notify};
+
+/// A connected output with an EDID, and the fingerprint of the EDID
+#[derive(Serialize)]
+struct Fingerprint {
+    output: String,
+    fingerprint: String,
+}
+
+/// A connected monitor, aliased by the name of its output
+#[derive(Serialize)]
+struct Aliased {
+    alias: String,
+    monitor: Monitor,
+}
+
+/// The connected monitors, in the order that keys a layout
+#[derive(Serialize)]
+struct Matches {
+    monitors: Vec<Aliased>,
+    matches: Vec<String>,
+}
+
+/// Each connected output with an EDID, and the fingerprint of the EDID, sorted by output name.
+fn fingerprints<C: Connection>(
+    conn: &C,
+    root: Window,
+    atom_edid: Atom,
+) -> Result<Vec<Fingerprint>> {
+    let res = get_outputs(conn, root).into_diagnostic()?;
+    let mut fingerprints = Vec::with_capacity(res.outputs.len());
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if info.connection != RandrConnection::CONNECTED {
+            continue;
+        }
+        let edid = get_edid_bytes(conn, atom_edid, out).into_diagnostic()?;
+        if edid.is_empty() {
+            continue;
+        }
+        fingerprints.push(Fingerprint {
+            output: String::from_utf8_lossy(&info.name).into_owned(),
+            fingerprint: edid_fingerprint(&edid),
+        });
+    }
+    fingerprints.sort_by(|a, b| a.output.cmp(&b.output));
+    Ok(fingerprints)
+}
+
+/// The connected monitors aliased by their output name, in the order that keys a layout.
+fn matches<C: Connection>(conn: &C, root: Window, atom_edid: Atom) -> Result<Matches> {
+    let res = get_outputs(conn, root).into_diagnostic()?;
+    let mut monitors = Vec::with_capacity(res.outputs.len());
+    for (out, monitor) in get_monitors(conn, &res.outputs, atom_edid) {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let alias = String::from_utf8_lossy(&info.name).into_owned();
+        monitors.push(Aliased { alias, monitor });
+    }
+    monitors.sort_by(|a, b| (&a.monitor, &a.alias).cmp(&(&b.monitor, &b.alias)));
+    let matches = monitors.iter().map(|m| m.alias.clone()).collect();
+    Ok(Matches { monitors, matches })
+}
+
+/// Serialize `value` as JSON; on one line when `compact` is set, so that each value printed
+/// while watching is a line of its own.
+fn to_json<T: Serialize>(value: &T, compact: bool) -> Result<String> {
+    if compact {
+        serde_json::to_string(value).into_diagnostic()
+    } else {
+        serde_json::to_string_pretty(value).into_diagnostic()
+    }
+}
+
+/// The fingerprints, or with `--matches` the connected monitors, as text or as JSON.
+fn render<C: Connection>(
+    args: &ArgMatches<'_>,
+    conn: &C,
+    root: Window,
+    atom_edid: Atom,
+) -> Result<String> {
+    let json = args.is_present("json");
+    let watch = args.is_present("watch");
+    if args.is_present("matches") {
+        let matches = matches(conn, root, atom_edid)?;
+        if json {
+            return to_json(&matches, watch);
+        }
+        let quote = |alias: &str| KdlValue::String(alias.to_string()).to_string();
+        let mut lines: Vec<_> = matches
+            .monitors
+            .iter()
+            .map(|m| format!("monitor {} {}", quote(&m.alias), m.monitor))
+            .collect();
+        let aliases: Vec<_> = matches.matches.iter().map(|a| quote(a)).collect();
+        lines.push(format!("matches {}", aliases.join(" ")));
+        Ok(lines.join("\n"))
+    } else {
+        let fingerprints = fingerprints(conn, root, atom_edid)?;
+        if json {
+            return to_json(&fingerprints, watch);
+        }
+        let lines: Vec<_> = fingerprints
+            .iter()
+            .map(|f| format!("{} {}", f.output, f.fingerprint))
+            .collect();
+        Ok(lines.join("\n"))
+    }
+}
+
+/// Print the name of each connected output with an EDID, followed by the fingerprint of the
+/// EDID, or with `--matches`, the connected monitors as a layout matches them. With `--json`,
+/// print them as JSON. With `--watch`, print them again each time they change.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let json = args.is_present("json");
+    let mut printed = render(args, &conn, root, atom_edid)?;
+    if !printed.is_empty() {
+        println!("{}", printed);
+    }
+    if !args.is_present("watch") {
+        return Ok(());
+    }
+    setup_notify(
+        &conn,
+        root,
+        NotifyMask::SCREEN_CHANGE | NotifyMask::OUTPUT_CHANGE,
+    )?;
+    loop {
+        let event = conn.wait_for_event().into_diagnostic()?;
+        if change_timestamp(&event).is_none() {
+            continue;
+        }
+        // One change sends several events, so the output is only printed again when it changed
+        let next = render(args, &conn, root, atom_edid)?;
+        if next != printed {
+            // JSON values are separated by lines already
+            if !json {
+                println!();
+            }
+            println!("{}", next);
+            printed = next;
+        }
+    }
+}
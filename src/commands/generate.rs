@@ -0,0 +1,172 @@
+use clap::ArgMatches;
+use kdl::KdlValue;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{connect, connection::Connection, protocol::randr::ConnectionExt as RandrExt};
+
+use std::{fmt::Write as _, path::Path};
+
+use crate::{
+    best_mode_for_output,
+    config::{Config, Monitor},
+    edid_atom, get_monitors, get_outputs, mode_sizes, refresh_rate,
+};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No monitors with an EDID are connected")]
+    NoMonitors,
+}
+
+/// The name of the layout when neither `--name` nor the hostname gives one
+const DEFAULT_LAYOUT: &str = "default";
+
+/// How many of a monitor's other mode sizes are suggested in comments
+const ALTERNATIVES: usize = 4;
+
+/// A connected monitor, as it is laid out in the generated configuration
+struct Generated {
+    connector: String,
+    monitor: Monitor,
+    w: u16,
+    h: u16,
+    rate: Option<f64>,
+    /// Other sizes of mode the monitor offers, largest first
+    alternatives: Vec<(u16, u16)>,
+}
+
+fn quote(s: &str) -> String {
+    KdlValue::String(s.to_string()).to_string()
+}
+
+/// The name of this machine, if it has one.
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match String::from_utf8_lossy(&buf[..len]).trim() {
+        "" => None,
+        name => Some(name.to_string()),
+    }
+}
+
+/// Print a configuration with a single layout for the connected monitors: each in its
+/// preferred mode, side by side from left to right in order of output name. Other sizes that
+/// each monitor offers are suggested in comments.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+
+    let mut generated = Vec::new();
+    for (out, monitor) in get_monitors(&conn, &res.outputs, atom_edid) {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let best = best_mode_for_output(&info, &mode_sizes(&res.modes), None, None)
+            .and_then(|id| res.modes.iter().find(|mi| mi.id == id));
+        let (w, h, rate) = match best {
+            Some(mi) => (mi.width, mi.height, Some(refresh_rate(mi))),
+            None => continue,
+        };
+        let mut alternatives: Vec<_> = info
+            .modes
+            .iter()
+            .filter_map(|id| res.modes.iter().find(|mi| mi.id == *id))
+            .map(|mi| (mi.width, mi.height))
+            .filter(|&size| size != (w, h))
+            .collect();
+        alternatives.sort_by_key(|&(w, h)| (std::cmp::Reverse(w as u32 * h as u32), w));
+        alternatives.dedup();
+        alternatives.truncate(ALTERNATIVES);
+        generated.push(Generated {
+            connector: String::from_utf8_lossy(&info.name).into_owned(),
+            monitor,
+            w,
+            h,
+            rate,
+            alternatives,
+        });
+    }
+    if generated.is_empty() {
+        return Err(Error::NoMonitors).into_diagnostic();
+    }
+    generated.sort_by(|a, b| a.connector.cmp(&b.connector));
+
+    let name = match args.value_of("name") {
+        Some(name) => name.to_string(),
+        None => hostname().unwrap_or_else(|| DEFAULT_LAYOUT.to_string()),
+    };
+    let fb_w: u32 = generated.iter().map(|g| g.w as u32).sum();
+    let fb_h = generated.iter().map(|g| g.h).max().unwrap_or(0);
+
+    let mut text = String::new();
+    for g in generated.iter() {
+        write!(text, "monitor {}", quote(&g.connector)).unwrap();
+        if let Some(product) = &g.monitor.product {
+            write!(text, " product={}", quote(product)).unwrap();
+        }
+        if let Some(serial) = &g.monitor.serial {
+            write!(text, " serial={}", quote(serial)).unwrap();
+        }
+        if let Some(connector) = &g.monitor.connector {
+            write!(text, " connector={}", quote(connector)).unwrap();
+        }
+        if let Some(year) = g.monitor.year {
+            write!(text, " year={}", year).unwrap();
+        }
+        if let Some(week) = g.monitor.week {
+            write!(text, " week={}", week).unwrap();
+        }
+        text.push('\n');
+    }
+    text.push('\n');
+    write!(text, "layout {} {{\n    matches", quote(&name)).unwrap();
+    for g in generated.iter() {
+        write!(text, " {}", quote(&g.connector)).unwrap();
+    }
+    text.push('\n');
+    writeln!(text, "    fb-size w={} h={}", fb_w, fb_h).unwrap();
+    let mut x = 0;
+    for (i, g) in generated.iter().enumerate() {
+        write!(
+            text,
+            "    monitor {} w={} h={} x={} y=0",
+            quote(&g.connector),
+            g.w,
+            g.h,
+            x
+        )
+        .unwrap();
+        if let Some(rate) = g.rate {
+            write!(text, " rate={:.2}", rate).unwrap();
+        }
+        if i == 0 {
+            text.push_str(" primary=true");
+        }
+        text.push('\n');
+        for (w, h) in g.alternatives.iter() {
+            writeln!(
+                text,
+                "    // monitor {} w={} h={} x={} y=0",
+                quote(&g.connector),
+                w,
+                h,
+                x
+            )
+            .unwrap();
+        }
+        x += g.w as u32;
+    }
+    text.push_str("}\n");
+    // Make sure that what is printed can be read back
+    Config::from_text(&text, Path::new(&name)).into_diagnostic()?;
+    print!("{}", text);
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+use x11rb::{connect, connection::Connection};
+
+use std::collections::BTreeMap;
+
+use crate::{
+    config::{resolve_positions, Config, Mode, ModeSpec, Monitor, Position, Scale, SingleConfig},
+    edid_atom, get_monitors, get_outputs,
+};
+
+use super::check;
+
+/// How a single monitor is laid out within a profile
+#[derive(Serialize)]
+struct Placement<'a> {
+    alias: &'a str,
+    /// None for the wildcard monitor
+    monitor: Option<&'a Monitor>,
+    mode: String,
+    rate: Option<f64>,
+    /// The position with relative positions resolved, or None when that needs the size of a
+    /// preferred mode, which is unknown until the layout is applied
+    position: Option<Position>,
+    /// The relative position as configured, such as "right-of laptop"
+    relative: Option<String>,
+    scale: Option<Scale>,
+    primary: bool,
+    enabled: bool,
+}
+
+#[derive(Serialize)]
+struct Profile<'a> {
+    monitors: &'a [Monitor],
+    /// Whether the layout also matches any one other monitor
+    wildcard: bool,
+    /// Whether the layout is applied when no other layout matches
+    fallback: bool,
+    /// Whether the layout also matches when more monitors are connected
+    allow_extra_monitors: bool,
+    disable_extra_monitors: bool,
+    priority: i64,
+    /// The number of monitors the layout shows, and the number it turns off
+    shown: usize,
+    off: usize,
+    /// The fb-size of the layout, or the size it computes when every size is known
+    fb_size: Option<Mode>,
+    /// Whether the connected monitors are ones the layout matches, or None without an X server
+    matches_now: Option<bool>,
+    /// Whether the layout is the one the connected monitors select, or None without an X server
+    selected: Option<bool>,
+    /// The monitors the layout names that are not connected
+    missing: Vec<&'a Monitor>,
+    layout: Vec<Placement<'a>>,
+}
+
+fn placements(single: &SingleConfig) -> Vec<Placement<'_>> {
+    let positions = resolve_positions(single.monitor_configs(), |conf| match &conf.mode {
+        ModeSpec::Exact(mode) => Some(conf.scaled(mode)),
+        ModeSpec::Preferred => None,
+    })
+    .ok();
+    let mut placements: Vec<_> = single
+        .setup
+        .iter()
+        .map(|(monitor, conf)| (Some(monitor), conf))
+        .chain(single.wildcard.iter().map(|conf| (None, conf)))
+        .map(|(monitor, conf)| Placement {
+            alias: &conf.name,
+            monitor,
+            mode: conf.mode.to_string(),
+            rate: conf.rate,
+            position: match (&positions, &conf.relative) {
+                (Some(positions), _) => Some(positions[&conf.name]),
+                (None, Some(_)) => None,
+                (None, None) => Some(conf.position),
+            },
+            relative: conf.relative.as_ref().map(|r| r.to_string()),
+            scale: conf.scale,
+            primary: conf.primary,
+            enabled: conf.enabled,
+        })
+        .collect();
+    placements.sort_by(|a, b| a.alias.cmp(b.alias));
+    placements
+}
+
+/// The connected monitors, identified and sorted as the daemon looks them up, or None when
+/// there is no X server to ask.
+fn connected_monitors(config: &Config) -> Option<Vec<Monitor>> {
+    let (conn, screen_num) = connect(None).ok()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).ok()?;
+    let res = get_outputs(&conn, root).ok()?;
+    let mut monitors: Vec<_> = get_monitors(&conn, &res.outputs, atom_edid)
+        .map(|(_, mon)| config.identify(mon))
+        .collect();
+    monitors.sort();
+    Some(monitors)
+}
+
+fn profile<'a>(
+    monitors: &'a [Monitor],
+    single: &'a SingleConfig,
+    wildcard: bool,
+    fallback: bool,
+    connected: Option<&[Monitor]>,
+    selected: Option<&str>,
+) -> Profile<'a> {
+    let configs = || single.monitor_configs();
+    let sizes_known = configs().all(|m| !m.enabled || matches!(m.mode, ModeSpec::Exact(_)));
+    let fb_size = single
+        .fb_size
+        .clone()
+        .or_else(|| sizes_known.then(|| Config::computed_fb_size(configs())));
+    let missing: Vec<_> = match connected {
+        Some(connected) => monitors.iter().filter(|m| !connected.contains(m)).collect(),
+        None => Vec::new(),
+    };
+    // As Config::lookup matches them, apart from which layout wins
+    let matches_now = connected.map(|connected| {
+        let extras = connected.len() - (monitors.len() - missing.len());
+        missing.is_empty()
+            && match (wildcard, fallback) {
+                (true, _) => extras == 1,
+                (_, true) => selected == Some(single.name.as_str()),
+                _ => extras == 0 || single.allow_extra_monitors,
+            }
+    });
+    Profile {
+        monitors,
+        wildcard,
+        fallback,
+        allow_extra_monitors: single.allow_extra_monitors,
+        disable_extra_monitors: single.disable_extra_monitors,
+        priority: single.priority,
+        shown: configs().filter(|m| m.enabled).count(),
+        off: configs().filter(|m| !m.enabled).count(),
+        fb_size,
+        matches_now,
+        selected: connected.map(|_| selected == Some(single.name.as_str())),
+        missing,
+        layout: placements(single),
+    }
+}
+
+/// Print the profiles within the configuration and the monitors they require, with a summary
+/// of each layout. When an X server is available, also print whether each profile matches the
+/// connected monitors, and which one they select.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    let connected = connected_monitors(&config);
+    let selected = connected.as_ref().and_then(|connected| {
+        match config.lookup(connected) {
+            Some((_, single)) => Some(single),
+            None => config.fallback.as_ref(),
+        }
+        .map(|single| single.name.as_str())
+    });
+    let connected = connected.as_deref();
+    let profiles: BTreeMap<_, _> = config
+        .layouts
+        .iter()
+        .map(|layout| (layout, false))
+        .chain(config.wildcard_layouts.iter().map(|layout| (layout, true)))
+        .map(|((monitors, single), wildcard)| {
+            (
+                single.name.as_str(),
+                profile(monitors, single, wildcard, false, connected, selected),
+            )
+        })
+        .chain(config.fallback.iter().map(|single| {
+            (
+                single.name.as_str(),
+                profile(&[], single, false, true, connected, selected),
+            )
+        }))
+        .collect();
+    if args.is_present("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&profiles).into_diagnostic()?
+        );
+        return Ok(());
+    }
+    for (name, profile) in profiles.iter() {
+        println!("{}", name);
+        for monitor in profile.monitors.iter() {
+            println!("    {}", monitor);
+        }
+        if profile.wildcard {
+            println!("    any other monitor");
+        }
+        if profile.fallback {
+            println!("    fallback when no other layout matches");
+        }
+        if profile.allow_extra_monitors {
+            let extras = if profile.disable_extra_monitors {
+                "disabled"
+            } else {
+                "left as they are"
+            };
+            println!(
+                "    any other monitors, {}, with priority {}",
+                extras, profile.priority
+            );
+        }
+        let mut summary = format!(
+            "{} monitor{}",
+            profile.shown,
+            if profile.shown == 1 { "" } else { "s" }
+        );
+        if profile.off > 0 {
+            summary.push_str(&format!(", {} off", profile.off));
+        }
+        match &profile.fb_size {
+            Some(fb_size) => summary.push_str(&format!(", fb-size {}", fb_size)),
+            None => summary.push_str(", fb-size known once applied"),
+        }
+        println!("    layout: {}", summary);
+        match (profile.matches_now, profile.selected) {
+            (Some(true), Some(true)) => println!("    matches now, and is selected"),
+            // A layout matches, so one is selected
+            (Some(true), _) => println!("    matches now, but {} is selected", selected.unwrap()),
+            (Some(false), _) if !profile.missing.is_empty() => {
+                let missing: Vec<_> = profile.missing.iter().map(|m| m.to_string()).collect();
+                println!(
+                    "    does not match now: {} not connected",
+                    missing.join(", ")
+                );
+            }
+            (Some(false), _) if profile.fallback => {
+                println!(
+                    "    does not match now, as {} is selected",
+                    selected.unwrap()
+                )
+            }
+            (Some(false), _) => println!("    does not match now: other monitors are connected"),
+            (None, _) => (),
+        }
+        if args.is_present("verbose") {
+            for p in profile.layout.iter() {
+                if !p.enabled {
+                    println!("    {}: off", p.alias);
+                    continue;
+                }
+                let rate = p.rate.map(|r| format!("@{}", r)).unwrap_or_default();
+                let scale = p
+                    .scale
+                    .map(|s| format!(" scaled by {}", s))
+                    .unwrap_or_default();
+                let primary = if p.primary { " primary" } else { "" };
+                let position = match p.position {
+                    Some(pos) => format!("at {},{}", pos.x, pos.y),
+                    None => p.relative.clone().unwrap_or_default(),
+                };
+                println!(
+                    "    {}: {}{} {}{}{}",
+                    p.alias, p.mode, rate, position, scale, primary
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use clap::ArgMatches;
+use edid::parse;
+use miette::{IntoDiagnostic, Result};
+use nom::IResult;
+use serde::Serialize;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt},
+};
+
+use crate::{config::Monitor, edid_atom, get_edid_bytes, get_outputs};
+
+/// A connected output and the monitor attached to it, as a config names it
+#[derive(Serialize)]
+struct Listed {
+    output: String,
+    monitor: Monitor,
+    mm_width: u32,
+    mm_height: u32,
+    /// Whether the EDID was read
+    edid: bool,
+    /// Why the EDID was not read, when it was not
+    edid_error: Option<String>,
+}
+
+/// Print a line for each connected output: its name, the monitor as a config names it, its
+/// physical size and whether its EDID could be read, or why not. With `--json`, print a JSON
+/// array instead.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let mut listed = Vec::with_capacity(res.outputs.len());
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if info.connection != RandrConnection::CONNECTED {
+            continue;
+        }
+        let name = String::from_utf8_lossy(&info.name).into_owned();
+        let bytes = get_edid_bytes(&conn, atom_edid, out).into_diagnostic()?;
+        // Monitors are named as get_monitors names them, by their connector without an EDID
+        let (monitor, edid_error) = match parse(&bytes) {
+            IResult::Done(_, edid) => (Monitor::from(edid), None),
+            _ if bytes.is_empty() => (
+                Monitor::from_connector(name.clone()),
+                Some("no EDID".to_string()),
+            ),
+            IResult::Error(e) => (
+                Monitor::from_connector(name.clone()),
+                Some(format!("EDID could not be parsed: {:?}", e)),
+            ),
+            IResult::Incomplete(needed) => (
+                Monitor::from_connector(name.clone()),
+                Some(format!(
+                    "EDID could not be parsed: {} bytes is too short, {:?}",
+                    bytes.len(),
+                    needed
+                )),
+            ),
+        };
+        listed.push(Listed {
+            output: name,
+            monitor,
+            mm_width: info.mm_width,
+            mm_height: info.mm_height,
+            edid: edid_error.is_none(),
+            edid_error,
+        });
+    }
+    listed.sort_by(|a, b| a.output.cmp(&b.output));
+    if args.is_present("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&listed).into_diagnostic()?
+        );
+        return Ok(());
+    }
+    for l in listed {
+        println!(
+            "{}: {}, {}mmx{}mm, {}",
+            l.output,
+            l.monitor,
+            l.mm_width,
+            l.mm_height,
+            l.edid_error.as_deref().unwrap_or("EDID read")
+        );
+    }
+    Ok(())
+}
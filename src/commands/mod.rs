@@ -1,4 +1,55 @@
+mod apply;
+mod completions;
+mod current;
+mod cycle;
 mod daemon;
+mod decode_edid;
+mod detect;
+mod diagram;
+mod diff;
+mod doctor;
+mod dump_config;
+mod export;
+mod fingerprint;
+mod generate;
+mod list;
+mod list_monitors;
+mod print_edid;
 mod print_edids;
-pub use daemon::{check, daemon};
+mod providers;
+mod render;
+mod reset;
+mod save;
+mod snapshot;
+mod status;
+mod switch;
+mod test;
+mod verify;
+mod watch;
+pub use apply::main as apply;
+pub use completions::{main as completions, profile_names};
+pub use current::main as current;
+pub use cycle::main as cycle;
+pub use daemon::{check, daemon, DaemonContext};
+pub(crate) use daemon::{config_name, config_names};
+pub use decode_edid::main as decode_edid;
+pub use detect::main as detect;
+pub use diff::main as diff;
+pub use doctor::main as doctor;
+pub use dump_config::main as dump_config;
+pub use export::main as export;
+pub use fingerprint::main as fingerprint;
+pub use generate::main as generate;
+pub use list::main as list;
+pub use list_monitors::main as list_monitors;
+pub use print_edid::main as print_edid;
 pub use print_edids::main as print_edids;
+pub use providers::main as providers;
+pub use render::main as render;
+pub use reset::main as reset;
+pub use save::main as save;
+pub use status::main as status;
+pub use switch::main as switch;
+pub use test::main as test;
+pub use verify::main as verify;
+pub use watch::main as watch;
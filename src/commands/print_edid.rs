@@ -0,0 +1,98 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt},
+};
+
+use crate::{edid_atom, edid_parses, get_edid_property, get_outputs};
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No connected output named {0}")]
+    UnknownOutput(String),
+    #[error("{0} outputs have an EDID; name the one to write")]
+    SeveralEdids(usize),
+    #[error("No output has an EDID to write")]
+    NoEdid,
+}
+
+/// The size of an EDID block; a complete EDID is a whole number of them
+const EDID_BLOCK: usize = 128;
+
+/// Print bytes as lines of 16 hexadecimal bytes, each line led by its offset.
+fn hex_dump(bytes: &[u8]) {
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<_> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+        println!("    {:04x}: {}", i * 16, hex.join(" "));
+    }
+}
+
+/// Read the EDID of the named output, or of every connected output, exactly as a monitor is
+/// matched, and print its length, whether it parses, and its bytes as a hex dump. With
+/// `--output`, the raw bytes of the one EDID are written to a file instead of the hex dump.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let wanted = args.value_of("output-name");
+    let mut edids = Vec::new();
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let name = String::from_utf8_lossy(&info.name).into_owned();
+        if info.connection != RandrConnection::CONNECTED || wanted.is_some_and(|w| w != name) {
+            continue;
+        }
+        let property = get_edid_property(&conn, atom_edid, out).into_diagnostic()?;
+        edids.push((name, property));
+    }
+    if let Some(wanted) = wanted.filter(|_| edids.is_empty()) {
+        return Err(Error::UnknownOutput(wanted.to_string())).into_diagnostic();
+    }
+
+    for (name, property) in edids.iter() {
+        let bytes = &property.data;
+        if bytes.is_empty() {
+            println!("{}: no EDID", name);
+            continue;
+        }
+        let mut notes = Vec::new();
+        if property.bytes_after > 0 {
+            notes.push(format!("{} more bytes not read", property.bytes_after));
+        }
+        if bytes.len() % EDID_BLOCK != 0 {
+            notes.push(format!("not a whole number of {}-byte blocks", EDID_BLOCK));
+        }
+        notes.push(
+            if edid_parses(bytes) {
+                "parses"
+            } else {
+                "does not parse"
+            }
+            .to_string(),
+        );
+        println!("{}: {} bytes, {}", name, bytes.len(), notes.join(", "));
+        if !args.is_present("output") {
+            hex_dump(bytes);
+        }
+    }
+
+    if let Some(file) = args.value_of("output") {
+        let mut with_edid = edids.iter().filter(|(_, p)| !p.data.is_empty());
+        let (name, property) = match (with_edid.next(), with_edid.count()) {
+            (Some(edid), 0) => edid,
+            (Some(_), others) => return Err(Error::SeveralEdids(others + 1)).into_diagnostic(),
+            (None, _) => return Err(Error::NoEdid).into_diagnostic(),
+        };
+        std::fs::write(file, &property.data).into_diagnostic()?;
+        println!("Wrote the EDID of {} to {}", name, file);
+    }
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use clap::ArgMatches;
-use miette::Result;
 use log::debug;
+use miette::Result;
 use x11rb::{
     connect,
     connection::Connection,
@@ -53,11 +53,20 @@ pub fn main(_: &ArgMatches<'_>) -> Result<()> {
             .serial
             .map(|s| format!(r#"serial="{}""#, s))
             .unwrap_or_default();
+        let connector = m
+            .connector
+            .map(|c| format!(r#"connector="{}""#, c))
+            .unwrap_or_default();
+        let year = m.year.map(|y| format!(" year={}", y)).unwrap_or_default();
+        let week = m.week.map(|w| format!(" week={}", w)).unwrap_or_default();
         println!(
-            r#"monitor "{name}" {product} {serial}"#,
+            r#"monitor "{name}" {product} {serial}{connector}{year}{week}"#,
             name = name,
             serial = serial,
-            product = product
+            product = product,
+            connector = connector,
+            year = year,
+            week = week
         );
     }
     Ok(())
@@ -0,0 +1,236 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{
+        Connection as RandrConnection, ConnectionExt as RandrExt, GetProviderInfoReply, Provider,
+        ProviderCapability,
+    },
+    protocol::xproto::{Timestamp, Window},
+    NONE,
+};
+
+use crate::get_outputs;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("No provider {0}; expected the name, number or XID of one of the providers")]
+    UnknownProvider(String),
+    #[error("{0} cannot show the outputs of another provider")]
+    NotSink(String),
+    #[error("{0} cannot show its image on the outputs of another provider")]
+    NotSource(String),
+}
+
+/// The value of `--set-output-source` that disconnects a provider from its source
+const NO_SOURCE: &str = "none";
+
+/// The providers of the screen, in the order the X server lists them, with their information.
+/// The first is the provider that the screen is rendered by.
+pub(crate) fn providers<C: Connection>(
+    conn: &C,
+    root: Window,
+) -> Result<Vec<(Provider, GetProviderInfoReply)>> {
+    let reply = conn
+        .randr_get_providers(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    let mut providers = Vec::with_capacity(reply.providers.len());
+    for &provider in reply.providers.iter() {
+        let info = conn
+            .randr_get_provider_info(provider, reply.timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        providers.push((provider, info));
+    }
+    Ok(providers)
+}
+
+fn capability_names(capabilities: u32) -> Vec<&'static str> {
+    [
+        (ProviderCapability::SOURCE_OUTPUT, "source output"),
+        (ProviderCapability::SINK_OUTPUT, "sink output"),
+        (ProviderCapability::SOURCE_OFFLOAD, "source offload"),
+        (ProviderCapability::SINK_OFFLOAD, "sink offload"),
+    ]
+    .iter()
+    .filter(|(bit, _)| capabilities & u32::from(u8::from(*bit)) != 0)
+    .map(|(_, name)| *name)
+    .collect()
+}
+
+fn has_capability(info: &GetProviderInfoReply, capability: ProviderCapability) -> bool {
+    info.capabilities & u32::from(u8::from(capability)) != 0
+}
+
+/// The providers, other than the one that renders the screen, that could show the image of
+/// another provider on their outputs but do not, so that the monitors connected to them cannot
+/// be shown. Each is described with a hint of how to use it.
+pub(crate) fn unsourced_providers<C: Connection>(conn: &C, root: Window) -> Result<Vec<String>> {
+    let providers = providers(conn, root)?;
+    let renderer = match providers.first() {
+        Some((_, info)) => String::from_utf8_lossy(&info.name).into_owned(),
+        None => return Ok(Vec::new()),
+    };
+    Ok(providers
+        .iter()
+        .skip(1)
+        .filter(|(_, info)| {
+            has_capability(info, ProviderCapability::SINK_OUTPUT)
+                && !info.outputs.is_empty()
+                && info.associated_providers.is_empty()
+        })
+        .map(|(_, info)| {
+            let name = String::from_utf8_lossy(&info.name);
+            format!(
+                "the {} outputs of provider {} have no source; see monitor-layout providers --set-output-source {} {}",
+                info.outputs.len(),
+                name,
+                name,
+                renderer
+            )
+        })
+        .collect())
+}
+
+/// Find a provider by its name, its number in the list of providers, or its XID.
+fn find_provider(
+    providers: &[(Provider, GetProviderInfoReply)],
+    text: &str,
+) -> Result<(Provider, String)> {
+    let named = |(provider, info): &(Provider, GetProviderInfoReply)| {
+        (*provider, String::from_utf8_lossy(&info.name).into_owned())
+    };
+    providers
+        .iter()
+        .find(|(_, info)| info.name == text.as_bytes())
+        .or_else(|| providers.get(text.parse::<usize>().ok()?))
+        .or_else(|| {
+            let xid = u32::from_str_radix(text.strip_prefix("0x")?, 16).ok()?;
+            providers.iter().find(|(provider, _)| *provider == xid)
+        })
+        .map(named)
+        .ok_or_else(|| Error::UnknownProvider(text.to_string()))
+        .into_diagnostic()
+}
+
+/// Show the image of `source` on the outputs of `sink`, or of no provider when `source` is
+/// "none", as `xrandr --setprovideroutputsource` does.
+fn set_output_source<C: Connection>(
+    conn: &C,
+    providers: &[(Provider, GetProviderInfoReply)],
+    timestamp: Timestamp,
+    sink: &str,
+    source: &str,
+) -> Result<()> {
+    let (sink, sink_name) = find_provider(providers, sink)?;
+    let sink_info = &providers.iter().find(|(p, _)| *p == sink).unwrap().1;
+    if !has_capability(sink_info, ProviderCapability::SINK_OUTPUT) {
+        return Err(Error::NotSink(sink_name)).into_diagnostic();
+    }
+    let (source, source_name) = match source {
+        NO_SOURCE => (NONE, NO_SOURCE.to_string()),
+        source => {
+            let (source, source_name) = find_provider(providers, source)?;
+            let source_info = &providers.iter().find(|(p, _)| *p == source).unwrap().1;
+            if !has_capability(source_info, ProviderCapability::SOURCE_OUTPUT) {
+                return Err(Error::NotSource(source_name)).into_diagnostic();
+            }
+            (source, source_name)
+        }
+    };
+    conn.randr_set_provider_output_source(sink, source, timestamp)
+        .into_diagnostic()?
+        .check()
+        .into_diagnostic()?;
+    println!(
+        "Set the output source of provider {} to {}",
+        sink_name, source_name
+    );
+    Ok(())
+}
+
+/// Print each RandR provider, such as each GPU of a laptop with hybrid graphics: its
+/// capabilities, its CRTCs and outputs, and the providers it is associated with. With
+/// `--set-output-source`, first show the image of one provider on the outputs of another.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    if let Some(mut values) = args.values_of("set-output-source") {
+        // clap takes exactly two values
+        let (sink, source) = (values.next().unwrap(), values.next().unwrap());
+        let res = get_outputs(&conn, root).into_diagnostic()?;
+        set_output_source(
+            &conn,
+            &providers(&conn, root)?,
+            res.config_timestamp,
+            sink,
+            source,
+        )?;
+    }
+    let providers = providers(&conn, root)?;
+    if providers.is_empty() {
+        println!("No providers");
+    }
+    let name_of = |provider: Provider| {
+        providers
+            .iter()
+            .find(|(p, _)| *p == provider)
+            .map(|(_, info)| String::from_utf8_lossy(&info.name).into_owned())
+            .unwrap_or_else(|| format!("0x{:x}", provider))
+    };
+    for (i, (provider, info)) in providers.iter().enumerate() {
+        println!(
+            "Provider {} (0x{:x}): {}",
+            i,
+            provider,
+            String::from_utf8_lossy(&info.name)
+        );
+        let capabilities = capability_names(info.capabilities);
+        if capabilities.is_empty() {
+            println!("    capabilities: none");
+        } else {
+            println!("    capabilities: {}", capabilities.join(", "));
+        }
+        println!("    CRTCs: {}", info.crtcs.len());
+        let mut outputs = Vec::with_capacity(info.outputs.len());
+        for &out in info.outputs.iter() {
+            let out_info = conn
+                .randr_get_output_info(out, info.timestamp)
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic()?;
+            let connection = if out_info.connection == RandrConnection::CONNECTED {
+                "connected"
+            } else {
+                "disconnected"
+            };
+            outputs.push(format!(
+                "{} ({})",
+                String::from_utf8_lossy(&out_info.name),
+                connection
+            ));
+        }
+        if outputs.is_empty() {
+            println!("    outputs: none");
+        } else {
+            println!("    outputs: {}", outputs.join(", "));
+        }
+        for (associated, capability) in info
+            .associated_providers
+            .iter()
+            .zip(info.associated_capability.iter())
+        {
+            println!(
+                "    associated with {}: {}",
+                name_of(*associated),
+                capability_names(*capability).join(", ")
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,189 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use svg::{
+    node::{
+        element::{Rectangle, Text},
+        Text as TextNode,
+    },
+    Document,
+};
+use x11rb::{connect, connection::Connection, protocol::randr::ConnectionExt as RandrExt};
+
+use std::collections::HashMap;
+
+use crate::{
+    config::{resolve_positions, Mode, ModeSpec, MonConfig},
+    edid_atom, get_monitors, get_outputs,
+};
+
+use super::check;
+
+/// The size drawn for monitors that use their preferred mode, which is unknown until the layout
+/// is applied
+const PREFERRED_SIZE: Mode = Mode { w: 1920, h: 1080 };
+
+const PRIMARY_FILL: &str = "#8fb8de";
+const SECONDARY_FILL: &str = "#d9d9d9";
+
+/// A single monitor to draw
+struct Screen {
+    label: String,
+    mode: String,
+    x: i16,
+    y: i16,
+    size: Mode,
+    primary: bool,
+}
+
+/// Read the screens of a layout from the configuration.
+fn configured_screens(args: &ArgMatches<'_>, profile: &str) -> Result<Vec<Screen>> {
+    let config = check(args)?;
+    let (_, single) = config.layout_named(profile).into_diagnostic()?;
+    let size_of = |conf: &MonConfig| match &conf.mode {
+        ModeSpec::Exact(mode) => conf.scaled(mode),
+        ModeSpec::Preferred => conf.scaled(&PREFERRED_SIZE),
+    };
+    // Relative positions are resolved with the sizes that are drawn
+    let positions = resolve_positions(single.monitor_configs(), |conf| Some(size_of(conf)))
+        .into_diagnostic()?;
+    let screens = single
+        .setup
+        .iter()
+        .map(|(mon, conf)| (Some(mon), conf))
+        .chain(single.wildcard.iter().map(|conf| (None, conf)))
+        .filter(|(_, conf)| conf.enabled)
+        .map(|(mon, conf)| Screen {
+            label: mon
+                .and_then(|mon| mon.product.clone())
+                .unwrap_or_else(|| conf.name.clone()),
+            mode: conf.mode.to_string(),
+            x: positions[&conf.name].x,
+            y: positions[&conf.name].y,
+            size: size_of(conf),
+            primary: conf.primary,
+        })
+        .collect();
+    Ok(screens)
+}
+
+/// Read the screens that the X server is currently displaying.
+fn current_screens() -> Result<Vec<Screen>> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let primary = conn
+        .randr_get_output_primary(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .output;
+    let products: HashMap<_, _> = get_monitors(&conn, &res.outputs, atom_edid)
+        .filter_map(|(out, mon)| mon.product.map(|p| (out, p)))
+        .collect();
+    let mut screens = Vec::new();
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if info.crtc == 0 {
+            continue;
+        }
+        let crtc = conn
+            .randr_get_crtc_info(info.crtc, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if crtc.mode == 0 {
+            continue;
+        }
+        let size = Mode {
+            w: crtc.width,
+            h: crtc.height,
+        };
+        screens.push(Screen {
+            label: products
+                .get(&out)
+                .cloned()
+                .unwrap_or_else(|| String::from_utf8_lossy(&info.name).into_owned()),
+            mode: size.to_string(),
+            x: crtc.x,
+            y: crtc.y,
+            size,
+            primary: out == primary,
+        });
+    }
+    Ok(screens)
+}
+
+/// Draw screens to scale, one rectangle per screen.
+fn draw(screens: &[Screen]) -> Document {
+    let min_x = screens.iter().map(|s| s.x as i32).min().unwrap_or(0);
+    let min_y = screens.iter().map(|s| s.y as i32).min().unwrap_or(0);
+    let max_x = screens
+        .iter()
+        .map(|s| s.x as i32 + s.size.w as i32)
+        .max()
+        .unwrap_or(0);
+    let max_y = screens
+        .iter()
+        .map(|s| s.y as i32 + s.size.h as i32)
+        .max()
+        .unwrap_or(0);
+    // Scale the text with the layout, so that it stays legible once the diagram is shrunk
+    let font_size = (max_y - min_y).max(1) / 20;
+    let mut document = Document::new().set(
+        "viewBox",
+        (min_x, min_y, (max_x - min_x).max(1), (max_y - min_y).max(1)),
+    );
+    for screen in screens {
+        let rect = Rectangle::new()
+            .set("x", screen.x)
+            .set("y", screen.y)
+            .set("width", screen.size.w)
+            .set("height", screen.size.h)
+            .set(
+                "fill",
+                if screen.primary {
+                    PRIMARY_FILL
+                } else {
+                    SECONDARY_FILL
+                },
+            )
+            .set("stroke", "black")
+            .set("stroke-width", font_size / 8);
+        let cx = screen.x as i32 + screen.size.w as i32 / 2;
+        let cy = screen.y as i32 + screen.size.h as i32 / 2;
+        let label = Text::new()
+            .set("x", cx)
+            .set("y", cy)
+            .set("text-anchor", "middle")
+            .set("font-family", "sans-serif")
+            .set("font-size", font_size)
+            .add(TextNode::new(screen.label.clone()));
+        let mode = Text::new()
+            .set("x", cx)
+            .set("y", cy + font_size * 3 / 2)
+            .set("text-anchor", "middle")
+            .set("font-family", "sans-serif")
+            .set("font-size", font_size)
+            .add(TextNode::new(screen.mode.clone()));
+        document = document.add(rect).add(label).add(mode);
+    }
+    document
+}
+
+/// Draw an SVG diagram of a configured layout or of the current layout.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let screens = match args.value_of("profile") {
+        Some(profile) => configured_screens(args, profile)?,
+        None => current_screens()?,
+    };
+    let document = draw(&screens);
+    match args.value_of("output") {
+        Some(path) => svg::save(path, &document).into_diagnostic(),
+        None => svg::write(std::io::stdout(), &document).into_diagnostic(),
+    }
+}
@@ -0,0 +1,92 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::randr::{Connection as RandrConnection, ConnectionExt as RandrExt, Output},
+};
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    config::{Direction, ModeSpec, MonConfig, Position, Relative, SingleConfig},
+    get_outputs,
+};
+
+use super::daemon::apply_config;
+
+/// The name of the layout that `reset` applies, as it appears in the logs
+const RESET_LAYOUT: &str = "reset";
+
+/// The configuration of a connected output when resetting: its preferred mode, placed right of
+/// the output before it.
+fn reset_config(name: String, previous: Option<&str>) -> MonConfig {
+    MonConfig {
+        name,
+        mode: ModeSpec::Preferred,
+        position: Position { x: 0, y: 0 },
+        primary: false,
+        rate: None,
+        enabled: true,
+        clone_of: None,
+        relative: previous.map(|of| Relative {
+            direction: Direction::RightOf,
+            of: of.to_string(),
+            gap: 0,
+        }),
+        unplaced: false,
+        scale: None,
+        gamma: None,
+        brightness: None,
+        panning: None,
+        modeline: None,
+        properties: BTreeMap::new(),
+    }
+}
+
+/// Show every connected output in its preferred mode, side by side from left to right in order
+/// of output name, and turn off every other output. The configuration is not read, so that this
+/// works even when it is missing or has errors.
+pub fn main(_: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let mut connected = Vec::with_capacity(res.outputs.len());
+    for &out in res.outputs.iter() {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        if info.connection == RandrConnection::CONNECTED {
+            connected.push((String::from_utf8_lossy(&info.name).into_owned(), out));
+        }
+    }
+    connected.sort();
+    let mut configs: Vec<(Output, MonConfig)> = Vec::with_capacity(connected.len());
+    for (name, out) in connected {
+        let previous = configs.last().map(|(_, conf)| conf.name.as_str());
+        let conf = reset_config(name, previous);
+        configs.push((out, conf));
+    }
+    // The screen is sized to fit the outputs once their modes are known
+    let single = SingleConfig {
+        name: RESET_LAYOUT.to_string(),
+        fb_size: None,
+        setup: HashMap::new(),
+        wildcard: None,
+        on_apply: None,
+        pre_switch: None,
+        post_switch: None,
+        allow_extra_monitors: false,
+        disable_extra_monitors: false,
+        priority: 0,
+        dpi: None,
+        dpms: None,
+    };
+    let setup: HashMap<_, _> = configs.iter().map(|(out, conf)| (*out, conf)).collect();
+    if apply_config(&conn, &res, &single, setup, root, true, || ())? {
+        println!("Monitor configuration: {}", single.name);
+    }
+    Ok(())
+}
@@ -0,0 +1,394 @@
+use clap::ArgMatches;
+use kdl::{parse_document, KdlValue};
+use miette::{IntoDiagnostic, Result};
+use thiserror::Error;
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::{randr::ConnectionExt as RandrExt, xproto::ConnectionExt as XprotoExt},
+};
+
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{BufRead, Write},
+    ops::Range,
+    path::Path,
+};
+
+use crate::{
+    config::Config, config::Monitor, config::STDIN_CONFIG, edid_atom, get_monitors, get_outputs,
+    refresh_rate,
+};
+
+use super::config_name;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Layout {0} already matches the connected monitors; use --overwrite to replace it")]
+    LayoutExists(String),
+    #[error("Layout {0} already exists; use --overwrite to replace it")]
+    NameExists(String),
+    #[error("No monitors with an EDID are connected")]
+    NoMonitors,
+    #[error("A layout needs a name")]
+    NoName,
+    #[error("A configuration read from stdin can not be saved to")]
+    StdinConfig,
+}
+
+/// The current state of a single output
+struct Current {
+    alias: String,
+    w: u16,
+    h: u16,
+    x: i16,
+    y: i16,
+    rate: Option<f64>,
+    primary: bool,
+}
+
+fn quote(s: &str) -> String {
+    KdlValue::String(s.to_string()).to_string()
+}
+
+/// The index just past the end of the string that starts at `i`, which is a plain string when
+/// `bytes[i]` is a quote, or a raw string when it is an `r` followed by hashes and a quote.
+fn skip_string(bytes: &[u8], i: usize) -> Option<usize> {
+    if bytes[i] == b'"' {
+        let mut j = i + 1;
+        while j < bytes.len() {
+            match bytes[j] {
+                b'\\' => j += 2,
+                b'"' => return Some(j + 1),
+                _ => j += 1,
+            }
+        }
+        return Some(bytes.len());
+    }
+    let hashes = bytes[i + 1..].iter().take_while(|&&b| b == b'#').count();
+    if bytes.get(i + 1 + hashes) != Some(&b'"') {
+        return None;
+    }
+    let mut j = i + 2 + hashes;
+    while j < bytes.len() {
+        if bytes[j] == b'"' && bytes[j + 1..].iter().take_while(|&&b| b == b'#').count() >= hashes {
+            return Some(j + 1 + hashes);
+        }
+        j += 1;
+    }
+    Some(bytes.len())
+}
+
+/// The byte ranges of the top-level nodes of a KDL document, so that a node can be removed
+/// without rewriting the comments and formatting around it. A comment at the end of a node's
+/// last line is part of the node.
+fn node_spans(text: &str) -> Vec<Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'/', Some(b'/')) => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            (b'/', Some(b'*')) => {
+                let mut nested = 0;
+                while i < bytes.len() {
+                    if bytes[i..].starts_with(b"/*") {
+                        nested += 1;
+                        i += 2;
+                    } else if bytes[i..].starts_with(b"*/") {
+                        nested -= 1;
+                        i += 2;
+                        if nested == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                continue;
+            }
+            (b'\n', _) | (b';', _) if depth == 0 => {
+                if let Some(start) = start.take() {
+                    spans.push(start..i);
+                }
+            }
+            (b, _) if b.is_ascii_whitespace() => (),
+            (b, _) => {
+                start.get_or_insert(i);
+                match b {
+                    b'"' | b'r' => {
+                        if let Some(end) = skip_string(bytes, i) {
+                            i = end;
+                            continue;
+                        }
+                    }
+                    b'{' => depth += 1,
+                    b'}' => depth = depth.saturating_sub(1),
+                    _ => (),
+                }
+            }
+        }
+        i += 1;
+    }
+    if let Some(start) = start {
+        spans.push(start..bytes.len());
+    }
+    spans
+}
+
+/// Remove the layouts with the given names from the text of a configuration, leaving every
+/// other line of it as it is.
+fn remove_layouts(text: &str, names: &[String]) -> String {
+    let mut text = text.to_string();
+    for span in node_spans(&text).into_iter().rev() {
+        let is_replaced = match parse_document(&text[span.clone()]) {
+            Ok(nodes) => nodes.iter().any(|n| {
+                n.name == "layout"
+                    && matches!(n.values.first(), Some(KdlValue::String(s)) if names.contains(s))
+            }),
+            Err(_) => false,
+        };
+        if !is_replaced {
+            continue;
+        }
+        // Remove the lines of the node, unless it shares them with other nodes
+        let line_start = text[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let start = if text[line_start..span.start].trim().is_empty() {
+            line_start
+        } else {
+            span.start
+        };
+        let end = match text.as_bytes().get(span.end) {
+            Some(b'\n') | Some(b';') => span.end + 1,
+            _ => span.end,
+        };
+        text.replace_range(start..end, "");
+    }
+    text
+}
+
+/// Ask for a layout name on stdin.
+fn prompt_name() -> Result<String> {
+    eprint!("Layout name: ");
+    std::io::stderr().flush().into_diagnostic()?;
+    let mut name = String::new();
+    std::io::stdin()
+        .lock()
+        .read_line(&mut name)
+        .into_diagnostic()?;
+    match name.trim() {
+        "" => Err(Error::NoName).into_diagnostic(),
+        name => Ok(name.to_string()),
+    }
+}
+
+/// Save the current layout of the connected monitors to the configuration file, or print it
+/// with `--print`.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config_name = config_name(args)?;
+    let config_name = config_name.as_str();
+    if config_name == STDIN_CONFIG {
+        return Err(Error::StdinConfig).into_diagnostic();
+    }
+    let text = match std::fs::read_to_string(config_name) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).into_diagnostic(),
+    };
+    let document = parse_document(&text).into_diagnostic()?;
+    let config = Config::from_text(&text, Path::new(config_name)).into_diagnostic()?;
+
+    // Monitors that are already described in the configuration keep their alias
+    let mut aliases = HashMap::new();
+    for node in document.iter().filter(|n| n.name == "monitor") {
+        if let Some(KdlValue::String(alias)) = node.values.first() {
+            let field = |f: &str| match node.properties.get(f) {
+                Some(KdlValue::String(s)) => Some(s.clone()),
+                _ => None,
+            };
+            let int = |f: &str| match node.properties.get(f) {
+                Some(KdlValue::Int(i)) => Some(*i),
+                _ => None,
+            };
+            let mon = Monitor {
+                product: field("product"),
+                serial: field("serial"),
+                connector: field("connector"),
+                year: int("year").map(|y| y as u16),
+                week: int("week").map(|w| w as u8),
+            };
+            aliases.insert(mon, alias.clone());
+        }
+    }
+
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let primary = conn
+        .randr_get_output_primary(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?
+        .output;
+    let geom = conn
+        .get_geometry(root)
+        .into_diagnostic()?
+        .reply()
+        .into_diagnostic()?;
+    let rates: HashMap<_, _> = res
+        .modes
+        .iter()
+        .map(|mi| (mi.id, refresh_rate(mi)))
+        .collect();
+
+    let mut new_monitors = String::new();
+    let mut matches = Vec::new();
+    let mut current = Vec::new();
+    let mut off = Vec::new();
+    for (out, mon) in get_monitors(&conn, &res.outputs, atom_edid) {
+        let mon = config.identify(mon);
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let alias = match aliases.get(&mon) {
+            Some(alias) => alias.clone(),
+            None => {
+                let connector = String::from_utf8_lossy(&info.name).into_owned();
+                let mut alias = connector.clone();
+                let mut n = 1;
+                while aliases.values().any(|a| a == &alias) {
+                    n += 1;
+                    alias = format!("{}-{}", connector, n);
+                }
+                write!(new_monitors, "monitor {}", quote(&alias)).unwrap();
+                if let Some(product) = &mon.product {
+                    write!(new_monitors, " product={}", quote(product)).unwrap();
+                }
+                if let Some(serial) = &mon.serial {
+                    write!(new_monitors, " serial={}", quote(serial)).unwrap();
+                }
+                if let Some(connector) = &mon.connector {
+                    write!(new_monitors, " connector={}", quote(connector)).unwrap();
+                }
+                if let Some(year) = mon.year {
+                    write!(new_monitors, " year={}", year).unwrap();
+                }
+                if let Some(week) = mon.week {
+                    write!(new_monitors, " week={}", week).unwrap();
+                }
+                new_monitors.push('\n');
+                aliases.insert(mon.clone(), alias.clone());
+                alias
+            }
+        };
+        matches.push((mon, alias.clone()));
+        let crtc = match info.crtc {
+            0 => None,
+            crtc => Some(
+                conn.randr_get_crtc_info(crtc, res.config_timestamp)
+                    .into_diagnostic()?
+                    .reply()
+                    .into_diagnostic()?,
+            ),
+        };
+        match crtc {
+            Some(crtc) if crtc.mode != 0 => current.push(Current {
+                alias,
+                w: crtc.width,
+                h: crtc.height,
+                x: crtc.x,
+                y: crtc.y,
+                rate: rates.get(&crtc.mode).copied(),
+                primary: out == primary,
+            }),
+            _ => off.push(alias),
+        }
+    }
+    if matches.is_empty() {
+        return Err(Error::NoMonitors).into_diagnostic();
+    }
+    matches.sort();
+
+    let mut text = text;
+    let overwrite = args.is_present("overwrite");
+    let mut replaced = Vec::new();
+    let monitors: Vec<_> = matches.iter().map(|(mon, _)| mon.clone()).collect();
+    if let Some(existing) = config.layouts.get(&monitors) {
+        if !overwrite {
+            return Err(Error::LayoutExists(existing.name.clone())).into_diagnostic();
+        }
+        replaced.push(existing.name.clone());
+    }
+    let name = match args.value_of("name") {
+        Some(name) => name.to_string(),
+        None => prompt_name()?,
+    };
+    if !replaced.contains(&name) && config.layout_named(&name).is_ok() {
+        if !overwrite {
+            return Err(Error::NameExists(name)).into_diagnostic();
+        }
+        replaced.push(name.clone());
+    }
+    if !replaced.is_empty() {
+        text = remove_layouts(&text, &replaced);
+    }
+
+    let mut layout = format!("layout {} {{\n    matches", quote(&name));
+    for (_, alias) in matches.iter() {
+        write!(layout, " {}", quote(alias)).unwrap();
+    }
+    layout.push('\n');
+    writeln!(layout, "    fb-size w={} h={}", geom.width, geom.height).unwrap();
+    for c in current.iter() {
+        write!(
+            layout,
+            "    monitor {} w={} h={} x={} y={}",
+            quote(&c.alias),
+            c.w,
+            c.h,
+            c.x,
+            c.y
+        )
+        .unwrap();
+        if let Some(rate) = c.rate {
+            write!(layout, " rate={:.2}", rate).unwrap();
+        }
+        if c.primary {
+            layout.push_str(" primary=true");
+        }
+        layout.push('\n');
+    }
+    for alias in off.iter() {
+        writeln!(layout, "    monitor {} state=\"off\"", quote(alias)).unwrap();
+    }
+    layout.push_str("}\n");
+
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(&new_monitors);
+    text.push_str(&layout);
+    // Make sure that what is written can be read back
+    Config::from_text(&text, Path::new(config_name)).into_diagnostic()?;
+    if args.is_present("print") {
+        print!("{}{}", new_monitors, layout);
+        return Ok(());
+    }
+    if let Some(dir) = Path::new(config_name).parent() {
+        std::fs::create_dir_all(dir).into_diagnostic()?;
+    }
+    std::fs::write(config_name, text).into_diagnostic()?;
+    println!("Saved layout {} to {}", name, config_name);
+    Ok(())
+}
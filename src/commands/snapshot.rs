@@ -0,0 +1,150 @@
+use log::info;
+use miette::{IntoDiagnostic, Result};
+use x11rb::{
+    connection::Connection,
+    protocol::randr::{
+        ConnectionExt as RandrExt, GetScreenResourcesCurrentReply, Output, SetCrtcConfigRequest,
+    },
+    protocol::xproto::{ConnectionExt as XprotoExt, Timestamp, Window},
+    CURRENT_TIME,
+};
+
+use std::collections::HashMap;
+
+use crate::config::Mode;
+
+use super::daemon::{batch_config, disable_crtc};
+
+/// The CRTCs, screen size and primary output as they were before a layout was applied, so that
+/// they can be restored when applying it fails partway through, or when a tested layout is not
+/// kept.
+pub(crate) struct Snapshot {
+    crtcs: Vec<SetCrtcConfigRequest<'static>>,
+    size: Mode,
+    mm_size: (u32, u32),
+    primary: Output,
+}
+
+impl Snapshot {
+    pub(crate) fn take<C: Connection>(
+        conn: &C,
+        res: &GetScreenResourcesCurrentReply,
+        root: Window,
+        timestamp: Timestamp,
+    ) -> Result<Self> {
+        let mut crtcs = Vec::with_capacity(res.crtcs.len());
+        for &crtc in res.crtcs.iter() {
+            let info = conn
+                .randr_get_crtc_info(crtc, timestamp)
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic()?;
+            crtcs.push(SetCrtcConfigRequest {
+                // The changes made since have moved the server's last change time forward
+                timestamp: CURRENT_TIME,
+                mode: info.mode,
+                outputs: info.outputs.clone().into(),
+                ..disable_crtc(crtc, &info)
+            });
+        }
+        let geom = conn
+            .get_geometry(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let size = Mode {
+            w: geom.width,
+            h: geom.height,
+        };
+        let screen_info = conn
+            .randr_get_screen_info(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        // Without a current size, the physical size is computed at 96 DPI, as the server does
+        let mm_size = match screen_info.sizes.get(screen_info.size_id as usize) {
+            Some(s) if s.width == size.w && s.height == size.h => {
+                (s.mwidth as u32, s.mheight as u32)
+            }
+            _ => (
+                (size.w as f64 * 25.4 / 96.0).round() as u32,
+                (size.h as f64 * 25.4 / 96.0).round() as u32,
+            ),
+        };
+        let primary = conn
+            .randr_get_output_primary(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?
+            .output;
+        Ok(Self {
+            crtcs,
+            size,
+            mm_size,
+            primary,
+        })
+    }
+
+    /// Put the CRTCs, screen size and primary output back as they were. CRTCs that changed are
+    /// disabled first, so that their outputs are free to return to the CRTCs they were on.
+    pub(crate) fn restore<C: Connection>(self, conn: &C, root: Window) -> Result<()> {
+        let mut disables = Vec::with_capacity(self.crtcs.len());
+        let mut enables = Vec::with_capacity(self.crtcs.len());
+        for req in self.crtcs {
+            let info = conn
+                .randr_get_crtc_info(req.crtc, CURRENT_TIME)
+                .into_diagnostic()?
+                .reply()
+                .into_diagnostic()?;
+            if (info.x, info.y, info.mode, info.rotation) == (req.x, req.y, req.mode, req.rotation)
+                && info.outputs[..] == req.outputs[..]
+            {
+                continue;
+            }
+            if info.mode != 0 {
+                disables.push(SetCrtcConfigRequest {
+                    timestamp: CURRENT_TIME,
+                    config_timestamp: req.config_timestamp,
+                    ..disable_crtc(req.crtc, &info)
+                });
+            }
+            if req.mode != 0 {
+                enables.push(req);
+            }
+        }
+        let geom = conn
+            .get_geometry(root)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let current = Mode {
+            w: geom.width,
+            h: geom.height,
+        };
+        let (mm_w, mm_h) = self.mm_size;
+        // Mode names are only needed to print changes rather than make them
+        let mode_names = HashMap::new();
+        info!("Restoring the previous layout");
+        batch_config(conn, disables, true, &mode_names)?;
+        // The screen must fit the CRTCs as they were before they are enabled again
+        let union = current.union(&self.size);
+        if union != current {
+            conn.randr_set_screen_size(root, union.w, union.h, mm_w, mm_h)
+                .into_diagnostic()?
+                .check()
+                .into_diagnostic()?;
+        }
+        batch_config(conn, enables, true, &mode_names)?;
+        if union != self.size {
+            conn.randr_set_screen_size(root, self.size.w, self.size.h, mm_w, mm_h)
+                .into_diagnostic()?
+                .check()
+                .into_diagnostic()?;
+        }
+        conn.randr_set_output_primary(root, self.primary)
+            .into_diagnostic()?
+            .check()
+            .into_diagnostic()?;
+        Ok(())
+    }
+}
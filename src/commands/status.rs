@@ -0,0 +1,359 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::{
+        randr::{ConnectionExt as RandrExt, Rotation},
+        xproto::Window,
+    },
+};
+
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+};
+
+use crate::{
+    config::{resolve_positions, Config, Mode, ModeSpec, Monitor, Position, Region, SingleConfig},
+    edid_atom, get_monitors, get_outputs, refresh_rate,
+};
+
+use super::{check, diagram::diagram};
+
+/// What an output is currently displaying
+#[derive(Debug, Clone)]
+pub(crate) struct OutputState {
+    pub mode: Mode,
+    pub rate: f64,
+    pub position: Position,
+    /// The rotation and reflection bits of the output's CRTC
+    pub rotation: u16,
+}
+
+/// Read the state of every output with a monitor attached, by monitor. Monitors that are
+/// attached but turned off map to `None`.
+pub(crate) fn current_state<C: Connection>(
+    conn: &C,
+    root: Window,
+) -> Result<HashMap<Monitor, Option<OutputState>>> {
+    let atom_edid = edid_atom(conn).into_diagnostic()?;
+    let res = get_outputs(conn, root).into_diagnostic()?;
+    let rates: HashMap<_, _> = res
+        .modes
+        .iter()
+        .map(|mi| (mi.id, refresh_rate(mi)))
+        .collect();
+    let mut state = HashMap::new();
+    for (out, mon) in get_monitors(conn, &res.outputs, atom_edid) {
+        let info = conn
+            .randr_get_output_info(out, res.config_timestamp)
+            .into_diagnostic()?
+            .reply()
+            .into_diagnostic()?;
+        let crtc = match info.crtc {
+            0 => None,
+            crtc => Some(
+                conn.randr_get_crtc_info(crtc, res.config_timestamp)
+                    .into_diagnostic()?
+                    .reply()
+                    .into_diagnostic()?,
+            ),
+        };
+        let output = crtc.filter(|c| c.mode != 0).map(|c| OutputState {
+            mode: Mode {
+                w: c.width,
+                h: c.height,
+            },
+            rate: rates.get(&c.mode).copied().unwrap_or_default(),
+            position: Position { x: c.x, y: c.y },
+            rotation: c.rotation,
+        });
+        state.insert(mon, output);
+    }
+    Ok(state)
+}
+
+/// One way in which the monitors differ from a layout, naming the monitor by its alias when
+/// the layout has one
+#[derive(Debug, Clone)]
+pub(crate) enum Difference {
+    /// A monitor that the layout matches is not connected
+    Missing(String),
+    /// A monitor is on, though the layout turns it off or does not show it
+    On(String),
+    /// A monitor that the layout shows is off
+    Off(String),
+    /// A monitor displays a mode other than the layout's, as (expected, found)
+    Mode(String, Mode, Mode),
+    /// A monitor displays a refresh rate other than the layout's, as (expected, found)
+    Rate(String, f64, f64),
+    /// A monitor is placed elsewhere than in the layout, as (expected, found)
+    Position(String, Position, Position),
+    /// A monitor is rotated or reflected, which a layout never does, with its rotation bits
+    Rotation(String, u16),
+    /// The screen has a size other than the layout's, as (expected, found)
+    Screen(Mode, Mode),
+    /// The relative positions of the layout cannot be resolved with the modes displayed
+    Unresolved(String),
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Difference::Missing(name) => write!(f, "monitor {} is not connected", name),
+            Difference::On(name) => write!(f, "monitor {} is on, but should be off", name),
+            Difference::Off(name) => write!(f, "monitor {} is off, but should be on", name),
+            Difference::Mode(name, expected, found) => write!(
+                f,
+                "monitor {} displays {}, but should display {}",
+                name, found, expected
+            ),
+            Difference::Rate(name, expected, found) => write!(
+                f,
+                "monitor {} refreshes at {:.2}Hz, but should refresh at {}Hz",
+                name, found, expected
+            ),
+            Difference::Position(name, expected, found) => write!(
+                f,
+                "monitor {} is at {},{}, but should be at {},{}",
+                name, found.x, found.y, expected.x, expected.y
+            ),
+            Difference::Rotation(name, rotation) => {
+                write!(f, "monitor {} is", name)?;
+                let names = [
+                    (Rotation::ROTATE90, " rotated left"),
+                    (Rotation::ROTATE180, " inverted"),
+                    (Rotation::ROTATE270, " rotated right"),
+                    (Rotation::REFLECT_X, " reflected in x"),
+                    (Rotation::REFLECT_Y, " reflected in y"),
+                ];
+                for (bit, name) in names.iter() {
+                    if rotation & u16::from(*bit) != 0 {
+                        write!(f, "{}", name)?;
+                    }
+                }
+                write!(f, ", but should not be")
+            }
+            Difference::Screen(expected, found) => {
+                write!(f, "the screen is {}, but should be {}", found, expected)
+            }
+            Difference::Unresolved(reason) => {
+                write!(f, "the monitors cannot be positioned: {}", reason)
+            }
+        }
+    }
+}
+
+/// List how the monitors differ from a layout, given the monitors the layout matches by name.
+/// This is the inverse of applying a layout: the configured modes and positions are compared
+/// against those read from the server.
+pub(crate) fn differences(
+    matches: &[Monitor],
+    single: &SingleConfig,
+    state: &HashMap<Monitor, Option<OutputState>>,
+) -> Vec<Difference> {
+    let mut configs: Vec<_> = state
+        .iter()
+        .map(|(mon, current)| (mon, single.monitor_config(matches, mon), current.as_ref()))
+        .collect();
+    // Sorted so that the differences are listed in a stable order
+    configs.sort_by_key(|(mon, _, _)| *mon);
+    let mut out: Vec<_> = matches
+        .iter()
+        .filter(|mon| !state.contains_key(mon))
+        .map(|mon| {
+            let name = single.setup.get(mon).map(|c| c.name.clone());
+            Difference::Missing(name.unwrap_or_else(|| mon.to_string()))
+        })
+        .collect();
+    // Relative positions are resolved with the modes the monitors are displaying
+    let sizes: HashMap<_, _> = configs
+        .iter()
+        .filter_map(|(_, conf, current)| Some(((*conf)?.name.as_str(), (*current)?.mode.clone())))
+        .collect();
+    let positions = match resolve_positions(configs.iter().filter_map(|(_, conf, _)| *conf), |m| {
+        sizes.get(m.name.as_str()).cloned()
+    }) {
+        Ok(positions) => positions,
+        Err(e) => {
+            out.push(Difference::Unresolved(e.to_string()));
+            return out;
+        }
+    };
+    for (mon, conf, current) in configs {
+        match (conf, current) {
+            (None, None) => (),
+            (None, Some(_)) => out.push(Difference::On(mon.to_string())),
+            (Some(conf), current) if !conf.enabled => {
+                if current.is_some() {
+                    out.push(Difference::On(conf.name.clone()));
+                }
+            }
+            (Some(conf), None) => out.push(Difference::Off(conf.name.clone())),
+            (Some(conf), Some(current)) => {
+                let name = &conf.name;
+                // A clone may display a smaller mode than the monitor it clones
+                match &conf.mode {
+                    ModeSpec::Exact(mode) if *mode != current.mode && conf.clone_of.is_none() => {
+                        out.push(Difference::Mode(
+                            name.clone(),
+                            mode.clone(),
+                            current.mode.clone(),
+                        ))
+                    }
+                    _ => (),
+                }
+                if let Some(rate) = conf.rate.filter(|rate| (rate - current.rate).abs() > 1.0) {
+                    out.push(Difference::Rate(name.clone(), rate, current.rate));
+                }
+                let position = positions[conf.clone_of.as_ref().unwrap_or(name)];
+                if position != current.position {
+                    out.push(Difference::Position(
+                        name.clone(),
+                        position,
+                        current.position,
+                    ));
+                }
+                if current.rotation != u16::from(Rotation::ROTATE0) {
+                    out.push(Difference::Rotation(name.clone(), current.rotation));
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Check whether the monitors are displaying a layout, given the monitors the layout matches by
+/// name.
+pub(crate) fn is_active(
+    matches: &[Monitor],
+    single: &SingleConfig,
+    state: &HashMap<Monitor, Option<OutputState>>,
+) -> bool {
+    differences(matches, single, state).is_empty()
+}
+
+/// Draw the monitors that are on, each labelled with its alias, when the configuration has one,
+/// and its mode.
+fn print_diagram(config: &Config, state: &HashMap<Monitor, Option<OutputState>>) {
+    let mut regions: Vec<_> = state
+        .iter()
+        .filter_map(|(mon, output)| {
+            let output = output.as_ref()?;
+            let alias = config
+                .aliases
+                .iter()
+                .find(|(_, aliased)| *aliased == mon)
+                .map_or_else(|| mon.to_string(), |(alias, _)| alias.clone());
+            let region = Region {
+                position: output.position,
+                size: output.mode.clone(),
+            };
+            Some((vec![alias, output.mode.to_string()], region))
+        })
+        .collect();
+    // Sorted so that overlapping monitors are always drawn in the same order
+    regions.sort_by(|(a, _), (b, _)| a.cmp(b));
+    print!("{}", diagram(&regions));
+}
+
+/// Print the name of the layout the monitors are currently displaying, and with `--diagram`,
+/// a drawing of the monitors.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let state: HashMap<_, _> = current_state(&conn, root)?
+        .into_iter()
+        .map(|(mon, output)| (config.identify(mon), output))
+        .collect();
+    let mut connected: Vec<_> = state.keys().cloned().collect();
+    connected.sort();
+    let layout = match config.lookup(&connected) {
+        Some((matches, single)) => Some((matches.as_slice(), single)),
+        None => config.fallback.as_ref().map(|single| (&[][..], single)),
+    };
+    let active = layout.filter(|(matches, single)| is_active(matches, single, &state));
+    match active {
+        Some((_, single)) => println!("{}", single.name),
+        None => println!("{}", args.value_of("none").unwrap_or("none")),
+    }
+    if args.is_present("diagram") {
+        print_diagram(&config, &state);
+    }
+    if active.is_none() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The only layout of a configuration with the laptop and desk monitors, where the desk
+    /// monitor is placed at `desk_position`.
+    fn layout(desk_position: &str) -> (Vec<Monitor>, SingleConfig) {
+        let text = format!(
+            r#"
+monitor "laptop" product="Thinkpad LCD"
+monitor "desk" product="DELL U2715H"
+layout "docked" {{
+    matches "laptop" "desk"
+    monitor "laptop" w=1920 h=1080 x=0 y=0
+    monitor "desk" w=2560 h=1440 {}
+}}
+"#,
+            desk_position
+        );
+        let config: Config = text.parse().unwrap();
+        config.layouts.into_iter().next().unwrap()
+    }
+
+    fn state_with_desk_at(desk_position: Position) -> HashMap<Monitor, Option<OutputState>> {
+        let output = |w, h, position| OutputState {
+            mode: Mode { w, h },
+            rate: 60.0,
+            position,
+            rotation: u16::from(Rotation::ROTATE0),
+        };
+        let mon = |product: &str| Monitor {
+            product: Some(product.to_string()),
+            serial: None,
+            connector: None,
+            year: None,
+            week: None,
+        };
+        let mut state = HashMap::new();
+        state.insert(
+            mon("Thinkpad LCD"),
+            Some(output(1920, 1080, Position { x: 0, y: 0 })),
+        );
+        state.insert(mon("DELL U2715H"), Some(output(2560, 1440, desk_position)));
+        state
+    }
+
+    #[test]
+    fn layouts_differ_by_position() {
+        let (beside_matches, beside) = layout("x=1920 y=0");
+        let (below_matches, below) = layout("below=\"laptop\"");
+
+        let state = state_with_desk_at(Position { x: 1920, y: 0 });
+        assert!(is_active(&beside_matches, &beside, &state));
+        let found = differences(&below_matches, &below, &state);
+        assert_eq!(found.len(), 1);
+        match &found[0] {
+            Difference::Position(name, expected, found) => {
+                assert_eq!(name, "desk");
+                assert_eq!(*expected, Position { x: 0, y: 1080 });
+                assert_eq!(*found, Position { x: 1920, y: 0 });
+            }
+            other => panic!("expected a position difference, got {}", other),
+        }
+
+        let state = state_with_desk_at(Position { x: 0, y: 1080 });
+        assert!(is_active(&below_matches, &below, &state));
+        assert!(!is_active(&beside_matches, &beside, &state));
+    }
+}
@@ -0,0 +1,11 @@
+use clap::ArgMatches;
+use miette::Result;
+
+use super::apply::apply_layout;
+
+/// Apply the layout named on the command line, whether or not it matches the connected monitors.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    // PROFILE is required
+    let profile = args.value_of("profile").unwrap();
+    apply_layout(args, Some(profile), args.is_present("force"))
+}
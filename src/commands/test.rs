@@ -0,0 +1,65 @@
+use clap::{value_t, ArgMatches};
+use miette::{IntoDiagnostic, Result};
+use x11rb::{connect, connection::Connection};
+
+use std::{
+    io::{BufRead, Write},
+    os::unix::io::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use crate::{edid_atom, get_outputs};
+
+use super::{
+    apply::named_layout,
+    check,
+    daemon::{apply_config, wait_readable},
+    snapshot::Snapshot,
+};
+
+/// Whether a line is entered on stdin before `timeout` passes. The end of stdin is no answer.
+fn confirmed(timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let stdin = std::io::stdin();
+    wait_readable(&[stdin.as_raw_fd()], Some(timeout));
+    if Instant::now() >= deadline {
+        return false;
+    }
+    let mut line = String::new();
+    matches!(stdin.lock().read_line(&mut line), Ok(n) if n > 0)
+}
+
+/// Apply the layout named on the command line, then restore the previous layout unless Enter
+/// is pressed before `--timeout` seconds pass, so that a layout that shows nothing undoes
+/// itself. Hooks are not run, as the layout may not be kept.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    // PROFILE is required
+    let profile = args.value_of("profile").unwrap();
+    let timeout = Duration::from_secs(value_t!(args, "timeout", u64).unwrap_or_else(|e| e.exit()));
+    let config = check(args)?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    let res = get_outputs(&conn, root).into_diagnostic()?;
+    let force = args.is_present("force");
+    let (single, setup) = named_layout(&config, &conn, &res, atom_edid, profile, force)?;
+    let snapshot = Snapshot::take(&conn, &res, root, res.config_timestamp)?;
+    if !apply_config(&conn, &res, single, setup, root, true, || ())? {
+        println!("Monitor configuration {} is already applied", single.name);
+        return Ok(());
+    }
+    print!(
+        "Keep monitor configuration {}? Press Enter within {} seconds, or the previous layout is restored: ",
+        single.name,
+        timeout.as_secs()
+    );
+    std::io::stdout().flush().into_diagnostic()?;
+    if confirmed(timeout) {
+        println!("Monitor configuration: {}", single.name);
+    } else {
+        println!();
+        snapshot.restore(&conn, root)?;
+        println!("Restored the previous layout");
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use x11rb::{connect, connection::Connection};
+
+use std::collections::HashMap;
+
+use super::{
+    check,
+    status::{current_state, differences},
+};
+
+/// Check whether the monitors are displaying the layout named by `--profile`, exiting with
+/// status 1 when they are not. With `--verbose`, each difference is printed.
+pub fn main(args: &ArgMatches<'_>) -> Result<()> {
+    let config = check(args)?;
+    // The profile is a required argument
+    let profile = args.value_of("profile").unwrap();
+    let (matches, single) = config.layout_named(profile).into_diagnostic()?;
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let state: HashMap<_, _> = current_state(&conn, root)?
+        .into_iter()
+        .map(|(mon, output)| (config.identify(mon), output))
+        .collect();
+    let differences = differences(matches, single, &state);
+    if differences.is_empty() {
+        return Ok(());
+    }
+    if args.is_present("verbose") {
+        for difference in differences {
+            println!("{}", difference);
+        }
+    }
+    std::process::exit(1);
+}
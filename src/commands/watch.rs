@@ -0,0 +1,124 @@
+use clap::ArgMatches;
+use miette::{IntoDiagnostic, Result};
+use x11rb::{
+    connect,
+    connection::Connection,
+    protocol::{
+        randr::{Connection as RandrConnection, ConnectionExt as RandrExt, Crtc, Notify, Output},
+        xproto::{Atom, ConnectionExt as XprotoExt, Property, Window},
+        Event,
+    },
+    CURRENT_TIME,
+};
+
+use crate::{edid_atom, get_monitors, get_outputs};
+
+use super::daemon::{notify_mask, setup_notify};
+
+/// The name of an output, or its number when it cannot be read
+fn output_name<C: Connection>(conn: &C, output: Output) -> String {
+    match conn
+        .randr_get_output_info(output, CURRENT_TIME)
+        .map(|cookie| cookie.reply())
+    {
+        Ok(Ok(info)) => String::from_utf8_lossy(&info.name).into_owned(),
+        _ => format!("output {}", output),
+    }
+}
+
+/// The name of an atom, or its number when it cannot be read
+fn atom_name<C: Connection>(conn: &C, atom: Atom) -> String {
+    match conn.get_atom_name(atom).map(|cookie| cookie.reply()) {
+        Ok(Ok(reply)) => String::from_utf8_lossy(&reply.name).into_owned(),
+        _ => format!("atom {}", atom),
+    }
+}
+
+fn connection_name(connection: RandrConnection) -> &'static str {
+    match connection {
+        RandrConnection::CONNECTED => "connected",
+        RandrConnection::DISCONNECTED => "disconnected",
+        _ => "unknown",
+    }
+}
+
+/// Describe a RandR event with its server timestamp, or `None` for other events.
+fn describe<C: Connection>(conn: &C, event: &Event) -> Option<String> {
+    let crtc = |crtc: Crtc| match crtc {
+        0 => "no CRTC".to_string(),
+        crtc => format!("CRTC {}", crtc),
+    };
+    match event {
+        Event::RandrScreenChangeNotify(e) => Some(format!(
+            "[{}] screen change: {}x{} ({}x{}mm), rotation {}",
+            e.timestamp, e.width, e.height, e.mwidth, e.mheight, e.rotation
+        )),
+        Event::RandrNotify(e) if e.sub_code == Notify::OUTPUT_CHANGE => {
+            let oc = e.u.as_oc();
+            Some(format!(
+                "[{}] output change: {} {}, {}, mode {}",
+                oc.timestamp,
+                output_name(conn, oc.output),
+                connection_name(oc.connection),
+                crtc(oc.crtc),
+                oc.mode
+            ))
+        }
+        Event::RandrNotify(e) if e.sub_code == Notify::CRTC_CHANGE => {
+            let cc = e.u.as_cc();
+            Some(format!(
+                "[{}] CRTC change: {}, mode {}, {}x{}+{}+{}, rotation {}",
+                cc.timestamp,
+                crtc(cc.crtc),
+                cc.mode,
+                cc.width,
+                cc.height,
+                cc.x,
+                cc.y,
+                cc.rotation
+            ))
+        }
+        Event::RandrNotify(e) if e.sub_code == Notify::OUTPUT_PROPERTY => {
+            let op = e.u.as_op();
+            let status = match op.status {
+                Property::DELETE => "deleted",
+                _ => "changed",
+            };
+            Some(format!(
+                "[{}] output property: {} {} {}",
+                op.timestamp,
+                output_name(conn, op.output),
+                atom_name(conn, op.atom),
+                status
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// The connected monitors, as the daemon would read them after an event
+fn monitors<C: Connection>(conn: &C, root: Window, atom_edid: Atom) -> Result<String> {
+    let res = get_outputs(conn, root).into_diagnostic()?;
+    let mut monitors: Vec<_> = get_monitors(conn, &res.outputs, atom_edid)
+        .map(|(_, mon)| mon.to_string())
+        .collect();
+    monitors.sort();
+    Ok(monitors.join(", "))
+}
+
+/// Print each RandR event that the daemon reacts to, followed by the connected monitors after
+/// it, without applying any layout.
+pub fn main(_: &ArgMatches<'_>) -> Result<()> {
+    let (conn, screen_num) = connect(None).into_diagnostic()?;
+    let root = conn.setup().roots[screen_num].root;
+    let atom_edid = edid_atom(&conn).into_diagnostic()?;
+    setup_notify(&conn, root, notify_mask())?;
+    println!("monitors: {}", monitors(&conn, root, atom_edid)?);
+    loop {
+        let event = conn.wait_for_event().into_diagnostic()?;
+        if let Some(description) = describe(&conn, &event) {
+            println!("{}", description);
+            println!("    monitors: {}", monitors(&conn, root, atom_edid)?);
+        }
+    }
+}
@@ -1,18 +1,24 @@
 //! Parser for the monitor-layout(5) configuration file
 use edid::{Descriptor, EDID};
-use kdl::{parse_document, KdlError, KdlNode as Node, KdlValue};
+use kdl::{parse_document, KdlError, KdlErrorKind, KdlNode as Node, KdlValue};
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use std::{
-    cmp::max,
-    collections::HashMap,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
     convert::TryFrom,
     fmt::{Display, Formatter},
     io::{Error as IoError, Read},
     num::ParseIntError,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+    time::Duration,
 };
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Diagnostic)]
 pub enum Error {
     #[error("{0} is missing its {1} field")]
     MissingField(&'static str, &'static str),
@@ -26,12 +32,89 @@ pub enum Error {
     NodeTypeMismatch(&'static str, String),
     #[error("Parse Error")]
     ParseError(#[from] KdlError),
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Syntax(#[from] SyntaxError),
     #[error("Duplicate singleton node {0}")]
     DuplicateSingleton(&'static str),
     #[error("Unexpected node {0}")]
     Unexpected(String),
     #[error("Io Error")]
     Io(#[from] IoError),
+    #[error("Invalid mode {0}; expected \"preferred\"")]
+    InvalidMode(String),
+    #[error("Invalid mode {0}; expected \"WIDTHxHEIGHT\" or \"WIDTHxHEIGHT@RATE\"")]
+    InvalidModeString(String),
+    #[error("Invalid position {0}; expected \"X,Y\" or \"X+Y\"")]
+    InvalidPosition(String),
+    #[error("monitor {1} in layout {0} clones a monitor that is not shown in the layout")]
+    InvalidClone(String, String),
+    #[error("Invalid state {0}; expected \"on\" or \"off\"")]
+    InvalidState(String),
+    #[error("Invalid match {0}; expected \"exact\" or \"subset\"")]
+    InvalidMatch(String),
+    #[error("{0} may not have both a {1} and a {2} field")]
+    ConflictingFields(&'static str, &'static str, &'static str),
+    #[error("Relative positions form a cycle: {0}")]
+    RelativeCycle(String),
+    #[error("{0} is positioned relative to {1}, which is not shown in the layout")]
+    RelativeTo(String, String),
+    #[error("Invalid gap {0}; expected a number of pixels from 0 to 32767")]
+    InvalidGap(i64),
+    #[error("{0} has a gap but is not positioned relative to another monitor")]
+    GapWithoutRelative(String),
+    #[error("The size of {0} is only known once the layout is applied")]
+    UnknownSize(String),
+    #[error("layout {0} matches more than one wildcard monitor")]
+    MultipleWildcards(String),
+    #[error("layouts {0} and {1} are both fallback layouts")]
+    MultipleFallbacks(String, String),
+    #[error("layout {0} is defined more than once")]
+    DuplicateLayout(String),
+    #[error("monitor {0} is defined more than once")]
+    DuplicateMonitor(String),
+    #[error("layouts {0} and {1} match the same monitors")]
+    SameMatches(String, String),
+    #[error("{0}, in {1} and {2}")]
+    InFiles(Box<Error>, String, String),
+    #[error("{0} includes itself")]
+    IncludeCycle(String),
+    #[error("layout {0} has more than one primary monitor")]
+    MultiplePrimaries(String),
+    #[error("Invalid scale {0}; expected a positive number or a pair of them, as \"x,y\"")]
+    InvalidScale(String),
+    #[error("Invalid gamma {0}; expected a positive number or three of them, as \"r:g:b\"")]
+    InvalidGamma(String),
+    #[error("Invalid brightness {0}; expected a number that is not negative")]
+    InvalidBrightness(f64),
+    #[error("monitor {1} in layout {0} pans across an area smaller than its mode")]
+    PanningTooSmall(String, String),
+    #[error("monitor {1} in layout {0} extends to {2}, past the fb-size of {3}")]
+    OutsideFbSize(String, String, Mode, Mode),
+    #[error("Invalid modeline flag {0}; expected one of {}", MODELINE_FLAGS.join(", "))]
+    InvalidModelineFlag(String),
+    #[error("monitor {0} has a mode of {1}, but its modeline is {2}")]
+    ModelineSize(String, Mode, Mode),
+    #[error("monitor {1} appears more than once in layout {0}")]
+    DuplicateLayoutMonitor(String, String),
+    #[error("monitor {0} sets property {1} more than once")]
+    DuplicateProperty(String, String),
+    #[error("Invalid DPMS timeout {1} for {0}; expected a number of seconds from 0 to 65535")]
+    InvalidDpmsTimeout(&'static str, i64),
+    #[error("layout {0} has a DPMS {1} timeout longer than its {2} timeout")]
+    DpmsOrder(String, &'static str, &'static str),
+    #[error("layout {0} has a dpi of {1}; expected a positive number")]
+    InvalidDpi(String, f64),
+    #[error("layout {0} matches a wildcard monitor, so it may not allow extra monitors")]
+    WildcardWithExtras(String),
+    #[error("No configuration file given, and neither XDG_CONFIG_HOME nor HOME is set")]
+    NoDefaultPath,
+    #[error("No configuration files given")]
+    NoConfigFiles,
+    #[error("No layout named {0}")]
+    UnknownLayout(String),
+    #[error("Invalid {0} of manufacture {1}")]
+    InvalidManufacture(&'static str, i64),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -41,14 +124,89 @@ trait FromNode: Sized {
 }
 
 /// A position, expressed an <x>x<y>
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub struct Position {
     pub x: i16,
     pub y: i16,
 }
 
+/// Parse a position written as "X,Y", or as "X+Y" as in xrandr geometries. Either offset may be
+/// negative.
+///
+/// ```
+/// use monitor_layout::config::Position;
+///
+/// assert_eq!("1920,0".parse::<Position>().unwrap(), Position { x: 1920, y: 0 });
+/// assert_eq!("0+1080".parse::<Position>().unwrap(), Position { x: 0, y: 1080 });
+/// assert_eq!("-1920,-40".parse::<Position>().unwrap(), Position { x: -1920, y: -40 });
+/// assert_eq!("-1920+-40".parse::<Position>().unwrap(), Position { x: -1920, y: -40 });
+/// assert!("1920".parse::<Position>().is_err());
+/// assert!("1920,left".parse::<Position>().is_err());
+/// assert!("1,2,3".parse::<Position>().is_err());
+/// ```
+impl FromStr for Position {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self> {
+        let invalid = || Error::InvalidPosition(text.to_string());
+        // The sign of x is not a separator
+        let split = text
+            .find(',')
+            .or_else(|| text.get(1..)?.find('+').map(|i| i + 1))
+            .ok_or_else(invalid)?;
+        let (x, y) = (&text[..split], &text[split + 1..]);
+        let x = x.trim().parse().map_err(|_| invalid())?;
+        let y = y.trim().parse().map_err(|_| invalid())?;
+        Ok(Self { x, y })
+    }
+}
+
+/// Where a monitor is placed relative to another monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    LeftOf,
+    RightOf,
+    Above,
+    Below,
+}
+
+impl Direction {
+    const ALL: [(&'static str, Direction); 4] = [
+        ("left-of", Direction::LeftOf),
+        ("right-of", Direction::RightOf),
+        ("above", Direction::Above),
+        ("below", Direction::Below),
+    ];
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        // Every direction is in ALL
+        let (name, _) = Direction::ALL.iter().find(|(_, d)| d == self).unwrap();
+        write!(f, "{}", name)
+    }
+}
+
+/// A position relative to another monitor of the same layout, by alias
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relative {
+    pub direction: Direction,
+    pub of: String,
+    /// The pixels left between the two monitors
+    pub gap: u16,
+}
+
+impl Display for Relative {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{} {}", self.direction, self.of)?;
+        if self.gap != 0 {
+            write!(f, " gap {}", self.gap)?;
+        }
+        Ok(())
+    }
+}
+
 /// A monitor mode, expressed an <w>x<h>
-#[derive(Debug, Hash, PartialEq, Eq, Clone)]
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Serialize)]
 pub struct Mode {
     pub w: u16,
     pub h: u16,
@@ -62,6 +220,36 @@ impl Mode {
             h: std::cmp::max(self.h, other.h),
         }
     }
+
+    /// Parse a mode written as "WIDTHxHEIGHT", optionally followed by "@RATE", a refresh rate
+    /// in Hz, returning the mode and the rate.
+    ///
+    /// ```
+    /// use monitor_layout::config::Mode;
+    ///
+    /// let (mode, rate) = Mode::parse_with_rate("2560x1440@144").unwrap();
+    /// assert_eq!(mode, Mode { w: 2560, h: 1440 });
+    /// assert_eq!(rate, Some(144.0));
+    /// assert_eq!(Mode::parse_with_rate("1920x1080").unwrap().1, None);
+    /// assert!(Mode::parse_with_rate("1920@60").is_err());
+    /// ```
+    pub fn parse_with_rate(text: &str) -> Result<(Self, Option<f64>)> {
+        let invalid = || Error::InvalidModeString(text.to_string());
+        let (size, rate) = match text.split_once('@') {
+            Some((size, rate)) => {
+                let rate: f64 = rate.trim().parse().map_err(|_| invalid())?;
+                if !(rate.is_finite() && rate > 0.0) {
+                    return Err(invalid());
+                }
+                (size, Some(rate))
+            }
+            None => (text, None),
+        };
+        let (w, h) = size.split_once('x').ok_or_else(invalid)?;
+        let w = w.trim().parse().map_err(|_| invalid())?;
+        let h = h.trim().parse().map_err(|_| invalid())?;
+        Ok((Self { w, h }, rate))
+    }
 }
 
 impl Display for Mode {
@@ -70,10 +258,491 @@ impl Display for Mode {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Parse a mode written as "WIDTHxHEIGHT". A refresh rate, as in "WIDTHxHEIGHT@RATE", is
+/// checked and then dropped; see [`Mode::parse_with_rate`] to keep it.
+///
+/// ```
+/// use monitor_layout::config::Mode;
+///
+/// assert_eq!("1920x1080".parse::<Mode>().unwrap(), Mode { w: 1920, h: 1080 });
+/// assert_eq!("1920x1080@60".parse::<Mode>().unwrap(), Mode { w: 1920, h: 1080 });
+/// assert!("1920-1080".parse::<Mode>().is_err());
+/// assert!("1920xwide".parse::<Mode>().is_err());
+/// assert!("1920x1080@fast".parse::<Mode>().is_err());
+/// ```
+impl FromStr for Mode {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self> {
+        Self::parse_with_rate(text).map(|(mode, _)| mode)
+    }
+}
+
+/// How much larger a monitor's image is than its mode, horizontally and vertically
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Scale {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Scale {
+    /// The size that a mode takes up on the screen once scaled
+    pub fn apply(&self, mode: &Mode) -> Mode {
+        Mode {
+            w: (mode.w as f64 * self.x).round() as u16,
+            h: (mode.h as f64 * self.y).round() as u16,
+        }
+    }
+}
+
+impl Display for Scale {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        if self.x == self.y {
+            write!(f, "{}", self.x)
+        } else {
+            write!(f, "{},{}", self.x, self.y)
+        }
+    }
+}
+
+/// An area of the screen, larger than a monitor's mode, that the monitor pans across as the
+/// pointer moves, as with `xrandr --panning`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Panning {
+    pub size: Mode,
+    /// The top left corner of the area, which defaults to the position of the monitor
+    pub position: Option<Position>,
+    /// The area of the screen in which the pointer pans the monitor, which defaults to the
+    /// whole screen
+    pub tracking: Option<Region>,
+}
+
+impl Panning {
+    /// The top left corner of the area, given the position of the monitor.
+    pub fn origin(&self, monitor: Position) -> Position {
+        self.position.unwrap_or(monitor)
+    }
+}
+
+impl FromNode for Panning {
+    fn from_node(n: &Node) -> Result<Self> {
+        let w = extract_int_value(n, "w", "layout.monitor.panning")? as u16;
+        let h = extract_int_value(n, "h", "layout.monitor.panning")? as u16;
+        let position = match (
+            n.properties.contains_key("x"),
+            n.properties.contains_key("y"),
+        ) {
+            (false, false) => None,
+            _ => Some(Position {
+                x: extract_int_value(n, "x", "layout.monitor.panning")? as i16,
+                y: extract_int_value(n, "y", "layout.monitor.panning")? as i16,
+            }),
+        };
+        let tracking = match ["tracking-w", "tracking-h", "tracking-x", "tracking-y"]
+            .iter()
+            .any(|field| n.properties.contains_key(*field))
+        {
+            false => None,
+            true => Some(Region {
+                position: Position {
+                    x: extract_int_value(n, "tracking-x", "layout.monitor.panning")? as i16,
+                    y: extract_int_value(n, "tracking-y", "layout.monitor.panning")? as i16,
+                },
+                size: Mode {
+                    w: extract_int_value(n, "tracking-w", "layout.monitor.panning")? as u16,
+                    h: extract_int_value(n, "tracking-h", "layout.monitor.panning")? as u16,
+                },
+            }),
+        };
+        Ok(Self {
+            size: Mode { w, h },
+            position,
+            tracking,
+        })
+    }
+}
+
+/// The DPMS timeouts that a layout sets, in seconds, as with `xset dpms`. A timeout of 0
+/// disables its stage, and a timeout that is not given is left as it is. DPMS applies to the
+/// whole screen, so these belong to a layout rather than to one of its monitors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Dpms {
+    pub standby: Option<u16>,
+    pub suspend: Option<u16>,
+    pub off: Option<u16>,
+}
+
+impl Dpms {
+    /// The timeouts to set, as (standby, suspend, off), given the current ones.
+    pub fn timeouts(&self, current: (u16, u16, u16)) -> (u16, u16, u16) {
+        (
+            self.standby.unwrap_or(current.0),
+            self.suspend.unwrap_or(current.1),
+            self.off.unwrap_or(current.2),
+        )
+    }
+
+    /// The first pair of stages, as (earlier, later), whose timeouts are both set and enabled
+    /// but out of order. The X server refuses to set them.
+    pub fn out_of_order(&self) -> Option<(&'static str, &'static str)> {
+        let stages = [
+            ("standby", self.standby),
+            ("suspend", self.suspend),
+            ("off", self.off),
+        ];
+        let enabled: Vec<_> = stages
+            .iter()
+            .filter_map(|(stage, t)| Some((*stage, (*t).filter(|t| *t != 0)?)))
+            .collect();
+        enabled
+            .windows(2)
+            .find(|pair| pair[0].1 > pair[1].1)
+            .map(|pair| (pair[0].0, pair[1].0))
+    }
+}
+
+impl Display for Dpms {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let stages = [
+            ("standby", self.standby),
+            ("suspend", self.suspend),
+            ("off", self.off),
+        ];
+        let set: Vec<_> = stages
+            .iter()
+            .filter_map(|(stage, t)| Some(format!("{}={}", stage, (*t)?)))
+            .collect();
+        write!(f, "{}", set.join(" "))
+    }
+}
+
+impl FromNode for Dpms {
+    fn from_node(n: &Node) -> Result<Self> {
+        let timeout = |field: &'static str, name: &'static str| match n.properties.get(field) {
+            None => Ok(None),
+            Some(KdlValue::Int(i)) => u16::try_from(*i)
+                .map(Some)
+                .map_err(|_| Error::InvalidDpmsTimeout(name, *i)),
+            Some(_) => Err(Error::FieldTypeMisMatch(name, "int")),
+        };
+        Ok(Self {
+            standby: timeout("standby", "layout.dpms.standby")?,
+            suspend: timeout("suspend", "layout.dpms.suspend")?,
+            off: timeout("off", "layout.dpms.off")?,
+        })
+    }
+}
+
+/// The flags a modeline may end with, as `xrandr --newmode` accepts them
+pub const MODELINE_FLAGS: [&str; 9] = [
+    "+hsync",
+    "-hsync",
+    "+vsync",
+    "-vsync",
+    "interlace",
+    "doublescan",
+    "csync",
+    "+csync",
+    "-csync",
+];
+
+/// The timings of a mode, as with `xrandr --newmode`, for monitors that do not offer the mode
+/// they should display
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Modeline {
+    /// The pixel clock, in MHz
+    pub clock: f64,
+    /// The horizontal display, sync start, sync end and total, in pixels
+    pub horizontal: [u16; 4],
+    /// The vertical display, sync start, sync end and total, in lines
+    pub vertical: [u16; 4],
+    /// Flags from `MODELINE_FLAGS`
+    pub flags: Vec<String>,
+}
+
+impl Modeline {
+    /// The size of the mode
+    pub fn size(&self) -> Mode {
+        Mode {
+            w: self.horizontal[0],
+            h: self.vertical[0],
+        }
+    }
+
+    /// The refresh rate of the mode, in Hz
+    pub fn rate(&self) -> f64 {
+        let mut vtotal = self.vertical[3] as f64;
+        if self.flags.iter().any(|f| f == "doublescan") {
+            vtotal *= 2.0;
+        }
+        if self.flags.iter().any(|f| f == "interlace") {
+            vtotal /= 2.0;
+        }
+        let dots = self.horizontal[3] as f64 * vtotal;
+        if dots == 0.0 {
+            0.0
+        } else {
+            self.clock * 1_000_000.0 / dots
+        }
+    }
+
+    /// The name of the mode on the server, such as "2560x1080_60.00", which is how a mode
+    /// created for an earlier switch is found again
+    pub fn name(&self) -> String {
+        format!("{}_{:.2}", self.size(), self.rate())
+    }
+}
+
+impl Display for Modeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}", self.clock)?;
+        for timing in self.horizontal.iter().chain(self.vertical.iter()) {
+            write!(f, " {}", timing)?;
+        }
+        for flag in self.flags.iter() {
+            write!(f, " {}", flag)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromNode for Modeline {
+    fn from_node(n: &Node) -> Result<Self> {
+        let clock = match n.values.first() {
+            None => return Err(Error::MissingField("layout.monitor.modeline", "clock")),
+            Some(KdlValue::Int(i)) => *i as f64,
+            Some(KdlValue::Float(f)) => *f,
+            Some(_) => return Err(Error::FieldTypeMisMatch("layout.monitor.modeline", "float")),
+        };
+        let mut timings = [0u16; 8];
+        for (i, timing) in timings.iter_mut().enumerate() {
+            *timing = match n.values.get(i + 1) {
+                None => return Err(Error::MissingField("layout.monitor.modeline", "timings")),
+                Some(KdlValue::Int(i)) => *i as u16,
+                Some(_) => return Err(Error::FieldTypeMisMatch("layout.monitor.modeline", "int")),
+            };
+        }
+        let mut flags = Vec::new();
+        for value in n.values.iter().skip(9) {
+            match value {
+                KdlValue::String(flag)
+                    if MODELINE_FLAGS.contains(&flag.to_lowercase().as_str()) =>
+                {
+                    flags.push(flag.to_lowercase())
+                }
+                KdlValue::String(flag) => return Err(Error::InvalidModelineFlag(flag.clone())),
+                _ => {
+                    return Err(Error::FieldTypeMisMatch(
+                        "layout.monitor.modeline",
+                        "String",
+                    ))
+                }
+            }
+        }
+        Ok(Self {
+            clock,
+            horizontal: [timings[0], timings[1], timings[2], timings[3]],
+            vertical: [timings[4], timings[5], timings[6], timings[7]],
+            flags,
+        })
+    }
+}
+
+/// The value of a RandR output property, as with `xrandr --set`. Text names one of the values
+/// of a property holding atoms, such as "Full" for "Broadcast RGB".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    Int(i64),
+    Text(String),
+}
+
+impl Display for PropertyValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            PropertyValue::Int(i) => write!(f, "{}", i),
+            PropertyValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+/// Read a `property` node: the name of the property and its value.
+fn extract_property(n: &Node) -> Result<(String, PropertyValue)> {
+    let name = get_name(n, "layout.monitor.property")?;
+    match n.values.get(1) {
+        None => Err(Error::MissingField("layout.monitor.property", "value")),
+        Some(KdlValue::Int(i)) => Ok((name, PropertyValue::Int(*i))),
+        Some(KdlValue::String(text)) => Ok((name, PropertyValue::Text(text.clone()))),
+        Some(_) => Err(Error::FieldTypeMisMatch(
+            "layout.monitor.property",
+            "int or String",
+        )),
+    }
+}
+
+/// The gamma correction of each color channel, as with `xrandr --gamma`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Gamma {
+    pub red: f64,
+    pub green: f64,
+    pub blue: f64,
+}
+
+impl Display for Gamma {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}:{}:{}", self.red, self.green, self.blue)
+    }
+}
+
+/// A rectangle of the screen that a monitor shows
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Region {
+    pub position: Position,
+    pub size: Mode,
+}
+
+impl Region {
+    /// The part of the screen that both regions cover, if any.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        let span = |a: &Self| {
+            let (x, y) = (a.position.x as i32, a.position.y as i32);
+            (x, y, x + a.size.w as i32, y + a.size.h as i32)
+        };
+        let (l1, t1, r1, b1) = span(self);
+        let (l2, t2, r2, b2) = span(other);
+        let (left, top) = (l1.max(l2), t1.max(t2));
+        let (right, bottom) = (r1.min(r2), b1.min(b2));
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some(Self {
+            position: Position {
+                x: left as i16,
+                y: top as i16,
+            },
+            size: Mode {
+                w: (right - left) as u16,
+                h: (bottom - top) as u16,
+            },
+        })
+    }
+
+    /// Every pair of named regions that overlap, along with the region they share.
+    pub fn overlaps<'a>(regions: &[(&'a str, Region)]) -> Vec<(&'a str, &'a str, Region)> {
+        let mut overlaps = Vec::new();
+        for (i, (a, region_a)) in regions.iter().enumerate() {
+            for (b, region_b) in regions[i + 1..].iter() {
+                if let Some(shared) = region_a.intersection(region_b) {
+                    overlaps.push((*a, *b, shared));
+                }
+            }
+        }
+        overlaps
+    }
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}+{}+{}", self.size, self.position.x, self.position.y)
+    }
+}
+
+/// The mode to select for a monitor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModeSpec {
+    /// A mode with exactly this width and height
+    Exact(Mode),
+    /// The monitor's preferred mode, resolved when the layout is applied
+    Preferred,
+}
+
+impl Display for ModeSpec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ModeSpec::Exact(mode) => mode.fmt(f),
+            ModeSpec::Preferred => write!(f, "preferred"),
+        }
+    }
+}
+
+/// A monitor, identified by the product name and serial number in its EDID, or by the name of
+/// the output it is connected to when it has no EDID
+///
+/// Monitors are ordered by product name, then by serial number, then by output name, then by
+/// year and week of manufacture, comparing strings byte-wise and placing a missing field before
+/// any present one. A layout's `matches` is kept sorted in this order, so that the connected
+/// monitors, sorted the same way, can be looked up directly.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Monitor {
     pub product: Option<String>,
     pub serial: Option<String>,
+    /// The output name, such as "DP-1", for monitors without an EDID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connector: Option<String>,
+    /// The year of manufacture, for monitors whose EDID has no serial number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub year: Option<u16>,
+    /// The week of manufacture, from 1 to 54, for monitors whose EDID has no serial number
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub week: Option<u8>,
+}
+
+impl Monitor {
+    /// A monitor without an EDID, connected to the output named `connector`.
+    pub fn from_connector(connector: String) -> Self {
+        Self {
+            product: None,
+            serial: None,
+            connector: Some(connector),
+            year: None,
+            week: None,
+        }
+    }
+}
+
+impl Ord for Monitor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (
+            &self.product,
+            &self.serial,
+            &self.connector,
+            self.year,
+            self.week,
+        )
+            .cmp(&(
+                &other.product,
+                &other.serial,
+                &other.connector,
+                other.year,
+                other.week,
+            ))
+    }
+}
+
+impl PartialOrd for Monitor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for Monitor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match (&self.product, &self.serial, &self.connector) {
+            (Some(product), Some(serial), _) => {
+                write!(f, "product={:?} serial={:?}", product, serial)
+            }
+            (Some(product), None, _) => write!(f, "product={:?}", product),
+            (None, Some(serial), _) => write!(f, "serial={:?}", serial),
+            (None, None, Some(connector)) => write!(f, "connector={:?}", connector),
+            (None, None, None) => write!(f, "(no product or serial)"),
+        }?;
+        if let Some(year) = self.year {
+            write!(f, " year={}", year)?;
+        }
+        if let Some(week) = self.week {
+            write!(f, " week={}", week)?;
+        }
+        Ok(())
+    }
 }
 
 impl From<EDID> for Monitor {
@@ -87,16 +756,159 @@ impl From<EDID> for Monitor {
                 _ => (),
             }
         }
-        Self { product, serial }
+        // Monitors without a serial number descriptor may still carry one in the header
+        if serial.is_none() && edid.header.serial != 0 {
+            serial = Some(edid.header.serial.to_string());
+        }
+        // Without a serial number, the date of manufacture may still tell identical monitors
+        // apart. A week of 0 is unknown, and 255 marks the year as a model year.
+        let (year, week) = match serial {
+            Some(_) => (None, None),
+            None => (
+                Some(EDID_FIRST_YEAR + edid.header.year as u16),
+                Some(edid.header.week).filter(|week| (1..=MAX_WEEK).contains(week)),
+            ),
+        };
+        Self {
+            product,
+            serial,
+            connector: None,
+            year,
+            week,
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct MonConfig {
     pub name: String,
-    pub mode: Mode,
+    pub mode: ModeSpec,
     pub position: Position,
     pub primary: bool,
+    /// The refresh rate, in Hz, to prefer among the modes matching `mode`
+    pub rate: Option<f64>,
+    /// When false, the monitor is turned off and its mode and position are meaningless
+    pub enabled: bool,
+    /// The alias of the monitor whose image this monitor shows. The mode and position are
+    /// copied from that monitor.
+    pub clone_of: Option<String>,
+    /// When present, `position` is computed from the position of another monitor
+    pub relative: Option<Relative>,
+    /// When true, neither a position nor a relative position was given, so the monitor is at
+    /// 0,0, as the origin that other monitors are positioned relative to
+    pub unplaced: bool,
+    /// When present, the image is scaled by this factor, as with `xrandr --scale`
+    pub scale: Option<Scale>,
+    /// When present, the gamma ramps of the monitor's CRTC are set from this gamma
+    pub gamma: Option<Gamma>,
+    /// When present, the gamma ramps of the monitor's CRTC are scaled by this brightness
+    pub brightness: Option<f64>,
+    /// When present, the monitor pans across this area of the screen
+    pub panning: Option<Panning>,
+    /// When present, the mode is created from these timings when the server does not have it
+    pub modeline: Option<Modeline>,
+    /// RandR properties of the monitor's output, set once it is configured
+    pub properties: BTreeMap<String, PropertyValue>,
+}
+
+impl MonConfig {
+    /// The refresh rate to prefer among the modes matching `mode`: the configured rate, or else
+    /// the rate of the modeline.
+    pub fn target_rate(&self) -> Option<f64> {
+        self.rate
+            .or_else(|| self.modeline.as_ref().map(Modeline::rate))
+    }
+
+    /// The size that `mode` takes up on the screen when this monitor shows it.
+    pub fn scaled(&self, mode: &Mode) -> Mode {
+        match &self.scale {
+            Some(scale) => scale.apply(mode),
+            None => mode.clone(),
+        }
+    }
+
+    /// The bottom right corner of the screen that this monitor needs, when placed at `position`
+    /// with a mode taking up `size`, including the area it pans across.
+    pub fn extent(&self, position: Position, size: &Mode) -> Mode {
+        let shown = Mode {
+            w: position.x as u16 + size.w,
+            h: position.y as u16 + size.h,
+        };
+        match &self.panning {
+            Some(panning) => {
+                let Position { x, y } = panning.origin(position);
+                shown.union(&Mode {
+                    w: x as u16 + panning.size.w,
+                    h: y as u16 + panning.size.h,
+                })
+            }
+            None => shown,
+        }
+    }
+
+    /// Whether this monitor pans across an area too small to show a mode taking up `size`.
+    pub fn pans_too_little(&self, size: &Mode) -> bool {
+        self.panning
+            .as_ref()
+            .is_some_and(|p| p.size.w < size.w || p.size.h < size.h)
+    }
+}
+
+fn extract_gamma(n: &Node) -> Result<Option<Gamma>> {
+    let gamma = match n.properties.get("gamma") {
+        None => return Ok(None),
+        Some(KdlValue::Int(i)) => [*i as f64; 3],
+        Some(KdlValue::Float(f)) => [*f; 3],
+        Some(KdlValue::String(s)) => {
+            let channels: std::result::Result<Vec<f64>, _> =
+                s.split(':').map(|c| c.trim().parse()).collect();
+            match channels.as_deref() {
+                Ok(&[red, green, blue]) => [red, green, blue],
+                _ => return Err(Error::InvalidGamma(s.clone())),
+            }
+        }
+        Some(_) => return Err(Error::FieldTypeMisMatch("layout.monitor.gamma", "String")),
+    };
+    let [red, green, blue] = gamma;
+    let gamma = Gamma { red, green, blue };
+    if [red, green, blue].iter().all(|f| f.is_finite() && *f > 0.0) {
+        Ok(Some(gamma))
+    } else {
+        Err(Error::InvalidGamma(gamma.to_string()))
+    }
+}
+
+fn extract_gamma_brightness(n: &Node) -> Result<(Option<Gamma>, Option<f64>)> {
+    let brightness = extract_optional_float(n, "brightness", "layout.monitor.brightness")?;
+    if let Some(brightness) = brightness.filter(|b| !(b.is_finite() && *b >= 0.0)) {
+        return Err(Error::InvalidBrightness(brightness));
+    }
+    Ok((extract_gamma(n)?, brightness))
+}
+
+fn extract_scale(n: &Node) -> Result<Option<Scale>> {
+    let scale = match n.properties.get("scale") {
+        None => return Ok(None),
+        Some(KdlValue::Int(i)) => Scale {
+            x: *i as f64,
+            y: *i as f64,
+        },
+        Some(KdlValue::Float(f)) => Scale { x: *f, y: *f },
+        Some(KdlValue::String(s)) => {
+            let invalid = || Error::InvalidScale(s.clone());
+            let (x, y) = s.split_once(',').ok_or_else(invalid)?;
+            Scale {
+                x: x.trim().parse().map_err(|_| invalid())?,
+                y: y.trim().parse().map_err(|_| invalid())?,
+            }
+        }
+        Some(_) => return Err(Error::FieldTypeMisMatch("layout.monitor.scale", "float")),
+    };
+    if [scale.x, scale.y].iter().all(|f| f.is_finite() && *f > 0.0) {
+        Ok(Some(scale))
+    } else {
+        Err(Error::InvalidScale(scale.to_string()))
+    }
 }
 
 fn extract_int_value(n: &Node, field: &'static str, name: &'static str) -> Result<i64> {
@@ -114,8 +926,22 @@ fn extract_bool_value(n: &Node, field: &'static str, name: &'static str) -> Resu
         Some(_) => Err(Error::FieldTypeMisMatch(name, "boolean")),
     }
 }
+
+fn extract_optional_float(
+    n: &Node,
+    field: &'static str,
+    name: &'static str,
+) -> Result<Option<f64>> {
+    match n.properties.get(field) {
+        None => Ok(None),
+        Some(KdlValue::Int(i)) => Ok(Some(*i as f64)),
+        Some(KdlValue::Float(f)) => Ok(Some(*f)),
+        Some(_) => Err(Error::FieldTypeMisMatch(name, "float")),
+    }
+}
+
 fn get_name(n: &Node, name: &'static str) -> Result<String> {
-    match n.values.get(0) {
+    match n.values.first() {
         None => Err(Error::MissingField(name, "name")),
         Some(KdlValue::String(out)) => Ok(out.clone()),
         Some(_) => Err(Error::FieldTypeMisMatch(name, "String")),
@@ -128,18 +954,222 @@ impl FromNode for MonConfig {
             return Err(Error::NodeTypeMismatch("monitor", n.name.clone()));
         }
         let name = get_name(n, "layout.monitor")?;
-        let x = extract_int_value(n, "x", "layout.monitor")? as i16;
-        let y = extract_int_value(n, "y", "layout.monitor")? as i16;
-        let w = extract_int_value(n, "w", "layout.monitor")? as u16;
-        let h = extract_int_value(n, "h", "layout.monitor")? as u16;
+        let mut panning = None;
+        let mut modeline = None;
+        let mut properties = BTreeMap::new();
+        for node in &n.children {
+            match node.name.as_str() {
+                "property" => {
+                    let (property, value) = extract_property(node)?;
+                    if properties.insert(property.clone(), value).is_some() {
+                        return Err(Error::DuplicateProperty(name, property));
+                    }
+                }
+                "panning" if panning.is_none() => panning = Some(Panning::from_node(node)?),
+                "panning" => return Err(Error::DuplicateSingleton("layout.monitor.panning")),
+                "modeline" if modeline.is_none() => modeline = Some(Modeline::from_node(node)?),
+                "modeline" => return Err(Error::DuplicateSingleton("layout.monitor.modeline")),
+                _ => return Err(Error::Unexpected(node.name.clone())),
+            }
+        }
+        match extract_optional_str(n, "state", "layout.monitor")?.as_deref() {
+            None | Some("on") => (),
+            Some("off") => {
+                for field in [
+                    "w",
+                    "h",
+                    "x",
+                    "y",
+                    "mode",
+                    "rate",
+                    "primary",
+                    "clone-of",
+                    "left-of",
+                    "right-of",
+                    "above",
+                    "below",
+                    "gap",
+                    "scale",
+                    "gamma",
+                    "brightness",
+                ] {
+                    if n.properties.contains_key(field) {
+                        return Err(Error::ConflictingFields("layout.monitor", "state", field));
+                    }
+                }
+                for (field, present) in [
+                    ("panning", panning.is_some()),
+                    ("modeline", modeline.is_some()),
+                    ("property", !properties.is_empty()),
+                ] {
+                    if present {
+                        return Err(Error::ConflictingFields("layout.monitor", "state", field));
+                    }
+                }
+                return Ok(Self {
+                    name,
+                    mode: ModeSpec::Exact(Mode { w: 0, h: 0 }),
+                    position: Position { x: 0, y: 0 },
+                    primary: false,
+                    rate: None,
+                    enabled: false,
+                    clone_of: None,
+                    relative: None,
+                    unplaced: false,
+                    scale: None,
+                    gamma: None,
+                    brightness: None,
+                    panning: None,
+                    modeline: None,
+                    properties: BTreeMap::new(),
+                });
+            }
+            Some(state) => return Err(Error::InvalidState(state.to_string())),
+        }
+        if let Some(clone_of) = extract_optional_str(n, "clone-of", "layout.monitor")? {
+            for field in [
+                "w", "h", "x", "y", "mode", "rate", "left-of", "right-of", "above", "below", "gap",
+                "scale",
+            ] {
+                if n.properties.contains_key(field) {
+                    return Err(Error::ConflictingFields(
+                        "layout.monitor",
+                        "clone-of",
+                        field,
+                    ));
+                }
+            }
+            for (field, present) in [
+                ("panning", panning.is_some()),
+                ("modeline", modeline.is_some()),
+            ] {
+                if present {
+                    return Err(Error::ConflictingFields(
+                        "layout.monitor",
+                        "clone-of",
+                        field,
+                    ));
+                }
+            }
+            let (gamma, brightness) = extract_gamma_brightness(n)?;
+            // The mode, position and scale are filled in once the whole layout is read
+            return Ok(Self {
+                name,
+                mode: ModeSpec::Exact(Mode { w: 0, h: 0 }),
+                position: Position { x: 0, y: 0 },
+                primary: extract_bool_value(n, "primary", "layout.monitor")?,
+                rate: None,
+                enabled: true,
+                clone_of: Some(clone_of),
+                relative: None,
+                unplaced: false,
+                scale: None,
+                gamma,
+                brightness,
+                panning: None,
+                modeline: None,
+                properties,
+            });
+        }
+        let mut relative: Option<(&'static str, Relative)> = None;
+        for (field, direction) in Direction::ALL.iter() {
+            if let Some(of) = extract_optional_str(n, field, "layout.monitor")? {
+                if let Some((other, _)) = relative {
+                    return Err(Error::ConflictingFields("layout.monitor", other, field));
+                }
+                let direction = *direction;
+                let gap = match n.properties.get("gap") {
+                    None => 0,
+                    Some(KdlValue::Int(gap)) => match i16::try_from(*gap) {
+                        Ok(g) if g >= 0 => g as u16,
+                        _ => return Err(Error::InvalidGap(*gap)),
+                    },
+                    Some(_) => return Err(Error::FieldTypeMisMatch("layout.monitor.gap", "int")),
+                };
+                relative = Some((field, Relative { direction, of, gap }));
+            }
+        }
+        let unplaced = relative.is_none()
+            && !n.properties.contains_key("x")
+            && !n.properties.contains_key("y");
+        let (x, y, relative) = match relative {
+            None if n.properties.contains_key("gap") => {
+                return Err(Error::GapWithoutRelative(name))
+            }
+            None if unplaced => (0, 0, None),
+            None => (
+                extract_int_value(n, "x", "layout.monitor")? as i16,
+                extract_int_value(n, "y", "layout.monitor")? as i16,
+                None,
+            ),
+            Some((field, relative)) => {
+                for xy in ["x", "y"] {
+                    if n.properties.contains_key(xy) {
+                        return Err(Error::ConflictingFields("layout.monitor", field, xy));
+                    }
+                }
+                // The position is resolved once the whole layout is read
+                (0, 0, Some(relative))
+            }
+        };
+        let mode = match (
+            extract_optional_str(n, "mode", "layout.monitor")?,
+            &modeline,
+        ) {
+            // The size of a mode given by a modeline need not be repeated
+            (None, Some(modeline))
+                if !n.properties.contains_key("w") && !n.properties.contains_key("h") =>
+            {
+                ModeSpec::Exact(modeline.size())
+            }
+            (None, _) => {
+                let w = extract_int_value(n, "w", "layout.monitor")? as u16;
+                let h = extract_int_value(n, "h", "layout.monitor")? as u16;
+                ModeSpec::Exact(Mode { w, h })
+            }
+            (Some(mode), _) if mode == "preferred" => {
+                for field in ["w", "h"] {
+                    if n.properties.contains_key(field) {
+                        return Err(Error::ConflictingFields("layout.monitor", "mode", field));
+                    }
+                }
+                if modeline.is_some() {
+                    return Err(Error::ConflictingFields(
+                        "layout.monitor",
+                        "mode",
+                        "modeline",
+                    ));
+                }
+                ModeSpec::Preferred
+            }
+            (Some(mode), _) => return Err(Error::InvalidMode(mode)),
+        };
+        if let (ModeSpec::Exact(mode), Some(modeline)) = (&mode, &modeline) {
+            if *mode != modeline.size() {
+                return Err(Error::ModelineSize(name, mode.clone(), modeline.size()));
+            }
+        }
         let primary = extract_bool_value(n, "primary", "layout.monitor")?;
-        let mode = Mode { w, h };
+        let rate = extract_optional_float(n, "rate", "layout.monitor")?;
         let position = Position { x, y };
+        let scale = extract_scale(n)?;
+        let (gamma, brightness) = extract_gamma_brightness(n)?;
         Ok(Self {
             name,
             mode,
             position,
             primary,
+            rate,
+            enabled: true,
+            clone_of: None,
+            relative,
+            unplaced,
+            scale,
+            gamma,
+            brightness,
+            panning,
+            modeline,
+            properties,
         })
     }
 }
@@ -147,7 +1177,18 @@ impl FromNode for MonConfig {
 #[derive(Debug)]
 struct LayoutIn {
     name: String,
+    /// Applied when no other layout matches, regardless of `matches`
+    fallback: bool,
+    on_apply: Option<Hook>,
+    pre_switch: Option<Hook>,
+    post_switch: Option<Hook>,
+    allow_extra_monitors: bool,
+    disable_extra_monitors: bool,
+    priority: i64,
+    dpi: Option<f64>,
+    dpms: Option<Dpms>,
     matches: Vec<String>,
+    fb_size: Option<Mode>,
     layout: Vec<MonConfig>,
 }
 
@@ -157,11 +1198,66 @@ impl FromNode for LayoutIn {
             return Err(Error::NodeTypeMismatch("layout", n.name.clone()));
         }
         let name = get_name(n, "layout")?;
+        let fallback = match n.properties.get("fallback") {
+            None => false,
+            Some(KdlValue::Boolean(b)) => *b,
+            Some(_) => return Err(Error::FieldTypeMisMatch("layout.fallback", "Boolean")),
+        };
+        let on_apply = extract_layout_hook(n, "on-apply", "on-apply-timeout-ms")?;
+        let pre_switch = extract_layout_hook(n, "pre-switch", "pre-switch-timeout-ms")?;
+        let post_switch = extract_layout_hook(n, "post-switch", "post-switch-timeout-ms")?;
+        let disable_extra_monitors =
+            extract_bool_value(n, "disable-extra-monitors", "layout.disable-extra-monitors")?;
+        // Disabling the extra monitors only makes sense when they are allowed
+        let allow_extra_monitors = disable_extra_monitors
+            || extract_bool_value(n, "allow-extra-monitors", "layout.allow-extra-monitors")?;
+        // match="subset" is another way to allow extra monitors, and match="exact" the default
+        let allow_extra_monitors = match extract_optional_str(n, "match", "layout.match")? {
+            None => allow_extra_monitors,
+            Some(m) if m == "subset" => true,
+            Some(m) if m == "exact" => {
+                for field in ["allow-extra-monitors", "disable-extra-monitors"] {
+                    if n.properties.get(field) == Some(&KdlValue::Boolean(true)) {
+                        return Err(Error::ConflictingFields("layout", "match", field));
+                    }
+                }
+                false
+            }
+            Some(m) => return Err(Error::InvalidMatch(m)),
+        };
+        let priority = match n.properties.get("priority") {
+            None => 0,
+            Some(KdlValue::Int(i)) => *i,
+            Some(_) => return Err(Error::FieldTypeMisMatch("layout.priority", "int")),
+        };
+        let dpi = extract_optional_float(n, "dpi", "layout.dpi")?;
+        if let Some(dpi) = dpi.filter(|dpi| !(dpi.is_finite() && *dpi > 0.0)) {
+            return Err(Error::InvalidDpi(name, dpi));
+        }
         let mut layout = Vec::new();
         let mut matches = None;
+        let mut fb_size = None;
+        let mut dpms = None;
         for node in &n.children {
             match node.name.as_str() {
                 "monitor" => layout.push(MonConfig::from_node(node)?),
+                "dpms" if dpms.is_none() => {
+                    let timeouts = Dpms::from_node(node)?;
+                    if let Some((earlier, later)) = timeouts.out_of_order() {
+                        return Err(Error::DpmsOrder(name, earlier, later));
+                    }
+                    dpms = Some(timeouts);
+                }
+                "dpms" => return Err(Error::DuplicateSingleton("layout.dpms")),
+                "fb-size" => {
+                    if fb_size.is_none() {
+                        let w = extract_int_value(node, "w", "layout.fb-size")? as u16;
+                        let h = extract_int_value(node, "h", "layout.fb-size")? as u16;
+                        fb_size = Some(Mode { w, h });
+                    } else {
+                        return Err(Error::DuplicateSingleton("layout.fb-size"));
+                    }
+                }
                 "matches" => {
                     if matches.is_none() {
                         let m: Result<Vec<_>> = node
@@ -180,29 +1276,202 @@ impl FromNode for LayoutIn {
                 _ => return Err(Error::Unexpected(node.name.clone())),
             }
         }
-        if let Some(matches) = matches {
-            Ok(Self {
+        match matches {
+            Some(matches) => Ok(Self {
                 name,
+                fallback,
+                on_apply,
+                pre_switch,
+                post_switch,
+                allow_extra_monitors,
+                disable_extra_monitors,
+                priority,
+                dpi,
+                dpms,
                 matches,
+                fb_size,
                 layout,
-            })
-        } else {
-            Err(Error::MissingField("layout", "matches"))
+            }),
+            // A layout named "default" without a matches node is the fallback layout
+            None if fallback || name == DEFAULT_LAYOUT => Ok(Self {
+                name,
+                fallback: true,
+                on_apply,
+                pre_switch,
+                post_switch,
+                allow_extra_monitors,
+                disable_extra_monitors,
+                priority,
+                dpi,
+                dpms,
+                matches: Vec::new(),
+                fb_size,
+                layout,
+            }),
+            None => Err(Error::MissingField("layout", "matches")),
         }
     }
 }
 
-pub struct SingleConfig {
-    pub name: String,
-    pub fb_size: Mode,
-    pub setup: HashMap<Monitor, MonConfig>,
+/// Follow the relative position of `mon`, resolving the monitors it depends on first.
+fn resolve_position<'a>(
+    mon: &'a MonConfig,
+    by_name: &HashMap<&str, &'a MonConfig>,
+    size_of: &impl Fn(&MonConfig) -> Option<Mode>,
+    visiting: &mut Vec<&'a str>,
+    resolved: &mut HashMap<String, Position>,
+) -> Result<Position> {
+    if let Some(pos) = resolved.get(&mon.name) {
+        return Ok(*pos);
+    }
+    let relative = match &mon.relative {
+        None => return Ok(mon.position),
+        Some(relative) => relative,
+    };
+    if let Some(start) = visiting.iter().position(|&n| n == mon.name) {
+        let mut cycle = visiting[start..].to_vec();
+        cycle.push(&mon.name);
+        return Err(Error::RelativeCycle(cycle.join(" -> ")));
+    }
+    let other = by_name
+        .get(relative.of.as_str())
+        .filter(|other| other.enabled)
+        .ok_or_else(|| Error::RelativeTo(mon.name.clone(), relative.of.clone()))?;
+    visiting.push(&mon.name);
+    let of = resolve_position(other, by_name, size_of, visiting, resolved)?;
+    visiting.pop();
+    let size = |m: &MonConfig| size_of(m).ok_or_else(|| Error::UnknownSize(m.name.clone()));
+    let gap = relative.gap as i16;
+    let pos = match relative.direction {
+        Direction::RightOf => Position {
+            x: of.x + size(other)?.w as i16 + gap,
+            y: of.y,
+        },
+        Direction::Below => Position {
+            x: of.x,
+            y: of.y + size(other)?.h as i16 + gap,
+        },
+        Direction::LeftOf => Position {
+            x: of.x - size(mon)?.w as i16 - gap,
+            y: of.y,
+        },
+        Direction::Above => Position {
+            x: of.x,
+            y: of.y - size(mon)?.h as i16 - gap,
+        },
+    };
+    resolved.insert(mon.name.clone(), pos);
+    Ok(pos)
 }
 
-fn extract_optional_str(
-    n: &Node,
-    field: &'static str,
-    name: &'static str,
-) -> Result<Option<String>> {
+/// Compute the absolute position of every monitor of a layout, by alias, following relative
+/// positions. `size_of` provides the size of a monitor's mode, when it is known. When a
+/// monitor would be placed left of or above the origin, the whole layout is moved so that it
+/// is not.
+pub fn resolve_positions<'a>(
+    monitors: impl IntoIterator<Item = &'a MonConfig>,
+    size_of: impl Fn(&MonConfig) -> Option<Mode>,
+) -> Result<HashMap<String, Position>> {
+    let by_name: HashMap<_, _> = monitors.into_iter().map(|m| (m.name.as_str(), m)).collect();
+    let mut resolved = HashMap::with_capacity(by_name.len());
+    for mon in by_name.values() {
+        let pos = resolve_position(mon, &by_name, &size_of, &mut Vec::new(), &mut resolved)?;
+        resolved.insert(mon.name.clone(), pos);
+    }
+    let shown = resolved
+        .iter()
+        .filter(|(name, _)| by_name[name.as_str()].enabled);
+    let min_x = shown.clone().map(|(_, p)| p.x).min().unwrap_or(0).min(0);
+    let min_y = shown.map(|(_, p)| p.y).min().unwrap_or(0).min(0);
+    for pos in resolved.values_mut() {
+        pos.x -= min_x;
+        pos.y -= min_y;
+    }
+    Ok(resolved)
+}
+
+/// The monitors of a layout that are placed at 0,0 only because they have no position, apart
+/// from the origin, so that they and the monitors positioned relative to them cannot be reached
+/// from the origin. The origin is a monitor with a position, or when none has one, the primary
+/// monitor without one, or else the first of them by alias.
+pub fn unreachable_monitors<'a>(monitors: impl IntoIterator<Item = &'a MonConfig>) -> Vec<&'a str> {
+    let shown: Vec<_> = monitors
+        .into_iter()
+        .filter(|m| m.enabled && m.clone_of.is_none())
+        .collect();
+    let mut unplaced: Vec<_> = shown.iter().filter(|m| m.unplaced).collect();
+    unplaced.sort_by_key(|m| (!m.primary, m.name.as_str()));
+    let skip = if shown.iter().any(|m| !m.unplaced && m.relative.is_none()) {
+        0
+    } else {
+        1
+    };
+    unplaced
+        .into_iter()
+        .skip(skip)
+        .map(|m| m.name.as_str())
+        .collect()
+}
+
+/// The alias that matches any single monitor not named by a layout
+pub const WILDCARD: &str = "*";
+
+/// The configuration name that reads the configuration from stdin
+pub const STDIN_CONFIG: &str = "-";
+
+/// The name of the layout that is the fallback layout when it has no matches node
+pub const DEFAULT_LAYOUT: &str = "default";
+
+#[derive(Debug, PartialEq)]
+pub struct SingleConfig {
+    pub name: String,
+    /// The screen size given in the configuration. When absent, the screen is sized to fit
+    /// the layout.
+    pub fb_size: Option<Mode>,
+    pub setup: HashMap<Monitor, MonConfig>,
+    /// How to lay out the one monitor that the layout does not name
+    pub wildcard: Option<MonConfig>,
+    /// A command to run after the layout is applied
+    pub on_apply: Option<Hook>,
+    /// A command to run before switching to the layout, after the top-level pre-switch hook
+    pub pre_switch: Option<Hook>,
+    /// A command to run after switching to the layout, after the top-level post-switch hook
+    pub post_switch: Option<Hook>,
+    /// Whether the layout matches when more monitors than it names are connected
+    pub allow_extra_monitors: bool,
+    /// Whether the monitors that the layout does not name are disabled, rather than left as
+    /// they are
+    pub disable_extra_monitors: bool,
+    /// Decides between layouts that allow extra monitors and name as many connected monitors
+    pub priority: i64,
+    /// The resolution that the screen reports, in dots per inch
+    pub dpi: Option<f64>,
+    /// The DPMS timeouts set when the layout is applied
+    pub dpms: Option<Dpms>,
+}
+
+impl SingleConfig {
+    /// The configuration of a monitor, given the monitors the layout matches by name. Monitors
+    /// that the layout does not name use the wildcard configuration.
+    pub fn monitor_config(&self, matches: &[Monitor], mon: &Monitor) -> Option<&MonConfig> {
+        match self.setup.get(mon) {
+            Some(conf) => Some(conf),
+            None if !matches.contains(mon) => self.wildcard.as_ref(),
+            None => None,
+        }
+    }
+
+    /// Every monitor configuration of the layout, including the wildcard.
+    pub fn monitor_configs(&self) -> impl Iterator<Item = &MonConfig> {
+        self.setup.values().chain(self.wildcard.iter())
+    }
+}
+
+fn extract_optional_str(
+    n: &Node,
+    field: &'static str,
+    name: &'static str,
+) -> Result<Option<String>> {
     match n.properties.get(field) {
         None => Ok(None),
         Some(KdlValue::String(v)) => Ok(Some(v.clone())),
@@ -210,72 +1479,1382 @@ fn extract_optional_str(
     }
 }
 
-pub struct Config(pub HashMap<Vec<Monitor>, SingleConfig>);
+/// The year that an EDID counts the year of manufacture from
+const EDID_FIRST_YEAR: u16 = 1990;
+
+/// The last week of a year of manufacture, as an EDID counts them
+const MAX_WEEK: u8 = 54;
+
+/// Read the year and week of manufacture of a monitor node, which are only given as properties.
+fn extract_manufacture(n: &Node) -> Result<(Option<u16>, Option<u8>)> {
+    let field =
+        |field: &'static str, range: std::ops::RangeInclusive<i64>| match n.properties.get(field) {
+            None => Ok(None),
+            Some(KdlValue::Int(i)) if range.contains(i) => Ok(Some(*i)),
+            Some(KdlValue::Int(i)) => Err(Error::InvalidManufacture(field, *i)),
+            Some(_) => Err(Error::FieldTypeMisMatch("monitor", "int")),
+        };
+    let year = field(
+        "year",
+        EDID_FIRST_YEAR as i64..=EDID_FIRST_YEAR as i64 + u8::MAX as i64,
+    )?;
+    let week = field("week", 1..=MAX_WEEK as i64)?;
+    Ok((year.map(|y| y as u16), week.map(|w| w as u8)))
+}
+
+/// The fields of a monitor node, given either as properties or as child nodes
+const MONITOR_FIELDS: [&str; 3] = ["product", "serial", "connector"];
+
+/// Read a field of a monitor node, from either its property or a child node of the same name
+/// holding the value as its only argument.
+fn monitor_field(n: &Node, field: &'static str) -> Result<Option<String>> {
+    let mut value = extract_optional_str(n, field, "monitor")?;
+    for child in n.children.iter().filter(|c| c.name == field) {
+        if value.is_some() {
+            return Err(Error::DuplicateSingleton(field));
+        }
+        match &child.values[..] {
+            [KdlValue::String(v)] => value = Some(v.clone()),
+            _ => return Err(Error::FieldTypeMisMatch(field, "String")),
+        }
+    }
+    Ok(value)
+}
+
+/// Collects the nodes of one or more configuration files, remembering which file each layout
+/// and monitor came from
+#[derive(Default)]
+struct Reader {
+    document: Vec<Node>,
+    sources: HashMap<String, PathBuf>,
+    monitor_sources: HashMap<String, PathBuf>,
+    including: Vec<PathBuf>,
+}
+
+/// Whether a file name matches a pattern, where `*` matches any run of characters and `?`
+/// matches any one character.
+///
+/// ```
+/// use monitor_layout::config::glob_matches;
+///
+/// assert!(glob_matches("*.kdl", "home.kdl"));
+/// assert!(glob_matches("dock-?.kdl", "dock-2.kdl"));
+/// assert!(glob_matches("*", "anything"));
+/// assert!(!glob_matches("*.kdl", "home.kdl.bak"));
+/// assert!(!glob_matches("dock-?.kdl", "dock-10.kdl"));
+/// ```
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<_> = pattern.chars().collect();
+    let name: Vec<_> = name.chars().collect();
+    // The pattern and name positions to return to when a `*` has to match one more character
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// When the last component of an included file name has a `*` or `?`, every file in that
+/// directory whose name matches it, in order of name, or `None` for the name of a single file.
+/// A pattern that matches nothing includes nothing, so that a directory may be empty.
+fn expand_include(path: &Path) -> Result<Option<Vec<PathBuf>>> {
+    let pattern = match path.file_name().and_then(|name| name.to_str()) {
+        Some(pattern) if pattern.contains(['*', '?']) => pattern,
+        _ => return Ok(None),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let mut files = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Some(files)),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let file = entry?.path();
+        let matches = file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| glob_matches(pattern, name));
+        if matches && file.is_file() {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(Some(files))
+}
+
+/// A configuration file that is not valid KDL, pointing at where in the file parsing failed
+#[derive(Error, Debug, Diagnostic)]
+#[error("{path} is not valid KDL: {kind}")]
+#[diagnostic(code(config::syntax))]
+pub struct SyntaxError {
+    path: String,
+    kind: KdlErrorKind,
+    #[source_code]
+    text: NamedSource,
+    #[label("parsing failed here")]
+    span: SourceSpan,
+}
+
+impl SyntaxError {
+    fn new(e: KdlError, text: &str, path: &Path) -> Self {
+        // The parser counts characters, where spans count bytes
+        let offset = text
+            .char_indices()
+            .nth(e.offset)
+            .map_or(text.len(), |(i, _)| i);
+        let path = path.display().to_string();
+        Self {
+            text: NamedSource::new(&path, text.to_string()),
+            path,
+            kind: e.kind,
+            span: (offset, 1).into(),
+        }
+    }
+}
+
+impl Reader {
+    /// Whether a file is being read, as it includes the file being read now
+    fn is_including(&self, path: &Path) -> bool {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.including.contains(&canonical)
+    }
+
+    fn read_file(&mut self, path: &Path) -> Result<()> {
+        let mut file = std::fs::File::open(path)?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        self.read_text(&text, path)
+    }
+
+    fn read_text(&mut self, text: &str, path: &Path) -> Result<()> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.including.contains(&canonical) {
+            return Err(Error::IncludeCycle(path.display().to_string()));
+        }
+        self.including.push(canonical);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let document = parse_document(text).map_err(|e| SyntaxError::new(e, text, path))?;
+        for node in document {
+            match node.name.as_str() {
+                "include" => {
+                    for value in node.values.iter() {
+                        match value {
+                            KdlValue::String(file) => match expand_include(&dir.join(file))? {
+                                Some(files) => {
+                                    // A pattern may match a file that is being read, such as
+                                    // this one, which is left out rather than being a cycle
+                                    let files: Vec<_> = files
+                                        .into_iter()
+                                        .filter(|f| !self.is_including(f))
+                                        .collect();
+                                    for file in files {
+                                        self.read_file(&file)?;
+                                    }
+                                }
+                                None => self.read_file(&dir.join(file))?,
+                            },
+                            _ => return Err(Error::FieldTypeMisMatch("include", "String")),
+                        }
+                    }
+                }
+                "layout" => {
+                    let name = get_name(&node, "layout")?;
+                    if let Some(other) = self.sources.get(&name) {
+                        if other != path {
+                            return Err(Error::InFiles(
+                                Box::new(Error::DuplicateLayout(name)),
+                                other.display().to_string(),
+                                path.display().to_string(),
+                            ));
+                        }
+                    }
+                    self.sources.insert(name, path.to_path_buf());
+                    self.document.push(node);
+                }
+                "monitor" => {
+                    let name = get_name(&node, "monitor")?;
+                    if let Some(other) = self.monitor_sources.get(&name) {
+                        if other != path {
+                            return Err(Error::InFiles(
+                                Box::new(Error::DuplicateMonitor(name)),
+                                other.display().to_string(),
+                                path.display().to_string(),
+                            ));
+                        }
+                    }
+                    self.monitor_sources.insert(name, path.to_path_buf());
+                    self.document.push(node);
+                }
+                _ => self.document.push(node),
+            }
+        }
+        self.including.pop();
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Config> {
+        let sources = self.sources;
+        Config::try_from(self.document).map_err(|e| match e {
+            Error::SameMatches(a, b) if sources.get(&a) != sources.get(&b) => Error::InFiles(
+                Box::new(Error::SameMatches(a.clone(), b.clone())),
+                sources[&a].display().to_string(),
+                sources[&b].display().to_string(),
+            ),
+            e => e,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    /// Layouts keyed by the sorted monitors they match
+    pub layouts: HashMap<Vec<Monitor>, SingleConfig>,
+    /// Layouts that also match any one other monitor, keyed by the sorted monitors they match
+    /// by name
+    pub wildcard_layouts: HashMap<Vec<Monitor>, SingleConfig>,
+    /// The layout applied when no other layout matches the connected monitors
+    pub fallback: Option<SingleConfig>,
+    /// Every monitor described by a monitor node
+    pub monitors: HashSet<Monitor>,
+    /// The monitor that each monitor node describes, by alias
+    pub aliases: BTreeMap<String, Monitor>,
+    /// A command to run before switching layouts
+    pub pre_switch: Option<Hook>,
+    /// A command to run after switching layouts
+    pub post_switch: Option<Hook>,
+    /// A command to run when no layout matches the connected monitors
+    pub on_no_match: Option<Hook>,
+}
+
+/// A command line, run with `sh -c`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hook {
+    pub command: String,
+    /// How long the command may run before it is killed
+    pub timeout: Option<Duration>,
+}
+
+fn extract_timeout(n: &Node, field: &'static str, name: &'static str) -> Result<Option<Duration>> {
+    match n.properties.get(field) {
+        None => Ok(None),
+        Some(KdlValue::Int(ms)) if *ms >= 0 => Ok(Some(Duration::from_millis(*ms as u64))),
+        Some(_) => Err(Error::FieldTypeMisMatch(name, "non-negative int")),
+    }
+}
+
+/// Read a top-level hook node, which may appear only once.
+fn set_hook(hook: &mut Option<Hook>, n: &Node, name: &'static str) -> Result<()> {
+    if hook.is_some() {
+        return Err(Error::DuplicateSingleton(name));
+    }
+    let command = match n.values.first() {
+        None => return Err(Error::MissingField(name, "command")),
+        Some(KdlValue::String(command)) => command.clone(),
+        Some(_) => return Err(Error::FieldTypeMisMatch(name, "String")),
+    };
+    let timeout = extract_timeout(n, "timeout-ms", name)?;
+    *hook = Some(Hook { command, timeout });
+    Ok(())
+}
+
+/// Read a hook given as a property of a layout node, along with its timeout property.
+fn extract_layout_hook(
+    n: &Node,
+    field: &'static str,
+    timeout_field: &'static str,
+) -> Result<Option<Hook>> {
+    match extract_optional_str(n, field, "layout")? {
+        None => Ok(None),
+        Some(command) => Ok(Some(Hook {
+            command,
+            timeout: extract_timeout(n, timeout_field, "layout")?,
+        })),
+    }
+}
 
 impl TryFrom<Vec<Node>> for Config {
     type Error = Error;
     fn try_from(document: Vec<Node>) -> Result<Self> {
         let mut layouts = Vec::new();
         let mut mon_names = HashMap::new();
+        let mut pre_switch = None;
+        let mut post_switch = None;
+        let mut on_no_match = None;
         for cld in &document {
             match cld.name.as_str() {
                 "layout" => layouts.push(LayoutIn::from_node(cld)?),
                 "monitor" => {
                     let name = get_name(cld, "monitor")?;
-                    if !cld.children.is_empty() {
-                        Err(Error::Unexpected(format!("in monitor {}", name)))?
+                    if let Some(child) = cld
+                        .children
+                        .iter()
+                        .find(|c| !MONITOR_FIELDS.contains(&c.name.as_str()))
+                    {
+                        Err(Error::Unexpected(format!(
+                            "{} in monitor {}",
+                            child.name, name
+                        )))?
+                    }
+                    let product = monitor_field(cld, "product")?;
+                    let serial = monitor_field(cld, "serial")?;
+                    let connector = monitor_field(cld, "connector")?;
+                    if connector.is_some() {
+                        for field in ["product", "serial"] {
+                            if monitor_field(cld, field)?.is_some() {
+                                return Err(Error::ConflictingFields(
+                                    "monitor",
+                                    "connector",
+                                    field,
+                                ));
+                            }
+                        }
+                    }
+                    let (year, week) = extract_manufacture(cld)?;
+                    let mon = Monitor {
+                        product,
+                        serial,
+                        connector,
+                        year,
+                        week,
+                    };
+                    if mon_names.insert(name.clone(), mon).is_some() {
+                        return Err(Error::DuplicateMonitor(name));
                     }
-                    let product = extract_optional_str(cld, "product", "monitor")?;
-                    let serial = extract_optional_str(cld, "serial", "monitor")?;
-                    mon_names.insert(name, Monitor { product, serial });
                 }
+                "pre-switch" => set_hook(&mut pre_switch, cld, "pre-switch")?,
+                "post-switch" => set_hook(&mut post_switch, cld, "post-switch")?,
+                "on-no-match" => set_hook(&mut on_no_match, cld, "on-no-match")?,
                 _ => Err(Error::Unexpected(cld.name.clone()))?,
             }
         }
-        let mut out = HashMap::new();
+        if mon_names.contains_key(WILDCARD) {
+            return Err(Error::Unexpected(format!("monitor {}", WILDCARD)));
+        }
+        let mut layouts_out: HashMap<Vec<Monitor>, SingleConfig> = HashMap::new();
+        let mut wildcard_layouts = HashMap::new();
+        let mut fallback_layout: Option<SingleConfig> = None;
+        let mut names = HashSet::new();
         for LayoutIn {
             name: conf_name,
+            fallback,
+            on_apply,
+            pre_switch: layout_pre_switch,
+            post_switch: layout_post_switch,
+            allow_extra_monitors,
+            disable_extra_monitors,
+            priority,
+            dpi,
+            dpms,
             matches,
-            layout: setup,
+            fb_size,
+            layout: mut setup,
         } in layouts
         {
+            if !names.insert(conf_name.clone()) {
+                return Err(Error::DuplicateLayout(conf_name));
+            }
             let mut mon_set = Vec::with_capacity(matches.len());
+            let mut has_wildcard = false;
+            // The monitors a fallback layout matches are ignored
+            let matches = if fallback { Vec::new() } else { matches };
             for m in matches.into_iter() {
+                if m == WILDCARD {
+                    if has_wildcard {
+                        return Err(Error::MultipleWildcards(conf_name));
+                    }
+                    has_wildcard = true;
+                    continue;
+                }
                 let mon_desc = mon_names
                     .get(&m)
                     .ok_or_else(|| Error::UnknownMonitor(conf_name.clone(), m))?;
                 mon_set.push(mon_desc.clone())
             }
             mon_set.sort();
-            let mut fb_size = Mode { w: 0, h: 0 };
+            if has_wildcard && allow_extra_monitors {
+                return Err(Error::WildcardWithExtras(conf_name));
+            }
+            if setup.iter().filter(|m| m.primary).count() > 1 {
+                return Err(Error::MultiplePrimaries(conf_name));
+            }
+            for mon in setup.iter() {
+                if let ModeSpec::Exact(mode) = &mon.mode {
+                    if mon.pans_too_little(&mon.scaled(mode)) {
+                        return Err(Error::PanningTooSmall(conf_name, mon.name.clone()));
+                    }
+                }
+            }
+            // Cycles are errors, even when sizes are only known once the layout is applied
+            resolve_positions(&setup, |_| Some(Mode { w: 0, h: 0 }))?;
+            let exact_size = |m: &MonConfig| match &m.mode {
+                ModeSpec::Exact(mode) => Some(m.scaled(mode)),
+                ModeSpec::Preferred => None,
+            };
+            match resolve_positions(&setup, exact_size) {
+                Ok(positions) => {
+                    for mon in setup.iter_mut() {
+                        mon.position = positions[&mon.name];
+                    }
+                }
+                Err(Error::UnknownSize(_)) => (),
+                Err(e) => return Err(e),
+            }
+            for i in 0..setup.len() {
+                if let Some(clone_of) = &setup[i].clone_of {
+                    let source = setup
+                        .iter()
+                        .find(|m| &m.name == clone_of && m.enabled && m.clone_of.is_none())
+                        .ok_or_else(|| {
+                            Error::InvalidClone(conf_name.clone(), setup[i].name.clone())
+                        })?;
+                    let (mode, rate, x, y, scale) = (
+                        source.mode.clone(),
+                        source.rate,
+                        source.position.x,
+                        source.position.y,
+                        source.scale,
+                    );
+                    let clone = &mut setup[i];
+                    clone.mode = mode;
+                    clone.rate = rate;
+                    clone.position = Position { x, y };
+                    clone.scale = scale;
+                }
+            }
+            if let Some(fb_size) = &fb_size {
+                for mon in setup.iter().filter(|m| m.enabled) {
+                    if let ModeSpec::Exact(mode) = &mon.mode {
+                        let extent = mon.extent(mon.position, &mon.scaled(mode));
+                        if fb_size.union(&extent) != *fb_size {
+                            return Err(Error::OutsideFbSize(
+                                conf_name,
+                                mon.name.clone(),
+                                extent,
+                                fb_size.clone(),
+                            ));
+                        }
+                    }
+                }
+            }
             let mut next_setup = HashMap::with_capacity(setup.len());
+            let mut wildcard = None;
             for mon in setup.into_iter() {
+                if mon.name == WILDCARD && has_wildcard {
+                    wildcard = Some(mon);
+                    continue;
+                }
                 let mon_desc = mon_names
                     .get(&mon.name)
                     .ok_or_else(|| Error::UnknownMonitor(conf_name.clone(), mon.name.clone()))?;
-                fb_size.w = max(fb_size.w, mon.position.x as u16 + mon.mode.w);
-                fb_size.h = max(fb_size.h, mon.position.y as u16 + mon.mode.h);
-                next_setup.insert(mon_desc.clone(), mon);
-            }
-            out.insert(
-                mon_set,
-                SingleConfig {
-                    name: conf_name,
-                    setup: next_setup,
-                    fb_size,
-                },
-            );
+                let name = mon.name.clone();
+                if next_setup.insert(mon_desc.clone(), mon).is_some() {
+                    return Err(Error::DuplicateLayoutMonitor(conf_name, name));
+                }
+            }
+            let single = SingleConfig {
+                name: conf_name,
+                setup: next_setup,
+                fb_size,
+                wildcard,
+                on_apply,
+                pre_switch: layout_pre_switch,
+                post_switch: layout_post_switch,
+                allow_extra_monitors,
+                disable_extra_monitors,
+                priority,
+                dpi,
+                dpms,
+            };
+            if fallback {
+                if let Some(other) = fallback_layout {
+                    return Err(Error::MultipleFallbacks(other.name, single.name));
+                }
+                fallback_layout = Some(single);
+            } else {
+                let layouts = if has_wildcard {
+                    &mut wildcard_layouts
+                } else {
+                    &mut layouts_out
+                };
+                if let Some(other) = layouts.get(&mon_set) {
+                    return Err(Error::SameMatches(other.name.clone(), single.name));
+                }
+                layouts.insert(mon_set, single);
+            }
         }
-        Ok(Config(out))
+        Ok(Config {
+            layouts: layouts_out,
+            wildcard_layouts,
+            fallback: fallback_layout,
+            monitors: mon_names.values().cloned().collect(),
+            aliases: mon_names.into_iter().collect(),
+            pre_switch,
+            post_switch,
+            on_no_match,
+        })
     }
 }
 
 impl Config {
+    /// Identify a connected monitor as the configuration describes it. A monitor node without a
+    /// serial number or date of manufacture matches any monitor with the same product name,
+    /// unless another node names that monitor's serial number or date of manufacture.
+    pub fn identify(&self, mon: Monitor) -> Monitor {
+        if self.monitors.contains(&mon) {
+            return mon;
+        }
+        let short = Monitor {
+            serial: None,
+            year: None,
+            week: None,
+            ..mon.clone()
+        };
+        if self.monitors.contains(&short) {
+            short
+        } else {
+            mon
+        }
+    }
+
+    /// Find the layout for the connected monitors, which must be sorted. A layout that names
+    /// every connected monitor wins over a layout with a wildcard, which wins over a layout that
+    /// allows extra monitors. Of the layouts that allow extra monitors, the one naming the most
+    /// connected monitors wins, then the one with the highest priority. On a match, this returns
+    /// the monitors the layout matches by name and the layout.
+    pub fn lookup(&self, monitors: &[Monitor]) -> Option<(&Vec<Monitor>, &SingleConfig)> {
+        self.layouts
+            .get_key_value(monitors)
+            .or_else(|| {
+                self.wildcard_layouts.iter().find(|(named, _)| {
+                    named.len() + 1 == monitors.len() && named.iter().all(|m| monitors.contains(m))
+                })
+            })
+            .or_else(|| {
+                self.layouts
+                    .iter()
+                    .filter(|(named, single)| {
+                        single.allow_extra_monitors && named.iter().all(|m| monitors.contains(m))
+                    })
+                    // Layout names break the remaining ties, so that the choice is stable
+                    .max_by(|(a, a_single), (b, b_single)| {
+                        (a.len(), a_single.priority)
+                            .cmp(&(b.len(), b_single.priority))
+                            .then_with(|| b_single.name.cmp(&a_single.name))
+                    })
+            })
+    }
+
+    /// Combine two configurations, where `other` takes priority. A layout of `other` replaces
+    /// the layout of `self` with the same name, and the layout of `self` that matches the same
+    /// monitors, as two layouts may not match the same monitors. Layouts of either that
+    /// collide with none are kept. Monitor aliases are combined the same way, and each hook and
+    /// the fallback layout are taken from `other` when it has one.
+    ///
+    /// ```
+    /// use monitor_layout::config::Config;
+    /// use std::path::Path;
+    ///
+    /// let read = |text: &str| Config::from_text(text, Path::new("config.kdl")).unwrap();
+    /// let names = |config: &Config| {
+    ///     let mut names: Vec<_> = config.iter().map(|(_, s)| s.name.clone()).collect();
+    ///     names.sort();
+    ///     names
+    /// };
+    /// let base = r#"
+    ///     monitor "a" product="A"
+    ///     monitor "b" product="B"
+    ///     layout "laptop" {
+    ///         matches "a"
+    ///         monitor "a" w=1920 h=1080 x=0 y=0
+    ///     }
+    ///     layout "docked" {
+    ///         matches "a" "b"
+    ///         monitor "a" w=1920 h=1080 x=0 y=0
+    ///         monitor "b" w=2560 h=1440 x=1920 y=0
+    ///     }
+    /// "#;
+    ///
+    /// // No overlap: every layout is kept
+    /// let tv = read(r#"
+    ///     monitor "tv" product="TV"
+    ///     layout "tv" {
+    ///         matches "tv"
+    ///         monitor "tv" w=3840 h=2160 x=0 y=0
+    ///     }
+    /// "#);
+    /// let merged = read(base).merge(tv);
+    /// assert_eq!(names(&merged), ["docked", "laptop", "tv"]);
+    ///
+    /// // Partial overlap: the override replaces the layout of the same name only
+    /// let docked = read(r#"
+    ///     monitor "a" product="A"
+    ///     monitor "b" product="B"
+    ///     layout "docked" {
+    ///         matches "a" "b"
+    ///         monitor "a" state="off"
+    ///         monitor "b" w=2560 h=1440 x=0 y=0
+    ///     }
+    /// "#);
+    /// let merged = read(base).merge(docked);
+    /// assert_eq!(names(&merged), ["docked", "laptop"]);
+    /// let (_, single) = merged.iter().find(|(_, s)| s.name == "docked").unwrap();
+    /// assert!(single.monitor_configs().any(|m| m.name == "a" && !m.enabled));
+    ///
+    /// // Complete override: a layout matching the same monitors replaces one of another name
+    /// let all = read(r#"
+    ///     monitor "a" product="A"
+    ///     monitor "b" product="B"
+    ///     layout "laptop" {
+    ///         matches "a"
+    ///         monitor "a" w=1280 h=720 x=0 y=0
+    ///     }
+    ///     layout "desk" {
+    ///         matches "a" "b"
+    ///         monitor "a" w=1920 h=1080 x=2560 y=0
+    ///         monitor "b" w=2560 h=1440 x=0 y=0
+    ///     }
+    /// "#);
+    /// let merged = read(base).merge(all);
+    /// assert_eq!(names(&merged), ["desk", "laptop"]);
+    /// ```
+    pub fn merge(mut self, other: Config) -> Config {
+        let names: HashSet<_> = other
+            .iter()
+            .map(|(_, single)| single.name.clone())
+            .collect();
+        for layouts in [&mut self.layouts, &mut self.wildcard_layouts] {
+            layouts.retain(|_, single| !names.contains(&single.name));
+        }
+        self.layouts.extend(other.layouts);
+        self.wildcard_layouts.extend(other.wildcard_layouts);
+        self.fallback = match (self.fallback, other.fallback) {
+            (_, Some(fallback)) => Some(fallback),
+            (Some(fallback), None) if names.contains(&fallback.name) => None,
+            (fallback, None) => fallback,
+        };
+        self.monitors.extend(other.monitors);
+        self.aliases.extend(other.aliases);
+        self.pre_switch = other.pre_switch.or(self.pre_switch);
+        self.post_switch = other.post_switch.or(self.post_switch);
+        self.on_no_match = other.on_no_match.or(self.on_no_match);
+        self
+    }
+
+    /// Iterate over every layout, along with the monitors it matches by name. The fallback
+    /// layout matches no monitors by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&[Monitor], &SingleConfig)> {
+        self.layouts
+            .iter()
+            .chain(self.wildcard_layouts.iter())
+            .map(|(matches, single)| (matches.as_slice(), single))
+            .chain(self.fallback.iter().map(|single| (&[][..], single)))
+    }
+
+    /// The layout named `name`, along with the monitors it matches by name.
+    pub fn layout_named(&self, name: &str) -> Result<(&[Monitor], &SingleConfig)> {
+        self.iter()
+            .find(|(_, single)| single.name == name)
+            .ok_or_else(|| Error::UnknownLayout(name.to_string()))
+    }
+
+    /// The regions of the screen that the monitors of a layout show, for the monitors whose
+    /// size is known from the configuration. Clones are left out, as they show the same region
+    /// as the monitor they clone. The regions are sorted by alias.
+    pub fn regions<'a>(setup: impl Iterator<Item = &'a MonConfig>) -> Vec<(&'a str, Region)> {
+        let mut regions: Vec<_> = setup
+            .filter(|m| m.enabled && m.clone_of.is_none())
+            .filter_map(|m| match &m.mode {
+                ModeSpec::Exact(mode) => Some((
+                    m.name.as_str(),
+                    Region {
+                        position: m.position,
+                        size: m.scaled(mode),
+                    },
+                )),
+                ModeSpec::Preferred => None,
+            })
+            .collect();
+        regions.sort_by_key(|(name, _)| *name);
+        regions
+    }
+
+    /// The smallest screen that holds every monitor of a layout. Monitors that use their
+    /// preferred mode only contribute their position, as their size is only known once the
+    /// layout is applied.
+    pub fn computed_fb_size<'a>(setup: impl Iterator<Item = &'a MonConfig>) -> Mode {
+        let mut fb_size = Mode { w: 0, h: 0 };
+        for mon in setup.filter(|m| m.enabled) {
+            let size = match &mon.mode {
+                ModeSpec::Exact(mode) => mon.scaled(mode),
+                ModeSpec::Preferred => Mode { w: 0, h: 0 },
+            };
+            fb_size = fb_size.union(&mon.extent(mon.position, &size));
+        }
+        fb_size
+    }
+
+    /// Read a configuration file, following its include nodes, or every `.kdl` file in a
+    /// directory. The name `-` reads the configuration from stdin, which is only read once;
+    /// reading it again parses the same text.
     pub fn from_fname(config_name: &str) -> Result<Self> {
-        let mut file = std::fs::File::open(&config_name)?;
-        let mut text = String::new();
-        file.read_to_string(&mut text)?;
-        let document = parse_document(&text)?;
-        Config::try_from(document)
+        if config_name == STDIN_CONFIG {
+            static TEXT: OnceLock<String> = OnceLock::new();
+            let text = match TEXT.get() {
+                Some(text) => text,
+                None => {
+                    let mut text = String::new();
+                    std::io::stdin().read_to_string(&mut text)?;
+                    TEXT.get_or_init(|| text)
+                }
+            };
+            return Self::from_text(text, Path::new(STDIN_CONFIG));
+        }
+        let path = Path::new(config_name);
+        let mut reader = Reader::default();
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(path)? {
+                let file = entry?.path();
+                if file.extension().is_some_and(|ext| ext == "kdl") {
+                    files.push(file);
+                }
+            }
+            files.sort();
+            for file in files.iter() {
+                reader.read_file(file)?;
+            }
+        } else {
+            reader.read_file(path)?;
+        }
+        reader.finish()
+    }
+
+    /// Parse the text of a configuration file, as if it were read from `path`.
+    pub fn from_text(text: &str, path: &Path) -> Result<Self> {
+        let mut reader = Reader::default();
+        reader.read_text(text, path)?;
+        reader.finish()
+    }
+
+    /// The configuration file used when none is given, following the XDG Base Directory
+    /// specification: `$XDG_CONFIG_HOME/autorandr-rs/config`, where `XDG_CONFIG_HOME` defaults
+    /// to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("autorandr-rs").join("config"))
+    }
+
+    /// Read several configuration files with `from_fname`, merging them in order, so that each
+    /// file takes priority over the files before it. At least one file must be given.
+    pub fn from_fnames<S: AsRef<str>>(config_names: &[S]) -> Result<Self> {
+        let mut merged: Option<Config> = None;
+        for config_name in config_names {
+            let config = Config::from_fname(config_name.as_ref())?;
+            merged = Some(match merged {
+                Some(merged) => merged.merge(config),
+                None => config,
+            });
+        }
+        merged.ok_or(Error::NoConfigFiles)
+    }
+
+    /// Write the configuration in the format that `from_fname` reads. Comments and include
+    /// nodes are not kept, and layouts are written in order of name.
+    pub fn to_kdl(&self) -> String {
+        let mut nodes = Vec::new();
+        for (alias, mon) in self.aliases.iter() {
+            let mut node = NodeOut::new("monitor").value(alias.as_str());
+            for (field, value) in [
+                ("product", &mon.product),
+                ("serial", &mon.serial),
+                ("connector", &mon.connector),
+            ] {
+                if let Some(value) = value {
+                    node = node.prop(field, value.as_str());
+                }
+            }
+            if let Some(year) = mon.year {
+                node = node.prop("year", year as i64);
+            }
+            if let Some(week) = mon.week {
+                node = node.prop("week", week as i64);
+            }
+            nodes.push(node);
+        }
+        for (name, hook) in [
+            ("pre-switch", &self.pre_switch),
+            ("post-switch", &self.post_switch),
+            ("on-no-match", &self.on_no_match),
+        ] {
+            if let Some(hook) = hook {
+                let mut node = NodeOut::new(name).value(hook.command.as_str());
+                if let Some(timeout) = hook.timeout {
+                    node = node.prop("timeout-ms", timeout.as_millis() as i64);
+                }
+                nodes.push(node);
+            }
+        }
+        let mut layouts: Vec<_> = self
+            .layouts
+            .iter()
+            .map(|(matches, single)| (matches.as_slice(), single, false))
+            .chain(
+                self.wildcard_layouts
+                    .iter()
+                    .map(|(matches, single)| (matches.as_slice(), single, true)),
+            )
+            .collect();
+        layouts.sort_by(|(_, a, _), (_, b, _)| a.name.cmp(&b.name));
+        for (matches, single, wildcard) in layouts {
+            nodes.push(self.layout_node(matches, single, wildcard, false));
+        }
+        if let Some(single) = &self.fallback {
+            nodes.push(self.layout_node(&[], single, false, true));
+        }
+        let mut out = String::new();
+        for node in nodes.iter() {
+            node.write(&mut out, 0);
+        }
+        out
+    }
+
+    /// Write the configuration to `path`, as `to_kdl` does.
+    pub fn to_file(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_kdl())?;
+        Ok(())
+    }
+
+    /// The alias that a layout uses for `mon`.
+    fn alias_in<'a>(&'a self, single: &'a SingleConfig, mon: &Monitor) -> Option<&'a str> {
+        match single.setup.get(mon) {
+            Some(conf) => Some(conf.name.as_str()),
+            None => self
+                .aliases
+                .iter()
+                .find(|(_, m)| *m == mon)
+                .map(|(alias, _)| alias.as_str()),
+        }
+    }
+
+    fn layout_node(
+        &self,
+        matches: &[Monitor],
+        single: &SingleConfig,
+        wildcard: bool,
+        fallback: bool,
+    ) -> NodeOut {
+        let mut node = NodeOut::new("layout").value(single.name.as_str());
+        if fallback {
+            node = node.prop("fallback", true);
+        }
+        if single.disable_extra_monitors {
+            node = node.prop("disable-extra-monitors", true);
+        } else if single.allow_extra_monitors {
+            node = node.prop("allow-extra-monitors", true);
+        }
+        if single.priority != 0 {
+            node = node.prop("priority", single.priority);
+        }
+        if let Some(dpi) = single.dpi {
+            node = node.prop("dpi", dpi);
+        }
+        for (field, timeout_field, hook) in [
+            ("on-apply", "on-apply-timeout-ms", &single.on_apply),
+            ("pre-switch", "pre-switch-timeout-ms", &single.pre_switch),
+            ("post-switch", "post-switch-timeout-ms", &single.post_switch),
+        ] {
+            if let Some(hook) = hook {
+                node = node.prop(field, hook.command.as_str());
+                if let Some(timeout) = hook.timeout {
+                    node = node.prop(timeout_field, timeout.as_millis() as i64);
+                }
+            }
+        }
+        if !fallback {
+            let mut matches_node = NodeOut::new("matches");
+            for mon in matches {
+                if let Some(alias) = self.alias_in(single, mon) {
+                    matches_node = matches_node.value(alias);
+                }
+            }
+            if wildcard {
+                matches_node = matches_node.value(WILDCARD);
+            }
+            node = node.child(matches_node);
+        }
+        if let Some(fb_size) = &single.fb_size {
+            node = node.child(
+                NodeOut::new("fb-size")
+                    .prop("w", fb_size.w as i64)
+                    .prop("h", fb_size.h as i64),
+            );
+        }
+        if let Some(dpms) = &single.dpms {
+            let mut dpms_node = NodeOut::new("dpms");
+            for (stage, timeout) in [
+                ("standby", dpms.standby),
+                ("suspend", dpms.suspend),
+                ("off", dpms.off),
+            ] {
+                if let Some(timeout) = timeout {
+                    dpms_node = dpms_node.prop(stage, timeout as i64);
+                }
+            }
+            node = node.child(dpms_node);
+        }
+        let mut monitors: Vec<_> = single.monitor_configs().collect();
+        monitors.sort_by(|a, b| a.name.cmp(&b.name));
+        for conf in monitors {
+            node = node.child(monitor_node(conf));
+        }
+        node
+    }
+}
+
+/// Parse a configuration from text rather than a file. Relative file names in include nodes are
+/// relative to the current directory.
+///
+/// ```
+/// use monitor_layout::config::Config;
+/// use std::str::FromStr;
+///
+/// let config = Config::from_str(
+///     r#"
+///     monitor "laptop" connector="eDP-1"
+///     layout "mobile" {
+///         matches "laptop"
+///         monitor "laptop" x=0 y=0 w=1920 h=1080 primary=true
+///     }
+///     "#,
+/// )
+/// .unwrap();
+/// assert_eq!(config.iter().count(), 1);
+/// assert!(Config::from_str(r#"layout "broken""#).is_err());
+/// ```
+impl FromStr for Config {
+    type Err = Error;
+    fn from_str(text: &str) -> Result<Self> {
+        Self::from_text(text, Path::new(""))
+    }
+}
+
+/// The layout.monitor node that reads back as `conf`.
+fn monitor_node(conf: &MonConfig) -> NodeOut {
+    let mut node = NodeOut::new("monitor").value(conf.name.as_str());
+    if !conf.enabled {
+        return node.prop("state", "off");
+    }
+    if let Some(clone_of) = &conf.clone_of {
+        node = node.prop("clone-of", clone_of.as_str());
+    } else {
+        match &conf.mode {
+            ModeSpec::Exact(mode) => {
+                node = node.prop("w", mode.w as i64).prop("h", mode.h as i64);
+            }
+            ModeSpec::Preferred => node = node.prop("mode", "preferred"),
+        }
+        if let Some(rate) = conf.rate {
+            node = node.prop("rate", rate);
+        }
+        match &conf.relative {
+            Some(relative) => {
+                node = node.prop(&relative.direction.to_string(), relative.of.as_str());
+                if relative.gap != 0 {
+                    node = node.prop("gap", relative.gap as i64);
+                }
+            }
+            None if conf.unplaced => (),
+            None => {
+                node = node
+                    .prop("x", conf.position.x as i64)
+                    .prop("y", conf.position.y as i64);
+            }
+        }
+        if let Some(scale) = conf.scale {
+            node = if scale.x == scale.y {
+                node.prop("scale", scale.x)
+            } else {
+                node.prop("scale", scale.to_string())
+            };
+        }
+        if let Some(panning) = &conf.panning {
+            let mut panning_node = NodeOut::new("panning")
+                .prop("w", panning.size.w as i64)
+                .prop("h", panning.size.h as i64);
+            if let Some(Position { x, y }) = panning.position {
+                panning_node = panning_node.prop("x", x as i64).prop("y", y as i64);
+            }
+            if let Some(Region { position, size }) = &panning.tracking {
+                panning_node = panning_node
+                    .prop("tracking-w", size.w as i64)
+                    .prop("tracking-h", size.h as i64)
+                    .prop("tracking-x", position.x as i64)
+                    .prop("tracking-y", position.y as i64);
+            }
+            node = node.child(panning_node);
+        }
+        if let Some(modeline) = &conf.modeline {
+            let mut modeline_node = NodeOut::new("modeline").value(modeline.clock);
+            for &timing in modeline.horizontal.iter().chain(modeline.vertical.iter()) {
+                modeline_node = modeline_node.value(timing as i64);
+            }
+            for flag in modeline.flags.iter() {
+                modeline_node = modeline_node.value(flag.as_str());
+            }
+            node = node.child(modeline_node);
+        }
+    }
+    for (property, value) in conf.properties.iter() {
+        let value = match value {
+            PropertyValue::Int(i) => KdlValue::Int(*i),
+            PropertyValue::Text(text) => KdlValue::String(text.clone()),
+        };
+        node = node.child(
+            NodeOut::new("property")
+                .value(property.as_str())
+                .value(value),
+        );
+    }
+    if let Some(gamma) = conf.gamma {
+        node = node.prop("gamma", gamma.to_string());
+    }
+    if let Some(brightness) = conf.brightness {
+        node = node.prop("brightness", brightness);
+    }
+    if conf.primary {
+        node = node.prop("primary", true);
+    }
+    node
+}
+
+/// A node being written, which keeps its properties in the order they are given
+struct NodeOut {
+    line: String,
+    children: Vec<NodeOut>,
+}
+
+impl NodeOut {
+    fn new(name: &str) -> Self {
+        Self {
+            line: name.to_string(),
+            children: Vec::new(),
+        }
+    }
+
+    fn value(mut self, value: impl Into<KdlValue>) -> Self {
+        self.line.push_str(&format!(" {}", value.into()));
+        self
+    }
+
+    fn prop(mut self, name: &str, value: impl Into<KdlValue>) -> Self {
+        self.line.push_str(&format!(" {}={}", name, value.into()));
+        self
+    }
+
+    fn child(mut self, child: NodeOut) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    fn write(&self, out: &mut String, indent: usize) {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&self.line);
+        if !self.children.is_empty() {
+            out.push_str(" {\n");
+            for child in self.children.iter() {
+                child.write(out, indent + 4);
+            }
+            out.push_str(&" ".repeat(indent));
+            out.push('}');
+        }
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAPTOP: &str = r#"
+monitor "laptop" product="Thinkpad LCD" serial="0"
+monitor "desk" product="DELL U2715H" serial="GH85D6B10KXL"
+
+layout "docked" {
+    matches "laptop" "desk"
+    monitor "desk" w=2560 h=1440 x=0 y=0 primary=true
+    monitor "laptop" w=1920 h=1080 x=2560 y=360
+}
+"#;
+
+    fn monitor(product: &str, serial: &str) -> Monitor {
+        Monitor {
+            product: Some(product.to_string()),
+            serial: Some(serial.to_string()),
+            connector: None,
+            year: None,
+            week: None,
+        }
+    }
+
+    #[test]
+    fn monitors_order_by_product_serial_then_connector() {
+        let mon = |product: Option<&str>, serial: Option<&str>, connector: Option<&str>| Monitor {
+            product: product.map(str::to_string),
+            serial: serial.map(str::to_string),
+            connector: connector.map(str::to_string),
+            year: None,
+            week: None,
+        };
+        let sorted = vec![
+            mon(None, None, None),
+            mon(None, None, Some("DP-1")),
+            mon(None, None, Some("HDMI-1")),
+            mon(None, Some("0"), None),
+            mon(Some("DELL"), None, None),
+            Monitor {
+                year: Some(2019),
+                ..mon(Some("DELL"), None, None)
+            },
+            Monitor {
+                year: Some(2019),
+                week: Some(3),
+                ..mon(Some("DELL"), None, None)
+            },
+            Monitor {
+                year: Some(2020),
+                week: Some(1),
+                ..mon(Some("DELL"), None, None)
+            },
+            mon(Some("DELL"), None, Some("DP-1")),
+            mon(Some("DELL"), Some("A"), None),
+            mon(Some("DELL"), Some("A"), Some("DP-1")),
+            mon(Some("DELL"), Some("A"), Some("DP-2")),
+            mon(Some("DELL"), Some("B"), None),
+            // Strings compare byte-wise, so upper case sorts before lower case
+            mon(Some("Dell"), None, None),
+            mon(Some("dell"), None, None),
+        ];
+        let mut shuffled = sorted.clone();
+        shuffled.reverse();
+        shuffled.swap(1, 6);
+        shuffled.swap(3, 9);
+        shuffled.sort();
+        assert_eq!(shuffled, sorted);
+        for pair in sorted.windows(2) {
+            assert_eq!(pair[0].cmp(&pair[1]), Ordering::Less);
+            assert_eq!(pair[1].cmp(&pair[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn identify_prefers_the_long_form() {
+        let text = r#"
+monitor "left" product="DELL U2720Q" year=2020 week=12
+monitor "right" product="DELL U2720Q" year=2020 week=30
+monitor "desk" product="DELL U2715H" serial="GH85D6B10KXL"
+monitor "tv" product="LG TV"
+"#;
+        let config: Config = text.parse().unwrap();
+        let made = |product: &str, year, week| Monitor {
+            product: Some(product.to_string()),
+            serial: None,
+            connector: None,
+            year: Some(year),
+            week: Some(week),
+        };
+        let left = made("DELL U2720Q", 2020, 12);
+        assert_eq!(config.identify(left.clone()), left);
+        let right = made("DELL U2720Q", 2020, 30);
+        assert_eq!(config.identify(right.clone()), right);
+        // A monitor that no node names in full matches the node of its product
+        let tv = Monitor {
+            product: Some("LG TV".to_string()),
+            serial: None,
+            connector: None,
+            year: None,
+            week: None,
+        };
+        assert_eq!(config.identify(made("LG TV", 2018, 7)), tv);
+        assert_eq!(config.identify(monitor("LG TV", "1234")), tv);
+        let desk = monitor("DELL U2715H", "GH85D6B10KXL");
+        assert_eq!(config.identify(desk.clone()), desk);
+        // Nor is a third panel taken for either of the two that are named
+        let other = made("DELL U2720Q", 2021, 2);
+        assert_eq!(config.identify(other.clone()), other);
+    }
+
+    #[test]
+    fn from_str_reads_layouts() {
+        let config: Config = LAPTOP.parse().unwrap();
+        let desk = monitor("DELL U2715H", "GH85D6B10KXL");
+        let laptop = monitor("Thinkpad LCD", "0");
+        assert_eq!(config.aliases["desk"], desk);
+        assert_eq!(config.aliases["laptop"], laptop);
+
+        let mut connected = vec![laptop.clone(), desk.clone()];
+        connected.sort();
+        let (matches, single) = config.lookup(&connected).unwrap();
+        assert_eq!(matches, &connected);
+        assert_eq!(single.name, "docked");
+        let conf = &single.setup[&desk];
+        assert_eq!(conf.mode, ModeSpec::Exact(Mode { w: 2560, h: 1440 }));
+        assert_eq!(conf.position, Position { x: 0, y: 0 });
+        assert!(conf.primary);
+        let conf = &single.setup[&laptop];
+        assert_eq!(conf.mode, ModeSpec::Exact(Mode { w: 1920, h: 1080 }));
+        assert_eq!(conf.position, Position { x: 2560, y: 360 });
+        assert!(!conf.primary);
+    }
+
+    #[test]
+    fn to_kdl_reads_back() {
+        let text = r#"
+monitor "desk" product="DELL U2715H" serial="GH85D6B10KXL"
+monitor "laptop" product="Thinkpad LCD"
+monitor "tv" product="LG TV"
+monitor "projector" connector="HDMI-1"
+monitor "old" product="Old CRT" serial="1"
+
+pre-switch "xset s off" timeout-ms=2000
+post-switch "notify-send switched"
+on-no-match "notify-send unknown" timeout-ms=100
+
+layout "everything" allow-extra-monitors=true priority=2 dpi=96 on-apply="notify-send everything" on-apply-timeout-ms=500 pre-switch="xset dpms force on" pre-switch-timeout-ms=1000 post-switch="nitrogen --restore" post-switch-timeout-ms=3000 {
+    matches "desk" "laptop" "tv" "projector" "old"
+    fb-size w=6500 h=2000
+    dpms standby=600 suspend=900 off=1200
+    monitor "desk" w=2560 h=1440 x=0 y=0 rate=59.95 primary=true scale="1.5,1.25" gamma="1:0.9:0.8" brightness=0.8 {
+        panning w=4000 h=2000 x=0 y=0 tracking-w=3840 tracking-h=1800 tracking-x=0 tracking-y=0
+        property "Broadcast RGB" "Full"
+        property "underscan hborder" 40
+    }
+    monitor "laptop" right-of="desk" gap=10 {
+        modeline 181.25 2560 2608 2640 2720 1080 1083 1093 1111 "+hsync" "-vsync"
+    }
+    monitor "tv" mode="preferred" scale=2
+    monitor "projector" clone-of="desk"
+    monitor "old" state="off"
+}
+
+layout "desk and any" {
+    matches "desk" "*"
+    monitor "desk" w=2560 h=1440 x=0 y=0 primary=true
+    monitor "*" mode="preferred" right-of="desk"
+}
+
+layout "laptop only" disable-extra-monitors=true {
+    matches "laptop"
+    monitor "laptop" w=1920 h=1080
+}
+
+layout "undocked" fallback=true {
+    monitor "laptop" w=1920 h=1080 x=0 y=0 primary=true
+}
+"#;
+        let config: Config = text.parse().unwrap();
+        assert_eq!(config.layouts.len(), 2);
+        assert_eq!(config.wildcard_layouts.len(), 1);
+        assert!(config.fallback.is_some());
+        let written = config.to_kdl();
+        let read: Config = written.parse().unwrap();
+        assert_eq!(read, config, "in\n{}", written);
+    }
+
+    #[test]
+    fn from_fnames_needs_a_file() {
+        let names: [&str; 0] = [];
+        assert!(matches!(
+            Config::from_fnames(&names),
+            Err(Error::NoConfigFiles)
+        ));
+    }
+
+    #[test]
+    fn from_str_needs_matches() {
+        let text = r#"
+monitor "desk" product="DELL U2715H"
+layout "desk" {
+    monitor "desk" w=2560 h=1440 x=0 y=0
+}
+"#;
+        assert!(matches!(
+            text.parse::<Config>(),
+            Err(Error::MissingField("layout", "matches"))
+        ));
+    }
+
+    #[test]
+    fn from_str_needs_names() {
+        let text = r#"
+monitor product="DELL U2715H"
+"#;
+        assert!(matches!(
+            text.parse::<Config>(),
+            Err(Error::MissingField("monitor", "name"))
+        ));
+    }
+
+    #[test]
+    fn from_str_needs_whole_modes() {
+        let text = r#"
+monitor "desk" product="DELL U2715H"
+layout "desk" {
+    matches "desk"
+    monitor "desk" w=2560 x=0 y=0
+}
+"#;
+        assert!(matches!(
+            text.parse::<Config>(),
+            Err(Error::MissingField(_, "h"))
+        ));
+    }
+
+    #[test]
+    fn from_str_rejects_duplicate_layout_monitors() {
+        let text = r#"
+monitor "desk" product="DELL U2715H"
+layout "desk" {
+    matches "desk"
+    monitor "desk" w=2560 h=1440 x=0 y=0
+    monitor "desk" w=1920 h=1080 x=0 y=0
+}
+"#;
+        match text.parse::<Config>() {
+            Err(Error::DuplicateLayoutMonitor(layout, monitor)) => {
+                assert_eq!(layout, "desk");
+                assert_eq!(monitor, "desk");
+            }
+            other => panic!(
+                "expected DuplicateLayoutMonitor, got {:?}",
+                other.map(|_| ())
+            ),
+        }
     }
 }
@@ -0,0 +1,55 @@
+//! D-Bus interface for querying and controlling a running daemon
+//!
+//! The daemon owns the name `rs.autorandr` on the session bus and serves the interface of the
+//! same name at `/rs/autorandr`.
+use zbus::{blocking::Connection, fdo, interface, SignalContext};
+
+use std::sync::Arc;
+
+use crate::commands::DaemonContext;
+
+pub const NAME: &str = "rs.autorandr";
+pub const PATH: &str = "/rs/autorandr";
+
+struct Daemon(Arc<DaemonContext>);
+
+#[interface(name = "rs.autorandr")]
+impl Daemon {
+    /// The name of the active profile, or an empty string when no profile is active
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn current_profile(&self) -> String {
+        self.0.status().profile.unwrap_or_default()
+    }
+
+    /// Apply the named profile, regardless of the connected monitors
+    fn apply(&self, name: String) -> fdo::Result<()> {
+        self.0
+            .switch_to(&name)
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Re-read the configuration file and apply the layout that matches the attached monitors
+    fn reload(&self) -> fdo::Result<()> {
+        self.0
+            .reload()
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+
+    /// Emitted after the monitors change to show a profile
+    #[zbus(signal)]
+    async fn profile_changed(ctxt: &SignalContext<'_>, name: &str) -> zbus::Result<()>;
+}
+
+/// Connect to the session bus and serve the daemon's interface. Method calls are handled on
+/// the connection's own thread.
+pub fn serve(ctx: Arc<DaemonContext>) -> zbus::Result<Connection> {
+    zbus::blocking::connection::Builder::session()?
+        .name(NAME)?
+        .serve_at(PATH, Daemon(ctx))?
+        .build()
+}
+
+/// Tell clients that the monitors changed to show `profile`.
+pub fn profile_changed(conn: &Connection, profile: &str) -> zbus::Result<()> {
+    conn.emit_signal(None::<()>, PATH, NAME, "ProfileChanged", &(profile,))
+}
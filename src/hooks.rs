@@ -0,0 +1,118 @@
+//! Commands run when the layout changes
+use log::{error, info};
+
+use std::{
+    io::{BufRead, BufReader, Read},
+    process::{Child, Command, Stdio},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::config::Hook;
+
+/// How long to wait for the pre-switch hook, which blocks the layout change, when its timeout
+/// is not configured
+pub const PRE_SWITCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often to check whether a hook has exited
+const POLL: Duration = Duration::from_millis(10);
+
+/// Log each line that a hook writes, with `log` deciding the level.
+fn log_lines(name: &'static str, output: impl Read + Send + 'static, log: fn(&str, &str)) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(|l| l.ok()) {
+            log(name, &line);
+        }
+    });
+}
+
+/// Start a hook with `sh -c`, with `env` added to its environment.
+fn start(name: &'static str, hook: &Hook, env: Vec<(&'static str, String)>) -> Option<Child> {
+    info!("Running {} hook {}", name, hook.command);
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg(&hook.command)
+        .envs(env)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Could not run {} hook {}: {}", name, hook.command, e);
+            return None;
+        }
+    };
+    if let Some(stdout) = child.stdout.take() {
+        log_lines(name, stdout, |name, line| info!("{} hook: {}", name, line));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        log_lines(name, stderr, |name, line| error!("{} hook: {}", name, line));
+    }
+    Some(child)
+}
+
+/// Wait for a hook to exit, killing it once `timeout` passes, and log how it exited.
+fn finish(name: &'static str, profile: &str, mut child: Child, timeout: Option<Duration>) {
+    let deadline = timeout.map(|t| Instant::now() + t);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                return info!("{} hook for {} exited with {}", name, profile, status)
+            }
+            Ok(Some(status)) => {
+                return error!("{} hook for {} exited with {}", name, profile, status)
+            }
+            Ok(None) if deadline.is_none_or(|d| Instant::now() < d) => std::thread::sleep(POLL),
+            Ok(None) => {
+                error!(
+                    "{} hook for {} timed out after {:?}; killing it",
+                    name,
+                    profile,
+                    timeout.unwrap_or_default()
+                );
+                let _ = child.kill();
+                // Reap the child, so that it does not linger as a zombie
+                let _ = child.wait();
+                return;
+            }
+            Err(e) => return error!("Could not wait for {} hook for {}: {}", name, profile, e),
+        }
+    }
+}
+
+/// Run a hook and wait for it to exit, or for `default_timeout` to pass when the hook does not
+/// configure its own timeout. Failures are logged.
+pub fn run(
+    name: &'static str,
+    profile: &str,
+    hook: &Hook,
+    env: Vec<(&'static str, String)>,
+    default_timeout: Duration,
+) {
+    if let Some(child) = start(name, hook, env) {
+        finish(
+            name,
+            profile,
+            child,
+            Some(hook.timeout.unwrap_or(default_timeout)),
+        );
+    }
+}
+
+/// Run a hook in the background. Failures are logged, as there is no one to report them to.
+/// The returned thread finishes once the hook exits or times out.
+pub fn spawn(
+    name: &'static str,
+    profile: &str,
+    hook: &Hook,
+    env: Vec<(&'static str, String)>,
+) -> Option<JoinHandle<()>> {
+    let child = start(name, hook, env)?;
+    let profile = profile.to_string();
+    let timeout = hook.timeout;
+    Some(std::thread::spawn(move || {
+        finish(name, &profile, child, timeout)
+    }))
+}
@@ -0,0 +1,202 @@
+//! Protocol for controlling a running daemon over a Unix socket
+//!
+//! Each message is a single line of JSON. A client sends one `Command` and the daemon answers
+//! with one `Response`.
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use std::{
+    fs::Permissions,
+    io::{BufRead, BufReader, Error as IoError, ErrorKind, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
+
+use crate::commands::DaemonContext;
+use crate::config::Monitor;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Io Error")]
+    Io(#[from] IoError),
+    #[error("Malformed message")]
+    Json(#[from] serde_json::Error),
+    #[error("The daemon closed the connection without responding")]
+    NoResponse,
+    #[error("Another daemon is listening on {0}")]
+    InUse(PathBuf),
+    #[error("{0} must be a directory that only this user can access")]
+    UnsafeDirectory(PathBuf),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Command {
+    /// Report the active profile and the monitors it was chosen for
+    Status,
+    /// Re-read the configuration file and apply the layout that matches the connected monitors
+    Reload,
+    /// Apply the named profile, regardless of the connected monitors
+    Switch { profile: String },
+    /// Apply the next of the layouts that match the connected monitors, reporting the new
+    /// status
+    Cycle,
+    /// Read the connected monitors from the X server
+    Fingerprint,
+    /// Report counters collected since the daemon started
+    Stats,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum Response {
+    Ok,
+    Status(StatusInfo),
+    Error(String),
+    Stats(DaemonStats),
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct StatusInfo {
+    /// The name of the active profile, if any
+    pub profile: Option<String>,
+    /// The connected monitors, sorted as they are in a layout's `matches`
+    pub monitors: Vec<Monitor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct DaemonStats {
+    /// The number of RandR events received
+    pub events: u64,
+    /// The number of times a profile was applied
+    pub switches: u64,
+    /// The number of times applying a profile failed
+    pub failures: u64,
+}
+
+/// The directory of the user's own within the temporary directory, for the socket when there is
+/// no `XDG_RUNTIME_DIR`
+fn fallback_dir() -> PathBuf {
+    // Safe, as getuid always succeeds
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("{}-{}", env!("CARGO_PKG_NAME"), uid))
+}
+
+/// The default location of the daemon's socket.
+pub fn socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(fallback_dir)
+        .join(concat!(env!("CARGO_PKG_NAME"), ".sock"))
+}
+
+/// Create the directory of the socket when it is missing, and make sure that no other user can
+/// reach the socket through it.
+fn private_dir(dir: &Path) -> Result<()> {
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e.into()),
+        _ => (),
+    }
+    let meta = std::fs::symlink_metadata(dir)?;
+    // Safe, as getuid always succeeds
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o077 != 0 {
+        return Err(Error::UnsafeDirectory(dir.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Send a command to the daemon listening on `socket_path` and wait for its response.
+pub fn send_command(socket_path: &Path, cmd: Command) -> Result<Response> {
+    let mut stream = UnixStream::connect(socket_path)?;
+    let mut line = serde_json::to_string(&cmd)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Error::NoResponse);
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Execute a command within the daemon.
+pub fn handle_command(ctx: &DaemonContext, cmd: Command) -> Response {
+    match cmd {
+        Command::Status => Response::Status(ctx.status()),
+        Command::Reload => match ctx.reload() {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Command::Switch { profile } => match ctx.switch_to(&profile) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Command::Cycle => match ctx.cycle() {
+            Ok(_) => Response::Status(ctx.status()),
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Command::Fingerprint => match ctx.fingerprint() {
+            Ok(monitors) => Response::Status(StatusInfo {
+                monitors,
+                ..ctx.status()
+            }),
+            Err(e) => Response::Error(e.to_string()),
+        },
+        Command::Stats => Response::Stats(ctx.stats()),
+    }
+}
+
+/// Answer commands from a single client until it disconnects.
+fn serve_client(ctx: &DaemonContext, stream: UnixStream) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let response = match serde_json::from_str(&line?) {
+            Ok(cmd) => handle_command(ctx, cmd),
+            Err(e) => Response::Error(e.to_string()),
+        };
+        let mut line = serde_json::to_string(&response)?;
+        line.push('\n');
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Listen on `socket_path` in a background thread, answering each client in a thread of its own
+/// with `handle_command`. Only this user may connect. A socket left behind by a daemon that
+/// exited is replaced, but not the socket of a daemon that is still listening.
+pub fn serve(ctx: Arc<DaemonContext>, socket_path: &Path) -> Result<()> {
+    if let Some(dir) = socket_path.parent().filter(|dir| *dir == fallback_dir()) {
+        private_dir(dir)?;
+    }
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).is_ok() {
+            return Err(Error::InUse(socket_path.to_path_buf()));
+        }
+        std::fs::remove_file(socket_path)?;
+    }
+    let listener = UnixListener::bind(socket_path)?;
+    std::fs::set_permissions(socket_path, Permissions::from_mode(0o600))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let ctx = ctx.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = serve_client(&ctx, stream) {
+                            warn!("IPC client error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => error!("IPC accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
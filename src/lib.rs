@@ -1,18 +1,50 @@
-use std::error::Error;
+use std::{cmp::Ordering, collections::HashMap, sync::OnceLock};
+use thiserror::Error;
 use x11rb::{
     connection::Connection,
-    protocol::randr::{ConnectionExt as RandrExt, GetScreenResourcesCurrentReply, Output},
-    protocol::xproto::{Atom, ConnectionExt as XprotoExt, Window},
+    errors::{ConnectionError, ReplyError},
+    protocol::randr::{
+        Connection as RandrConnection, ConnectionExt as RandrExt, GetOutputInfoReply,
+        GetOutputPropertyReply, GetScreenResourcesCurrentReply, ModeFlag, ModeInfo, Notify, Output,
+    },
+    protocol::xproto::{Atom, ConnectionExt as XprotoExt, Timestamp, Window},
+    protocol::Event,
+    CURRENT_TIME,
 };
 
 use edid::{parse, EDID};
 use nom::IResult;
+use sha1::{Digest, Sha1};
 
 pub mod app;
 pub mod commands;
 pub mod config;
+pub mod dbus;
+pub mod hooks;
+pub mod ipc;
+pub mod logging;
+pub mod systemd;
 
-use config::Monitor;
+use config::{Mode, Monitor};
+
+/// The errors of reading monitors and outputs from the X server
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Could not talk to the X server: {0}")]
+    X11Connection(#[from] ReplyError),
+    #[error("The EDID of output {0} could not be parsed")]
+    EdidParse(Output),
+    #[error("Could not intern the {0} atom: {1}")]
+    AtomIntern(&'static str, ReplyError),
+    #[error("Could not read property {1} of output {0}: {2}")]
+    OutputProperty(Output, Atom, ReplyError),
+}
+
+impl From<ConnectionError> for Error {
+    fn from(e: ConnectionError) -> Self {
+        Error::X11Connection(e.into())
+    }
+}
 
 /// Either unwrap the OK, or run the closure that returns an exit code and exit
 pub fn ok_or_exit<T, E>(r: Result<T, E>, f: impl Fn(E) -> i32) -> T {
@@ -22,40 +54,121 @@ pub fn ok_or_exit<T, E>(r: Result<T, E>, f: impl Fn(E) -> i32) -> T {
     }
 }
 
-/// Read an EDID from an output.
+/// Read the EDID property of an output, as an INTEGER property of up to 1024 bytes. Its data
+/// is empty when the output has no EDID, and its `bytes_after` counts any bytes left unread.
+pub fn get_edid_property<C: Connection>(
+    conn: &C,
+    atom_edid: Atom,
+    output: Output,
+) -> Result<GetOutputPropertyReply, Error> {
+    let property_error = |e: ReplyError| Error::OutputProperty(output, atom_edid, e);
+    let cookie = conn
+        .randr_get_output_property(output, atom_edid, 19u32, 0, 256, false, true)
+        .map_err(|e| property_error(e.into()))?;
+    cookie.reply().map_err(property_error)
+}
+
+/// Read the raw EDID bytes of an output, which are empty when it has none.
+pub fn get_edid_bytes<C: Connection>(
+    conn: &C,
+    atom_edid: Atom,
+    output: Output,
+) -> Result<Vec<u8>, Error> {
+    Ok(get_edid_property(conn, atom_edid, output)?.data)
+}
+
+/// Parse raw EDID bytes, or `None` when they do not parse as an EDID.
+pub fn parse_edid(bytes: &[u8]) -> Option<EDID> {
+    match parse(bytes) {
+        IResult::Done(_, edid) => Some(edid),
+        _ => None,
+    }
+}
+
+/// Whether raw EDID bytes parse as an EDID.
+pub fn edid_parses(bytes: &[u8]) -> bool {
+    parse_edid(bytes).is_some()
+}
+
+/// Read an EDID from an output. Outputs without an EDID have `None`, and outputs with an EDID
+/// that can not be parsed have an `Error::EdidParse`.
 pub fn get_edid<C: Connection>(
     conn: &C,
     atom_edid: Atom,
     output: Output,
-) -> Result<Option<EDID>, Box<dyn Error>> {
-    let cookie = conn.randr_get_output_property(output, atom_edid, 19u32, 0, 256, false, true)?;
-    let props = cookie.reply()?;
-    match parse(&props.data) {
+) -> Result<Option<EDID>, Error> {
+    let bytes = get_edid_bytes(conn, atom_edid, output)?;
+    match parse(&bytes) {
         IResult::Done(_, edid) => Ok(Some(edid)),
-        _ => Ok(None),
+        _ if bytes.is_empty() => Ok(None),
+        _ => Err(Error::EdidParse(output)),
     }
 }
 
+/// A stable fingerprint of a monitor: the SHA-1 hash of its raw EDID bytes, as 40 lowercase
+/// hexadecimal digits. Unlike the formatting of `Monitor`, this does not change between
+/// versions.
+pub fn edid_fingerprint(edid: &[u8]) -> String {
+    Sha1::digest(edid)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
 /// A convienience function to complete a RandR getScreenResourcesCurrent request.
 pub fn get_outputs<C: Connection>(
     conn: &C,
     root: Window,
-) -> Result<GetScreenResourcesCurrentReply, Box<dyn Error>> {
+) -> Result<GetScreenResourcesCurrentReply, Error> {
     Ok(conn.randr_get_screen_resources_current(root)?.reply()?)
 }
 
+/// The connector name of an output, such as "HDMI-1", for messages. An output whose name can
+/// not be read is named by its XID instead.
+pub fn output_name<C: Connection>(conn: &C, output: Output, timestamp: Timestamp) -> String {
+    match conn
+        .randr_get_output_info(output, timestamp)
+        .map_err(ReplyError::from)
+        .and_then(|cookie| cookie.reply())
+    {
+        Ok(info) => String::from_utf8_lossy(&info.name).into_owned(),
+        Err(_) => output.to_string(),
+    }
+}
+
+/// Describe the monitor connected to an output, if any. Monitors without a parseable EDID are
+/// described by the name of the output.
+fn get_monitor<C: Connection>(
+    conn: &C,
+    out: Output,
+    atom_edid: Atom,
+) -> Result<Option<Monitor>, Error> {
+    match get_edid(conn, atom_edid, out) {
+        Ok(Some(edid)) => return Ok(Some(Monitor::from(edid))),
+        // Monitors with a broken EDID are named by their output, as if they had none
+        Ok(None) | Err(Error::EdidParse(_)) => (),
+        Err(e) => return Err(e),
+    }
+    let info = conn.randr_get_output_info(out, CURRENT_TIME)?.reply()?;
+    if info.connection != RandrConnection::CONNECTED {
+        return Ok(None);
+    }
+    let name = String::from_utf8_lossy(&info.name).into_owned();
+    Ok(Some(Monitor::from_connector(name)))
+}
+
 /// Construct an iterator that represents a mapping from Xorg output ids to monitor descriptions.
-/// The monitor descriptions are generated from the EDID of the display.
+/// The monitor descriptions are generated from the EDID of the display, or from the name of the
+/// output when the display has no EDID.
 pub fn get_monitors<'o, C: Connection>(
     conn: &'o C,
-    outputs: &'o Vec<Output>,
+    outputs: &'o [Output],
     atom_edid: Atom,
 ) -> impl Iterator<Item = (Output, Monitor)> + 'o {
     outputs
         .iter()
-        .filter_map(move |out| match get_edid(conn, atom_edid, *out) {
-            Ok(Some(m)) => Some((*out, Monitor::from(m))),
-            Ok(None) => None,
+        .filter_map(move |out| match get_monitor(conn, *out, atom_edid) {
+            Ok(m) => m.map(|m| (*out, m)),
             Err(e) => {
                 eprintln!("Error reading EDID for Output {}: {}", out, e);
                 None
@@ -63,7 +176,313 @@ pub fn get_monitors<'o, C: Connection>(
         })
 }
 
+/// The monitors attached to each output, as `get_monitors` describes them, kept so that their
+/// EDIDs are not read again while the outputs stay the same.
+///
+/// The cache is emptied when the set of outputs changes. It only notices a monitor being
+/// attached, detached or replaced through the RandR events passed to `observe`, so it should
+/// only be used along with the output change and output property events.
+#[derive(Debug, Default)]
+pub struct EdidCache {
+    outputs: Vec<Output>,
+    monitors: HashMap<Output, Option<Monitor>>,
+}
+
+impl EdidCache {
+    /// Forget every monitor, so that each is read again.
+    pub fn invalidate(&mut self) {
+        self.monitors.clear();
+    }
+
+    /// Forget the monitor of an output that an event shows has changed: one that was attached
+    /// or detached, or whose EDID changed.
+    pub fn observe(&mut self, event: &Event, atom_edid: Atom) {
+        match event {
+            Event::RandrNotify(e) if e.sub_code == Notify::OUTPUT_CHANGE => {
+                let change = e.u.as_oc();
+                let connected = change.connection == RandrConnection::CONNECTED;
+                // Reconfiguring an output also raises this event, without changing its monitor
+                if let Some(monitor) = self.monitors.get(&change.output) {
+                    if monitor.is_some() != connected {
+                        self.monitors.remove(&change.output);
+                    }
+                }
+            }
+            Event::RandrNotify(e) if e.sub_code == Notify::OUTPUT_PROPERTY => {
+                let change = e.u.as_op();
+                if change.atom == atom_edid {
+                    self.monitors.remove(&change.output);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Map Xorg output ids to monitor descriptions, as `get_monitors` does, reading only the
+    /// outputs whose monitor is not already known.
+    pub fn get_monitors<C: Connection>(
+        &mut self,
+        conn: &C,
+        outputs: &[Output],
+        atom_edid: Atom,
+    ) -> Vec<(Output, Monitor)> {
+        self.get_monitors_with(outputs, |out| get_monitor(conn, out, atom_edid))
+    }
+
+    /// Map Xorg output ids to monitor descriptions as `get_monitors` does, calling `read` to
+    /// describe the monitor of each output that is not already known, such as to read them
+    /// from somewhere other than the X server.
+    pub fn get_monitors_with(
+        &mut self,
+        outputs: &[Output],
+        mut read: impl FnMut(Output) -> Result<Option<Monitor>, Error>,
+    ) -> Vec<(Output, Monitor)> {
+        if self.outputs != outputs {
+            self.outputs = outputs.to_vec();
+            self.invalidate();
+        }
+        let mut monitors = Vec::with_capacity(outputs.len());
+        for &out in outputs {
+            let monitor = match self.monitors.get(&out) {
+                Some(monitor) => monitor.clone(),
+                // Errors are not kept, so that the output is read again next time
+                None => match read(out) {
+                    Ok(monitor) => self.monitors.entry(out).or_insert(monitor).clone(),
+                    Err(e) => {
+                        eprintln!("Error reading EDID for Output {}: {}", out, e);
+                        None
+                    }
+                },
+            };
+            if let Some(monitor) = monitor {
+                monitors.push((out, monitor));
+            }
+        }
+        monitors
+    }
+}
+
+/// Compute the vertical refresh rate, in Hz, of a mode.
+pub fn refresh_rate(mi: &ModeInfo) -> f64 {
+    let mut vtotal = mi.vtotal as f64;
+    if mi.mode_flags & u32::from(ModeFlag::DOUBLE_SCAN) != 0 {
+        vtotal *= 2.0;
+    }
+    if mi.mode_flags & u32::from(ModeFlag::INTERLACE) != 0 {
+        vtotal /= 2.0;
+    }
+    let dots = mi.htotal as f64 * vtotal;
+    if dots == 0.0 {
+        0.0
+    } else {
+        mi.dot_clock as f64 / dots
+    }
+}
+
+/// The size and refresh rate of each mode, by mode id
+pub type ModeSizes = HashMap<u32, (Mode, f64)>;
+
+/// The sizes and refresh rates of the modes of a screen, by mode id.
+pub fn mode_sizes(modes: &[ModeInfo]) -> ModeSizes {
+    modes
+        .iter()
+        .map(|mi| {
+            let size = Mode {
+                w: mi.width,
+                h: mi.height,
+            };
+            (mi.id, (size, refresh_rate(mi)))
+        })
+        .collect()
+}
+
+/// Choose the best mode for an output, from the modes it offers that `modes` describes. The
+/// best mode has `size` when it is given, or else the size of the output's preferred mode, or
+/// of its largest mode when it has no preferred mode. Among modes of that size, the one closest
+/// to `prefer_refresh` is chosen; without a refresh rate, the preferred mode when no size is
+/// given, or else the mode with the highest refresh rate, is chosen.
+pub fn best_mode_for_output(
+    info: &GetOutputInfoReply,
+    modes: &ModeSizes,
+    size: Option<&Mode>,
+    prefer_refresh: Option<f64>,
+) -> Option<u32> {
+    let offered: Vec<(u32, &Mode, f64)> = info
+        .modes
+        .iter()
+        .filter_map(|id| modes.get(id).map(|(mode, rate)| (*id, mode, *rate)))
+        .collect();
+    let preferred = info
+        .modes
+        .iter()
+        .take(info.num_preferred as usize)
+        .find_map(|id| offered.iter().find(|(o, _, _)| o == id))
+        .filter(|_| size.is_none());
+    let target = match (size, preferred) {
+        (Some(size), _) => size,
+        (None, Some((_, mode, _))) => *mode,
+        (None, None) => offered
+            .iter()
+            .map(|(_, mode, _)| *mode)
+            .max_by_key(|mode| mode.w as u32 * mode.h as u32)?,
+    };
+    let same_size = offered.iter().filter(|(_, mode, _)| *mode == target);
+    let best = match (prefer_refresh, preferred) {
+        (Some(rate), _) => same_size.min_by(|(_, _, a), (_, _, b)| {
+            (a - rate)
+                .abs()
+                .partial_cmp(&(b - rate).abs())
+                .unwrap_or(Ordering::Equal)
+        })?,
+        (None, Some(preferred)) => preferred,
+        (None, None) => {
+            same_size.max_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))?
+        }
+    };
+    Some(best.0)
+}
+
+/// Get the atom with a name, interning it when it does not exist yet
+pub fn intern<C: Connection>(conn: &C, name: &'static str) -> Result<Atom, Error> {
+    let intern_error = |e: ReplyError| Error::AtomIntern(name, e);
+    let cookie = conn
+        .intern_atom(false, name.as_bytes())
+        .map_err(|e| intern_error(e.into()))?;
+    Ok(cookie.reply().map_err(intern_error)?.atom)
+}
+
 /// Get the atom that allows reading an EDID from an output
-pub fn edid_atom<C: Connection>(conn: &C) -> Result<Atom, Box<dyn Error>> {
-    Ok(conn.intern_atom(false, b"EDID")?.reply()?.atom)
+pub fn edid_atom<C: Connection>(conn: &C) -> Result<Atom, Error> {
+    intern(conn, "EDID")
+}
+
+/// Get the atom that allows reading an EDID from an output, interning it only if `cache` is
+/// empty.
+pub fn edid_atom_cached<C: Connection>(conn: &C, cache: &OnceLock<Atom>) -> Result<Atom, Error> {
+    match cache.get() {
+        Some(atom) => Ok(*atom),
+        None => {
+            let atom = edid_atom(conn)?;
+            Ok(*cache.get_or_init(|| atom))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x11rb::protocol::{
+        randr::{NotifyEvent, OutputChange, OutputProperty},
+        render::SubPixel,
+        xproto::Property,
+    };
+
+    const ATOM_EDID: Atom = 300;
+
+    fn monitor(product: &str) -> Monitor {
+        Monitor {
+            product: Some(product.to_string()),
+            serial: None,
+            connector: None,
+            year: None,
+            week: None,
+        }
+    }
+
+    /// A cache that knows the monitors of outputs 1 and 2, and that output 3 has none.
+    fn cache() -> EdidCache {
+        let mut cache = EdidCache {
+            outputs: vec![1, 2, 3],
+            ..Default::default()
+        };
+        cache.monitors.insert(1, Some(monitor("DELL U2715H")));
+        cache.monitors.insert(2, Some(monitor("Thinkpad LCD")));
+        cache.monitors.insert(3, None);
+        cache
+    }
+
+    fn property_event(output: Output, atom: Atom) -> Event {
+        Event::RandrNotify(NotifyEvent {
+            response_type: 0,
+            sub_code: Notify::OUTPUT_PROPERTY,
+            sequence: 0,
+            u: OutputProperty {
+                window: 0,
+                output,
+                atom,
+                timestamp: 0,
+                status: Property::NEW_VALUE,
+            }
+            .into(),
+        })
+    }
+
+    fn change_event(output: Output, connection: RandrConnection) -> Event {
+        Event::RandrNotify(NotifyEvent {
+            response_type: 0,
+            sub_code: Notify::OUTPUT_CHANGE,
+            sequence: 0,
+            u: OutputChange {
+                timestamp: 0,
+                config_timestamp: 0,
+                window: 0,
+                output,
+                crtc: 0,
+                mode: 0,
+                rotation: 0,
+                connection,
+                subpixel_order: SubPixel::UNKNOWN,
+            }
+            .into(),
+        })
+    }
+
+    #[test]
+    fn changed_outputs_forget_monitors() {
+        let mut cache = EdidCache::default();
+        let mut reads = Vec::new();
+        let mut read = |outputs: &[Output], reads: &mut Vec<Output>| {
+            cache.get_monitors_with(outputs, |out| {
+                reads.push(out);
+                Ok(Some(monitor(&out.to_string())))
+            })
+        };
+        let monitors = read(&[1, 2, 3], &mut reads);
+        assert_eq!(monitors.len(), 3);
+        assert_eq!(reads, [1, 2, 3]);
+        reads.clear();
+        read(&[1, 2, 3], &mut reads);
+        assert!(reads.is_empty());
+        // Outputs 1 and 2 are read again too, as a new set of outputs empties the cache
+        read(&[1, 2, 4], &mut reads);
+        assert_eq!(reads, [1, 2, 4]);
+    }
+
+    #[test]
+    fn edid_change_forgets_monitor() {
+        let mut cache = cache();
+        cache.observe(&property_event(1, ATOM_EDID), ATOM_EDID);
+        assert!(!cache.monitors.contains_key(&1));
+        assert_eq!(cache.monitors[&2], Some(monitor("Thinkpad LCD")));
+        assert_eq!(cache.monitors[&3], None);
+    }
+
+    #[test]
+    fn other_property_change_keeps_monitor() {
+        let mut cache = cache();
+        cache.observe(&property_event(1, ATOM_EDID + 1), ATOM_EDID);
+        assert_eq!(cache.monitors[&1], Some(monitor("DELL U2715H")));
+    }
+
+    #[test]
+    fn output_change_forgets_only_attached_or_detached_monitors() {
+        let mut cache = cache();
+        // Reconfiguring an output with a monitor attached
+        cache.observe(&change_event(1, RandrConnection::CONNECTED), ATOM_EDID);
+        assert_eq!(cache.monitors[&1], Some(monitor("DELL U2715H")));
+        cache.observe(&change_event(2, RandrConnection::DISCONNECTED), ATOM_EDID);
+        assert!(!cache.monitors.contains_key(&2));
+        cache.observe(&change_event(3, RandrConnection::CONNECTED), ATOM_EDID);
+        assert!(!cache.monitors.contains_key(&3));
+    }
 }
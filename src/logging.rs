@@ -0,0 +1,94 @@
+//! Log output formats
+use log::{LevelFilter, Log, Metadata, Record};
+use serde::Serialize;
+
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A single log record, as written by [`JsonLogger`]
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    /// Seconds since the Unix epoch
+    timestamp: f64,
+    level: &'a str,
+    target: &'a str,
+    message: String,
+}
+
+/// Writes each log record to stderr as a line of JSON
+pub struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl JsonLogger {
+    /// Install the logger, showing records at `level` and above.
+    pub fn init(level: LevelFilter) -> Result<(), log::SetLoggerError> {
+        log::set_boxed_logger(Box::new(JsonLogger { level }))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs_f64())
+            .unwrap_or_default();
+        let line = JsonRecord {
+            timestamp,
+            level: record.level().as_str(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        if let Ok(line) = serde_json::to_string(&line) {
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// The level shown for a number of `-v` flags, matching stderrlog's verbosity.
+pub fn level_for_verbosity(verbosity: u64) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// The number of `-v` flags that stderrlog shows `level` for, the inverse of
+/// [`level_for_verbosity`].
+pub fn verbosity_for_level(level: LevelFilter) -> usize {
+    (level as usize).saturating_sub(1)
+}
+
+/// The level of the messages to show: the level of `--log-level` when it is given, else the
+/// level for the number of `-v` flags when there are any, else the level that `RUST_LOG` names.
+/// Without any, `info` in debug builds and `warn` in release builds. `RUST_LOG` is only read as
+/// a single level, such as `debug`, rather than a list of filters by module.
+pub fn log_level(log_level: Option<&str>, verbosity: u64) -> LevelFilter {
+    log_level
+        .and_then(|level| level.parse().ok())
+        .or_else(|| (verbosity > 0).then(|| level_for_verbosity(verbosity)))
+        .or_else(|| std::env::var("RUST_LOG").ok()?.parse().ok())
+        .unwrap_or(if cfg!(debug_assertions) {
+            LevelFilter::Info
+        } else {
+            LevelFilter::Warn
+        })
+}
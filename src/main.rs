@@ -1,20 +1,54 @@
+use log::LevelFilter;
 use miette::{IntoDiagnostic, Result};
-use monitor_layout::app;
+use monitor_layout::{
+    app,
+    logging::{log_level, verbosity_for_level, JsonLogger},
+};
 
 fn main() -> Result<()> {
     let args = crate::app::args().get_matches();
-    stderrlog::new()
-        .verbosity(args.occurrences_of("verbosity") as usize)
-        .timestamp(stderrlog::Timestamp::Off)
-        .init()
-        .unwrap();
+    let level = log_level(args.value_of("log-level"), args.occurrences_of("verbosity"));
+    match args.value_of("log-format") {
+        Some("json") => JsonLogger::init(level).unwrap(),
+        _ => stderrlog::new()
+            .verbosity(verbosity_for_level(level))
+            .quiet(level == LevelFilter::Off)
+            .timestamp(stderrlog::Timestamp::Off)
+            .init()
+            .unwrap(),
+    }
     match args.subcommand() {
+        ("apply", Some(args)) => monitor_layout::commands::apply(args),
         ("daemon", Some(args)) => monitor_layout::commands::daemon(args),
         ("check", Some(args)) => monitor_layout::commands::check(args).map(|_| ()),
+        ("dump-config", Some(args)) => monitor_layout::commands::dump_config(args),
+        ("export", Some(args)) => monitor_layout::commands::export(args),
+        ("fingerprint", Some(args)) => monitor_layout::commands::fingerprint(args),
+        ("decode-edid", Some(args)) => monitor_layout::commands::decode_edid(args),
+        ("detect", Some(args)) => monitor_layout::commands::detect(args),
+        ("diff", Some(args)) => monitor_layout::commands::diff(args),
+        ("doctor", Some(args)) => monitor_layout::commands::doctor(args),
+        ("list", Some(args)) => monitor_layout::commands::list(args),
+        ("list-monitors", Some(args)) => monitor_layout::commands::list_monitors(args),
+        ("print-edid", Some(args)) => monitor_layout::commands::print_edid(args),
         ("print-edids", Some(args)) => monitor_layout::commands::print_edids(args),
+        ("providers", Some(args)) => monitor_layout::commands::providers(args),
+        ("profile-names", Some(args)) => monitor_layout::commands::profile_names(args),
+        ("render", Some(args)) => monitor_layout::commands::render(args),
+        ("reset", Some(args)) => monitor_layout::commands::reset(args),
+        ("completions", Some(args)) => monitor_layout::commands::completions(args),
+        ("generate", Some(args)) => monitor_layout::commands::generate(args),
+        ("current", Some(args)) => monitor_layout::commands::current(args),
+        ("cycle", Some(args)) => monitor_layout::commands::cycle(args),
+        ("status", Some(args)) => monitor_layout::commands::status(args),
+        ("switch", Some(args)) => monitor_layout::commands::switch(args),
+        ("test", Some(args)) => monitor_layout::commands::test(args),
+        ("verify", Some(args)) => monitor_layout::commands::verify(args),
+        ("watch", Some(args)) => monitor_layout::commands::watch(args),
+        ("save", Some(args)) => monitor_layout::commands::save(args),
         _ => {
             app::args().print_help().into_diagnostic()?;
-            println!("");
+            println!();
             Ok(())
         }
     }
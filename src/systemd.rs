@@ -0,0 +1,51 @@
+//! Notifying systemd of the daemon's state, when it runs as a `Type=notify` service
+use log::warn;
+
+use std::{
+    env,
+    ffi::OsStr,
+    io,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::{
+            ffi::OsStrExt,
+            net::{SocketAddr, UnixDatagram},
+        },
+    },
+    path::Path,
+    time::Duration,
+};
+
+/// Send one datagram to the socket that systemd names, which is in the abstract namespace when
+/// its name starts with `@`.
+fn send(socket_name: &OsStr, state: &str) -> io::Result<()> {
+    let addr = match socket_name.as_bytes().strip_prefix(b"@") {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(Path::new(socket_name))?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Tell systemd of a change of state, such as `READY=1`, as `sd_notify` does. Without a
+/// notification socket, the daemon is not run by systemd, and this does nothing.
+pub fn notify(state: &str) {
+    if let Some(socket_name) = env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send(&socket_name, state) {
+            warn!("Could not notify systemd of {}: {}", state, e);
+        }
+    }
+}
+
+/// How often to send `WATCHDOG=1`: half the watchdog timeout that systemd sets, as
+/// `sd_watchdog_enabled` recommends, or None when systemd does not watch this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    // The watchdog may be meant for another process, such as a parent shell
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}